@@ -3,6 +3,25 @@ echo Building Kernel...
 rustup override add nightly
 rustup override set nightly
 cd frame_kernel
-cargo build
+echo Embedding Kernel Symbols...
+rem the table can move the code when it grows, so link again until the addresses stop changing
+set FRAME_KERNEL_SYMBOLS=%cd%\target\kernel.sym
+if not exist target mkdir target
+type nul > target\kernel.sym
+for /L %%i in (1,1,5) do (
+    cargo build || goto failed
+    rust-nm -C --defined-only target\x86_64-frame_kernel\debug\frame_kernel > target\kernel.new.sym
+    fc /b target\kernel.sym target\kernel.new.sym > nul && goto embedded
+    move /y target\kernel.new.sym target\kernel.sym > nul
+)
+echo The symbol addresses kept moving, the backtraces would be wrong
+goto failed
+:embedded
+del target\kernel.new.sym
 cd ..
-echo Done Building
\ No newline at end of file
+echo Done Building
+exit /b 0
+:failed
+cd ..
+echo Build Failed
+exit /b 1
\ No newline at end of file
@@ -0,0 +1,54 @@
+// build.rs - embeds the kernel symbol table used by backtrace.rs
+//
+// The symbol table can only be produced from a linked kernel, so symbolised backtraces
+// need more than one pass (see build.bat):
+//   1.) build the kernel with FRAME_KERNEL_SYMBOLS pointing at an empty kernel.sym
+//   2.) dump the symbols with `rust-nm -C --defined-only <kernel binary>` and compare them with
+//       kernel.sym, they are embedded correctly if nothing changed
+//   3.) otherwise replace kernel.sym with them and go back to 1.)
+// rust-lld puts .rodata (where the table is) before .text, so a bigger table moves every
+// function. The names don't change between passes, only the addresses, so the table stops
+// growing after a pass or two and the addresses settle. build.bat gives up after 5 passes.
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=FRAME_KERNEL_SYMBOLS");
+
+    let mut symbols: Vec<(u64, String)> = Vec::new();
+
+    if let Ok(path) = env::var("FRAME_KERNEL_SYMBOLS") {
+        println!("cargo:rerun-if-changed={}", path);
+        let data = fs::read_to_string(&path).expect("failed to read FRAME_KERNEL_SYMBOLS");
+
+        for line in data.lines() {
+            // nm output is in the form of `<address> <type> <name>`
+            let mut parts = line.splitn(3, ' ');
+            let (addr, kind, name) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(addr), Some(kind), Some(name)) => (addr, kind, name),
+                _ => continue, // undefined symbols have no address
+            };
+            if kind != "t" && kind != "T" { // only code symbols are useful in a backtrace
+                continue;
+            }
+            if let Ok(addr) = u64::from_str_radix(addr, 16) {
+                symbols.push((addr, name.to_string()));
+            }
+        }
+    }
+
+    symbols.sort_by_key(|s| s.0);
+    symbols.dedup_by_key(|s| s.0);
+
+    let mut out = String::from("pub static SYMBOLS: &[(u64, &str)] = &[\n");
+    for (addr, name) in &symbols {
+        out.push_str(&format!("    ({:#x}, {:?}),\n", addr, name));
+    }
+    out.push_str("];\n");
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("symbols.rs");
+    fs::write(dest, out).expect("failed to write symbol table");
+}
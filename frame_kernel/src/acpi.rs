@@ -0,0 +1,426 @@
+// ACPI table parsing, shutdown and reboot
+//
+// Only the parts of ACPI FrameOS needs are parsed: the RSDT/XSDT, FADT, MADT and HPET tables,
+// plus a search of the DSDT for the `_S5` object (the sleep types needed for soft-off),
+// which avoids needing a full AML interpreter.
+
+use alloc::vec::Vec;
+use core::ptr;
+
+use conquer_once::spin::OnceCell;
+use x86_64::instructions::port::Port;
+use x86_64::PhysAddr;
+
+use crate::cpu::{self, Feature};
+use crate::fw_cfg;
+use crate::memory::phys_to_virt;
+use crate::{hlt_loop, println};
+
+static ACPI: OnceCell<Acpi> = OnceCell::uninit();
+
+const SLP_EN: u16 = 1 << 13;
+const SCI_EN: u16 = 1;
+const RESET_REG_SUP: u32 = 1 << 10; // FADT flag: the reset register is supported
+
+#[derive(Debug)]
+pub enum AcpiError {
+    NoRsdp,
+    BadChecksum([u8; 4]), // the signature of the table with the bad checksum
+    NoFadt,
+}
+
+/// A generic address structure, used by ACPI to describe registers
+#[derive(Debug, Clone, Copy)]
+pub struct GenericAddress {
+    pub space: u8, // 0 = memory, 1 = io port, 2 = pci configuration space
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Processor {
+    pub acpi_id: u8,
+    pub apic_id: u8,
+    pub enabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct InterruptOverride {
+    pub source: u8, // the isa irq
+    pub gsi: u32, // the global system interrupt it is connected to
+    pub flags: u16,
+}
+
+/// The information from the MADT (interrupt controllers)
+#[derive(Debug)]
+pub struct Madt {
+    pub local_apic_address: u64,
+    pub processors: Vec<Processor>,
+    pub io_apics: Vec<IoApic>,
+    pub overrides: Vec<InterruptOverride>,
+}
+
+/// The information from the HPET table
+#[derive(Debug, Clone, Copy)]
+pub struct Hpet {
+    pub address: u64,
+    pub number: u8,
+    pub min_tick: u16,
+}
+
+/// The information from the FADT needed for power management
+#[derive(Debug, Clone, Copy)]
+pub struct Fadt {
+    pub sci_interrupt: u16,
+    pub smi_command: u32,
+    pub acpi_enable: u8,
+    pub pm1a_control: u32,
+    pub pm1b_control: u32,
+    pub flags: u32,
+    pub reset_register: Option<GenericAddress>,
+    pub reset_value: u8,
+    pub dsdt: u64,
+}
+
+#[derive(Debug)]
+pub struct Acpi {
+    pub revision: u8,
+    pub fadt: Fadt,
+    pub madt: Option<Madt>,
+    pub hpet: Option<Hpet>,
+    pub s5: Option<(u16, u16)>, // SLP_TYPa and SLP_TYPb for soft-off
+}
+
+/// Finds and parses the ACPI tables
+/// Must be called after memory::init(...) and after the heap is initialized
+pub fn init() -> Result<(), AcpiError> {
+    let acpi = unsafe { parse()? };
+    ACPI.try_init_once(|| acpi).expect("acpi::init should only be called once");
+    Ok(())
+}
+
+/// returns the parsed tables, or None if acpi::init() has not succeeded
+pub fn get() -> Option<&'static Acpi> {
+    ACPI.get()
+}
+
+// ================= PHYSICAL MEMORY ACCESS
+
+/// Unsafe because `addr + offset` must be mapped physical memory
+unsafe fn read<T: Copy>(addr: u64, offset: u64) -> T {
+    let virt = phys_to_virt(PhysAddr::new(addr + offset));
+    ptr::read_unaligned(virt.as_ptr::<T>())
+}
+
+/// Unsafe because of call to read(...)
+unsafe fn checksum(addr: u64, len: u64) -> bool {
+    let mut sum: u8 = 0;
+    for i in 0..len {
+        sum = sum.wrapping_add(read::<u8>(addr, i));
+    }
+    sum == 0
+}
+
+// ================= TABLES
+
+/// Searches for the "RSD PTR " signature in the first KiB of the EBDA and the BIOS area
+unsafe fn find_rsdp() -> Option<u64> {
+    let ebda = (read::<u16>(0x40E, 0) as u64) << 4;
+    let regions = [(ebda, ebda + 1024), (0xE0000, 0x100000)];
+
+    for &(start, end) in regions.iter() {
+        if start == 0 {
+            continue;
+        }
+        let mut addr = start;
+        while addr < end {
+            if read::<[u8; 8]>(addr, 0) == *b"RSD PTR " && checksum(addr, 20) {
+                return Some(addr);
+            }
+            addr += 16; // the rsdp is always 16 byte aligned
+        }
+    }
+    None
+}
+
+unsafe fn parse() -> Result<Acpi, AcpiError> {
+    let rsdp = find_rsdp().ok_or(AcpiError::NoRsdp)?;
+    let revision = read::<u8>(rsdp, 15);
+
+    // acpi 2.0+ has a 64 bit xsdt, older versions only have the rsdt
+    let (root, entry_size) = if revision >= 2 && read::<u64>(rsdp, 24) != 0 {
+        (read::<u64>(rsdp, 24), 8)
+    } else {
+        (read::<u32>(rsdp, 16) as u64, 4)
+    };
+    verify(root)?;
+
+    let mut fadt = None;
+    let mut madt = None;
+    let mut hpet = None;
+
+    let entries = (read::<u32>(root, 4) as u64 - 36) / entry_size;
+    for i in 0..entries {
+        let table = if entry_size == 8 {
+            read::<u64>(root, 36 + i * 8)
+        } else {
+            read::<u32>(root, 36 + i * 4) as u64
+        };
+        if verify(table).is_err() {
+            continue; // skip broken tables instead of failing completely
+        }
+        match &read::<[u8; 4]>(table, 0) {
+            b"FACP" => fadt = Some(parse_fadt(table)),
+            b"APIC" => madt = Some(parse_madt(table)),
+            b"HPET" => hpet = Some(parse_hpet(table)),
+            _ => {}
+        }
+    }
+
+    let fadt = fadt.ok_or(AcpiError::NoFadt)?;
+    let s5 = if fadt.dsdt != 0 && verify(fadt.dsdt).is_ok() {
+        find_s5(fadt.dsdt)
+    } else {
+        None
+    };
+
+    Ok(Acpi {
+        revision,
+        fadt,
+        madt,
+        hpet,
+        s5,
+    })
+}
+
+/// Checks the checksum of the table at `addr`
+unsafe fn verify(addr: u64) -> Result<(), AcpiError> {
+    let len = read::<u32>(addr, 4) as u64;
+    if !checksum(addr, len) {
+        return Err(AcpiError::BadChecksum(read::<[u8; 4]>(addr, 0)));
+    }
+    Ok(())
+}
+
+unsafe fn parse_gas(addr: u64) -> GenericAddress {
+    GenericAddress {
+        space: read::<u8>(addr, 0),
+        bit_width: read::<u8>(addr, 1),
+        bit_offset: read::<u8>(addr, 2),
+        access_size: read::<u8>(addr, 3),
+        address: read::<u64>(addr, 4),
+    }
+}
+
+unsafe fn parse_fadt(table: u64) -> Fadt {
+    let len = read::<u32>(table, 4) as u64;
+    let flags = read::<u32>(table, 112);
+
+    // the reset register and 64 bit dsdt address only exist in newer (longer) FADTs
+    let reset_register = if len >= 129 && flags & RESET_REG_SUP != 0 {
+        Some(parse_gas(table + 116))
+    } else {
+        None
+    };
+    let x_dsdt = if len >= 148 { read::<u64>(table, 140) } else { 0 };
+
+    Fadt {
+        sci_interrupt: read::<u16>(table, 46),
+        smi_command: read::<u32>(table, 48),
+        acpi_enable: read::<u8>(table, 52),
+        pm1a_control: read::<u32>(table, 64),
+        pm1b_control: read::<u32>(table, 68),
+        flags,
+        reset_register,
+        reset_value: if len >= 129 { read::<u8>(table, 128) } else { 0 },
+        dsdt: if x_dsdt != 0 { x_dsdt } else { read::<u32>(table, 40) as u64 },
+    }
+}
+
+unsafe fn parse_madt(table: u64) -> Madt {
+    let len = read::<u32>(table, 4) as u64;
+    let mut madt = Madt {
+        local_apic_address: read::<u32>(table, 36) as u64,
+        processors: Vec::new(),
+        io_apics: Vec::new(),
+        overrides: Vec::new(),
+    };
+
+    let mut offset = 44;
+    while offset + 2 <= len {
+        let entry = table + offset;
+        let entry_len = read::<u8>(entry, 1) as u64;
+        if entry_len < 2 {
+            break; // a broken entry would loop forever
+        }
+        match read::<u8>(entry, 0) {
+            0 => madt.processors.push(Processor {
+                acpi_id: read::<u8>(entry, 2),
+                apic_id: read::<u8>(entry, 3),
+                enabled: read::<u32>(entry, 4) & 1 != 0,
+            }),
+            1 => madt.io_apics.push(IoApic {
+                id: read::<u8>(entry, 2),
+                address: read::<u32>(entry, 4),
+                gsi_base: read::<u32>(entry, 8),
+            }),
+            2 => madt.overrides.push(InterruptOverride {
+                source: read::<u8>(entry, 3),
+                gsi: read::<u32>(entry, 4),
+                flags: read::<u16>(entry, 8),
+            }),
+            5 => madt.local_apic_address = read::<u64>(entry, 4),
+            _ => {}
+        }
+        offset += entry_len;
+    }
+    madt
+}
+
+unsafe fn parse_hpet(table: u64) -> Hpet {
+    Hpet {
+        address: parse_gas(table + 40).address,
+        number: read::<u8>(table, 52),
+        min_tick: read::<u16>(table, 53),
+    }
+}
+
+/// Reads an AML integer (a byte prefix or the Zero / One opcodes) at `addr`
+/// returns the value and its length in bytes
+unsafe fn read_aml_integer(addr: u64) -> Option<(u16, u64)> {
+    match read::<u8>(addr, 0) {
+        0x00 => Some((0, 1)), // ZeroOp
+        0x01 => Some((1, 1)), // OneOp
+        0x0A => Some((read::<u8>(addr, 1) as u16, 2)), // BytePrefix
+        _ => None,
+    }
+}
+
+/// Finds the sleep types of `Name (_S5, Package () { SLP_TYPa, SLP_TYPb, ... })` in the DSDT
+unsafe fn find_s5(dsdt: u64) -> Option<(u16, u16)> {
+    let len = read::<u32>(dsdt, 4) as u64;
+
+    let mut offset = 36;
+    while offset + 4 < len {
+        if read::<[u8; 4]>(dsdt, offset) == *b"_S5_" {
+            // must be a NameOp (optionally with a root prefix), not a reference to _S5
+            let name_op = read::<u8>(dsdt, offset - 1) == 0x08
+                || (read::<u8>(dsdt, offset - 2) == 0x08 && read::<u8>(dsdt, offset - 1) == b'\\');
+            if name_op && read::<u8>(dsdt, offset + 4) == 0x12 { // PackageOp
+                // skip the package length (its top 2 bits give the extra length bytes) and element count
+                let mut pos = dsdt + offset + 5;
+                pos += ((read::<u8>(pos, 0) >> 6) as u64) + 1;
+                pos += 1;
+
+                let (slp_typ_a, a_len) = read_aml_integer(pos)?;
+                let (slp_typ_b, _) = read_aml_integer(pos + a_len)?;
+                return Some((slp_typ_a, slp_typ_b));
+            }
+        }
+        offset += 1;
+    }
+    None
+}
+
+// ================= POWER
+
+/// Turns off the computer through ACPI soft-off (S5)
+pub fn shutdown() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let Some(acpi) = get() {
+        if let Some((slp_typ_a, slp_typ_b)) = acpi.s5 {
+            let fadt = &acpi.fadt;
+            unsafe {
+                enable_acpi_mode(fadt);
+
+                Port::<u16>::new(fadt.pm1a_control as u16).write((slp_typ_a << 10) | SLP_EN);
+                if fadt.pm1b_control != 0 {
+                    Port::<u16>::new(fadt.pm1b_control as u16).write((slp_typ_b << 10) | SLP_EN);
+                }
+            }
+        }
+    }
+
+    // fallback for QEMU's default machine, in case the tables could not be used (on other
+    // machines the port can belong to anything, so is fw_cfg's, hence the hypervisor bit first)
+    if cpu::has(Feature::Hypervisor) && fw_cfg::is_present() {
+        unsafe { Port::<u16>::new(0x604).write(0x2000) };
+    }
+
+    println!("&eShutdown failed, it is now safe to turn off your computer.");
+    hlt_loop();
+}
+
+/// Switches the chipset into ACPI mode if the firmware left it in legacy mode
+///
+/// Unsafe due to writing to ports
+unsafe fn enable_acpi_mode(fadt: &Fadt) {
+    let mut control: Port<u16> = Port::new(fadt.pm1a_control as u16);
+    if control.read() & SCI_EN != 0 || fadt.smi_command == 0 || fadt.acpi_enable == 0 {
+        return; // already enabled, or no way to enable it
+    }
+
+    Port::<u8>::new(fadt.smi_command as u16).write(fadt.acpi_enable);
+    for _ in 0..1_000_000 { // the switch is not instant, give it some time
+        if control.read() & SCI_EN != 0 {
+            return;
+        }
+    }
+}
+
+/// Restarts the computer through the FADT reset register,
+/// falling back to the keyboard controller and finally a triple fault
+pub fn reboot() -> ! {
+    x86_64::instructions::interrupts::disable();
+
+    if let Some(acpi) = get() {
+        if let Some(reg) = acpi.fadt.reset_register {
+            unsafe { write_reset_register(reg, acpi.fadt.reset_value) };
+        }
+    }
+
+    unsafe {
+        // pulse the cpu reset line through the 8042 keyboard controller
+        let mut status: Port<u8> = Port::new(0x64);
+        for _ in 0..100_000 {
+            if status.read() & 0x02 == 0 { // wait for the input buffer to be empty
+                break;
+            }
+        }
+        status.write(0xFE);
+
+        // an empty IDT turns the next interrupt into a triple fault, which resets the cpu
+        let empty_idt: [u8; 10] = [0; 10];
+        asm!("lidt [{}]", "int3", in(reg) &empty_idt, options(nostack));
+    }
+
+    hlt_loop();
+}
+
+/// Unsafe due to writing to ports / physical memory
+unsafe fn write_reset_register(reg: GenericAddress, value: u8) {
+    match reg.space {
+        0 => ptr::write_volatile(phys_to_virt(PhysAddr::new(reg.address)).as_mut_ptr::<u8>(), value),
+        1 => Port::<u8>::new(reg.address as u16).write(value),
+        2 => {
+            // pci configuration space of bus 0: device in bits 32-47, function 16-31, offset 0-15
+            let device = (reg.address >> 32) & 0x1F;
+            let function = (reg.address >> 16) & 0x7;
+            let offset = reg.address & 0xFC;
+            let config_address = 0x8000_0000 | (device << 11) as u32 | (function << 8) as u32 | offset as u32;
+            Port::<u32>::new(0xCF8).write(config_address);
+            Port::<u8>::new(0xCFC + (reg.address & 0x3) as u16).write(value);
+        }
+        _ => {}
+    }
+}
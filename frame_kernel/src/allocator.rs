@@ -1,5 +1,6 @@
 use alloc::alloc::{GlobalAlloc, Layout};
 use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 //use crate::allocator::bump::BumpAllocator;
 use x86_64::{
@@ -25,6 +26,13 @@ static ALLOCATOR: Locked<LinkedListAllocator> = Locked::new(LinkedListAllocator:
 // static ALLOCATOR: Locked<FixedSizeBlockAllocator> = Locked::new(FixedSizeBlockAllocator::new());
 // static ALLOCATOR: Locked<BumpAllocator> = Locked::new(BumpAllocator::new());
 
+static HEAP_READY: AtomicBool = AtomicBool::new(false);
+
+/// returns true once init_heap(...) has run, for code that also runs before the heap exists
+pub fn heap_ready() -> bool {
+    HEAP_READY.load(Ordering::SeqCst)
+}
+
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
@@ -48,6 +56,7 @@ pub fn init_heap(
     unsafe {
         ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
     }
+    HEAP_READY.store(true, Ordering::SeqCst);
 
     Ok(())
 }
@@ -0,0 +1,164 @@
+use core::fmt;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::{memory, print, serial, serial_print, vga_textmode};
+
+mod symbols {
+    // generated by build.rs, empty unless the kernel was built with FRAME_KERNEL_SYMBOLS set
+    include!(concat!(env!("OUT_DIR"), "/symbols.rs"));
+}
+
+/// the maximum amount of frames to walk before giving up (protects against corrupted stacks)
+const MAX_FRAMES: usize = 32;
+
+static IN_BACKTRACE: AtomicBool = AtomicBool::new(false);
+
+/// A single frame of a backtrace
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub rip: u64, // the return address of the frame
+    pub rbp: u64, // the frame pointer of the frame
+}
+
+/// Looks up the function containing `addr` in the embedded symbol table
+/// returns the symbol name and the offset of `addr` into it
+pub fn resolve(addr: u64) -> Option<(&'static str, u64)> {
+    let table = symbols::SYMBOLS;
+    let index = match table.binary_search_by_key(&addr, |s| s.0) {
+        Ok(i) => i,
+        Err(0) => return None, // before the first symbol
+        Err(i) => i - 1, // the closest symbol below the address
+    };
+    let (start, name) = table[index];
+    Some((name, addr - start))
+}
+
+/// Reads the frame pointer of the caller
+#[inline(always)]
+pub fn current_rbp() -> u64 {
+    let rbp: u64;
+    unsafe {
+        asm!("mov {}, rbp", out(reg) rbp, options(nomem, nostack));
+    }
+    rbp
+}
+
+/// Walks the frame pointer chain starting at `rbp`, calling `f` for every frame found
+///
+/// Unsafe because `rbp` must be a valid frame pointer (the kernel is built with
+/// `-C force-frame-pointers=yes` so every function saves one), the walk stops at one that isn't
+/// mapped but can't tell a mapped one that isn't a frame
+pub unsafe fn walk<F: FnMut(Frame)>(mut rbp: u64, mut f: F) {
+    for _ in 0..MAX_FRAMES {
+        // a null or misaligned frame pointer marks the end of the chain (or a corrupted stack)
+        if rbp == 0 || rbp % 8 != 0 {
+            return;
+        }
+        // so is one pointing at memory that isn't mapped, reading it would fault in the middle
+        // of the report (the return address can be on the next page)
+        if memory::page_flags(rbp).is_none() || rbp.checked_add(8).and_then(memory::page_flags).is_none() {
+            return;
+        }
+        let frame = rbp as *const u64;
+        let next_rbp = *frame; // the saved rbp of the caller
+        let rip = *frame.offset(1); // the return address pushed by `call`
+        if rip == 0 {
+            return;
+        }
+        f(Frame { rip, rbp });
+
+        // stacks grow down, so a caller's frame is always above its callee
+        if next_rbp <= rbp {
+            return;
+        }
+        rbp = next_rbp;
+    }
+}
+
+/// Prints a line to both the screen and serial
+/// With `try_lock`, a screen or serial port that is locked is skipped instead of waited for
+fn output(try_lock: bool, screen: fmt::Arguments, serial: fmt::Arguments) {
+    if try_lock {
+        vga_textmode::try_print(screen);
+        serial::try_print(serial);
+    } else {
+        print!("{}", screen);
+        serial_print!("{}", serial);
+    }
+}
+
+/// Prints a line of the backtrace
+fn print_frame(try_lock: bool, index: usize, rip: u64) {
+    match resolve(rip) {
+        Some((name, offset)) => output(try_lock,
+            format_args!("&8  {:>2}: &7{:#018x} &b{}&8+{:#x}\n", index, rip, name, offset),
+            format_args!("  {:>2}: {:#018x} {}+{:#x}\n", index, rip, name, offset)),
+        None => output(try_lock,
+            format_args!("&8  {:>2}: &7{:#018x} &8<unknown>\n", index, rip),
+            format_args!("  {:>2}: {:#018x} <unknown>\n", index, rip)),
+    }
+}
+
+/// Prints the backtrace for a chain of frames, with `first_rip` as frame 0 if given
+///
+/// Unsafe because of call to walk(...)
+unsafe fn print_chain(try_lock: bool, first_rip: Option<u64>, rbp: u64) {
+    // a fault while walking would call back into here, so only allow one backtrace at a time
+    if IN_BACKTRACE.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    output(try_lock, format_args!("&8Backtrace:\n"), format_args!("Backtrace:\n"));
+    if symbols::SYMBOLS.is_empty() {
+        output(try_lock,
+            format_args!("&8  (no symbol table, rebuild with FRAME_KERNEL_SYMBOLS set)\n"),
+            format_args!("  (no symbol table, rebuild with FRAME_KERNEL_SYMBOLS set)\n"));
+    }
+
+    let mut index = 0;
+    if let Some(rip) = first_rip {
+        print_frame(try_lock, index, rip);
+        index += 1;
+    }
+    walk(rbp, |frame| {
+        print_frame(try_lock, index, frame.rip);
+        index += 1;
+    });
+
+    IN_BACKTRACE.store(false, Ordering::SeqCst);
+}
+
+/// Prints a backtrace of the current call stack
+#[inline(always)]
+pub fn print_backtrace() {
+    let rbp = current_rbp();
+    unsafe { print_chain(false, None, rbp) };
+}
+
+/// Prints a backtrace of the code that was interrupted by an exception
+///
+/// Must be called directly from the exception handler, as the handler's own frame
+/// is used to find the frame pointer of the interrupted code
+#[inline(always)]
+pub fn print_exception_backtrace(stack_frame: &InterruptStackFrame) {
+    let rbp = current_rbp();
+    unsafe { print_interrupted(false, stack_frame, rbp) };
+}
+
+/// Like print_exception_backtrace(...), but skips the screen or serial port if it is locked
+/// For handlers of interrupts that can come while the interrupted code holds one of the locks
+#[inline(always)]
+pub fn try_print_exception_backtrace(stack_frame: &InterruptStackFrame) {
+    let rbp = current_rbp();
+    unsafe { print_interrupted(true, stack_frame, rbp) };
+}
+
+/// Unsafe because `rbp` must be the frame pointer of an interrupt handler
+unsafe fn print_interrupted(try_lock: bool, stack_frame: &InterruptStackFrame, rbp: u64) {
+    // the handler's prologue saved the interrupted code's rbp, but the slot above it is
+    // the interrupt stack frame instead of a return address, so start from the saved rbp
+    let interrupted_rbp = *(rbp as *const u64);
+    print_chain(try_lock, Some(stack_frame.instruction_pointer.as_u64()), interrupted_rbp);
+}
@@ -1,4 +1,105 @@
-use alloc::string::String;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 
-pub mod memcmd;
\ No newline at end of file
+use futures_util::{future::{select, Either}, pin_mut};
+
+use crate::{print, println};
+use crate::task::key_events::{self, Signal};
+use crate::task::Priority;
+use crate::task::spawner::Spawner;
+use crate::task::sync::mpsc::Receiver;
+
+pub mod memcmd;
+pub mod cpucmd;
+pub mod powercmd;
+pub mod taskcmd;
+pub mod keycmd;
+pub mod consolecmd;
+pub mod gfxcmd;
+
+/// Runs a command line
+/// returns false if the command does not exist
+pub async fn execute(line: &str) -> bool {
+    let mut args = line.split_whitespace();
+    let name = match args.next() {
+        Some(name) => name,
+        None => return true, // empty line, nothing to do
+    };
+
+    match name {
+        "cpuinfo" => cpucmd::cpuinfo(),
+        "shutdown" => powercmd::shutdown(),
+        "reboot" => powercmd::reboot(),
+        "ps" => taskcmd::ps(),
+        "top" => {
+            let refreshes = args.next().and_then(|n| n.parse().ok()).unwrap_or(10);
+            taskcmd::top(refreshes).await
+        }
+        "monitor" => taskcmd::monitor().await,
+        "keymap" => keycmd::keymap(args.next()),
+        "layout" => keycmd::layout(args.next(), args.next()),
+        "kbdrate" => keycmd::kbdrate(args.next(), args.next()).await,
+        "scrollback" => consolecmd::scrollback(args.next()),
+        "blink" => consolecmd::blink(args.next()),
+        "codepage" => consolecmd::codepage(args.next()),
+        "loadfont" => consolecmd::loadfont(args.next(), args.next()),
+        "fontclock" => consolecmd::fontclock(args.next()),
+        "snapshot" => consolecmd::snapshot(args.next(), args.next()),
+        "gfx" => gfxcmd::gfx(args.next()).await,
+        "fbcon" => gfxcmd::fbcon(args.next()),
+        _ => {
+            println!("&cUnknown command: &f{}", name);
+            return false;
+        }
+    }
+    true
+}
+
+/// The shell: runs every line typed into the keyboard's line editor as a command task
+pub async fn shell(mut lines: Receiver<String>, spawner: Spawner) {
+    loop {
+        print!("&3frame&b> &f");
+        let line = match lines.recv().await {
+            Some(line) => line,
+            None => return, // the keyboard task is gone
+        };
+
+        let name = line.split_whitespace().next().unwrap_or("command").to_string();
+        let mut command = spawner.spawn_with(Some(&name), Priority::Normal, async move {
+            execute(&line).await;
+        });
+
+        // the command is in the foreground until it finishes, Ctrl-C cancels it and Ctrl-Z
+        // leaves it running in the background
+        let mut foreground = key_events::claim_foreground();
+        let result = loop {
+            let signal = {
+                let signal = foreground.signal();
+                pin_mut!(signal);
+                match select(&mut command, signal).await {
+                    Either::Left((result, _)) => break Some(result),
+                    Either::Right((signal, _)) => signal,
+                }
+            };
+            match signal {
+                Some(Signal::Interrupt) => {
+                    println!("^C");
+                    command.abort();
+                }
+                Some(Signal::Suspend) => {
+                    println!("^Z");
+                    break None;
+                }
+                Some(Signal::Eof) => {} // commands don't read input
+                None => break Some((&mut command).await),
+            }
+        };
+        drop(foreground);
+
+        match result {
+            Some(Ok(())) => {}
+            Some(Err(_)) => println!("&e{} was cancelled", name),
+            None => println!("&e{} moved to the background", name),
+        }
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::format;
+use alloc::string::String;
+
+use x86_64::instructions::interrupts;
+
+use crate::fw_cfg::{self, FwCfgError};
+use crate::{graphics, println};
+use crate::vga_textmode::{self, CodePage, FontError, Scope, MAX_HEIGHT, MAX_SCROLLBACK};
+
+/// `scrollback [lines]` - shows or sets how many lines this console keeps (up to MAX_SCROLLBACK)
+pub fn scrollback(lines: Option<&str>) {
+    let writer = vga_textmode::console(vga_textmode::output_console());
+    let lines = match lines {
+        Some(lines) => lines,
+        None => {
+            let lines = interrupts::without_interrupts(|| writer.lock().scrollback());
+            println!("&fThis console keeps &b{}&f lines", lines);
+            return;
+        }
+    };
+
+    match lines.parse::<usize>() {
+        Ok(lines) if lines > MAX_SCROLLBACK => {
+            println!("&cA console keeps at most &f{}&c lines", MAX_SCROLLBACK);
+        }
+        Ok(lines) => {
+            let lines = lines.max(MAX_HEIGHT as usize);
+            interrupts::without_interrupts(|| writer.lock().set_scrollback(lines));
+            println!("&fThis console now keeps &b{}&f lines", lines);
+        }
+        Err(_) => println!("&eUsage: scrollback [lines]"),
+    }
+}
+
+/// the biggest font file loadfont reads, a PSF font of 512 glyphs 32 lines high with a unicode
+/// table fits
+const MAX_FONT_FILE: u32 = 64 * 1024;
+
+/// returns true if the VGA is in the text mode, printing why not otherwise
+fn text_mode() -> bool {
+    if graphics::is_active() {
+        println!("&cOnly available in the text mode");
+        return false;
+    }
+    true
+}
+
+/// `blink <on|off>` - makes attribute bit 7 blink text, or select bright backgrounds
+pub fn blink(mode: Option<&str>) {
+    if !text_mode() {
+        return;
+    }
+    match mode {
+        Some("on") => {
+            vga_textmode::set_blink_enabled(true);
+            println!("&fBlinking text %+on%-");
+        }
+        Some("off") => {
+            vga_textmode::set_blink_enabled(false);
+            println!("&fBright backgrounds %+on%-");
+        }
+        _ => println!("&eUsage: blink <on|off>"),
+    }
+}
+
+/// `codepage [name]` - shows the code pages, or switches to one, loading its font from
+/// opt/frame/<name>.psf (see loadfont) the first time
+pub fn codepage(name: Option<&str>) {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            let current = vga_textmode::code_page();
+            for page in CodePage::ALL.iter() {
+                if *page == current {
+                    println!("&a* {}", page.name());
+                } else {
+                    println!("&f  {}", page.name());
+                }
+            }
+            return;
+        }
+    };
+    if !text_mode() { // the font is in the VGA
+        return;
+    }
+
+    let page = match CodePage::from_name(name) {
+        Some(page) => page,
+        None => {
+            println!("&cUnknown code page: &f{}", name);
+            return;
+        }
+    };
+    match vga_textmode::set_code_page(page, None) {
+        Ok(()) => println!("&fCode page set to &b{}", name),
+        Err(FontError::NotLoaded) => {
+            // only cp437 is in the BIOS, the others come from the host
+            let file = font_file(page);
+            if load_font(page, &file) {
+                println!("&fLoaded &b{}&f, code page set to &b{}", file, name);
+            }
+        }
+        Err(err) => println!("&cCouldn't load the font of &f{}&c: {:?}", name, err),
+    }
+}
+
+/// `loadfont <codepage> [file]` - loads the 8x16 PSF font of a code page from a file the host
+/// passed through QEMU's fw_cfg (opt/frame/<codepage>.psf by default) and switches to it
+pub fn loadfont(name: Option<&str>, file: Option<&str>) {
+    let page = match name.and_then(CodePage::from_name) {
+        Some(page) => page,
+        None => {
+            println!("&eUsage: loadfont <codepage> [file]");
+            return;
+        }
+    };
+    if !text_mode() {
+        return;
+    }
+
+    let file = match file {
+        Some(file) => String::from(file),
+        None => font_file(page),
+    };
+    if load_font(page, &file) {
+        println!("&fLoaded &b{}&f, code page set to &b{}", file, page.name());
+    }
+}
+
+/// Loads the font of a code page from a fw_cfg file and switches to it
+/// returns false if it couldn't, printing why
+fn load_font(page: CodePage, file: &str) -> bool {
+    let data = match fw_cfg::read(file, MAX_FONT_FILE) {
+        Ok(data) => data,
+        Err(FwCfgError::NotPresent) => {
+            println!("&cThere is no fw_cfg device to load fonts from (only QEMU has one)");
+            return false;
+        }
+        Err(FwCfgError::NotFound) => {
+            println!("&cThe host passed no &f{}&c, add &f-fw_cfg name={},file=<font.psf>&c to QEMU's arguments", file, file);
+            return false;
+        }
+        Err(FwCfgError::TooBig(size)) => {
+            println!("&f{}&c is too big for a font ({} bytes)", file, size);
+            return false;
+        }
+    };
+    match vga_textmode::load_psf_font(&data, page) {
+        Ok(()) => true,
+        Err(err) => {
+            println!("&cCouldn't load &f{}&c: {:?}", file, err);
+            false
+        }
+    }
+}
+
+/// returns the fw_cfg file loadfont reads the font of a code page from by default
+fn font_file(page: CodePage) -> String {
+    format!("opt/frame/{}.psf", page.name())
+}
+
+/// `fontclock <8|9>` - switches between 8 and 9 pixel wide characters
+pub fn fontclock(width: Option<&str>) {
+    if !text_mode() {
+        return;
+    }
+    match width {
+        Some("8") => vga_textmode::set_nine_dot_clock(false),
+        Some("9") => vga_textmode::set_nine_dot_clock(true),
+        _ => {
+            println!("&eUsage: fontclock <8|9>");
+            return;
+        }
+    }
+    println!("&fCharacters are now &b{}&f pixels wide", width.unwrap_or_default());
+}
+
+/// `snapshot [screen|all] [console]` - sends the screen or the whole scrollback of a console
+/// (this one by default) to the serial port, for tests to check
+pub fn snapshot(scope: Option<&str>, console: Option<&str>) {
+    let scope = match scope {
+        None | Some("screen") => Scope::Screen,
+        Some("all") => Scope::Scrollback,
+        Some(_) => {
+            println!("&eUsage: snapshot [screen|all] [console]");
+            return;
+        }
+    };
+    let console = match console.map(|console| console.parse::<usize>()) {
+        None => vga_textmode::output_console(),
+        Some(Ok(console)) if console < vga_textmode::CONSOLE_COUNT => console,
+        Some(_) => {
+            println!("&cThere are only &f{}&c consoles", vga_textmode::CONSOLE_COUNT);
+            return;
+        }
+    };
+
+    let snapshot = vga_textmode::take_snapshot(console, scope);
+    snapshot.write_serial();
+    println!("&fSent &b{}&f rows of console &b{}&f to the serial port", snapshot.rows.len(), console);
+}
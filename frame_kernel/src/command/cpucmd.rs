@@ -0,0 +1,41 @@
+use crate::cpu::{self, CacheType, Feature};
+use crate::{print, println};
+
+/// `cpuinfo` - prints the vendor, model, caches and features of the cpu
+pub fn cpuinfo() {
+    let info = cpu::info();
+
+    println!("&bVendor:   &f{}", info.vendor());
+    println!("&bModel:    &f{}", info.brand());
+    println!("&bFamily:   &f{:#x} &bModel: &f{:#x} &bStepping: &f{}", info.family, info.model, info.stepping);
+
+    for cache in info.caches() {
+        let kind = match cache.kind {
+            CacheType::Data => "data",
+            CacheType::Instruction => "instruction",
+            CacheType::Unified => "unified",
+        };
+        println!("&bL{} cache: &f{} KiB {} &8({}-way, {} byte lines)",
+                 cache.level, cache.size / 1024, kind, cache.ways, cache.line_size);
+    }
+
+    print!("&bFeatures: ");
+    for feature in Feature::ALL.iter() {
+        if info.has(*feature) {
+            print!("&a{} ", feature.name());
+        } else {
+            print!("&8{} ", feature.name());
+        }
+    }
+    println!();
+
+    print!("&bEnabled:  ");
+    for (feature, enabled) in cpu::enabled_protection().iter() {
+        if *enabled {
+            print!("&a{} ", feature.name());
+        } else {
+            print!("&c{} ", feature.name());
+        }
+    }
+    println!("&f");
+}
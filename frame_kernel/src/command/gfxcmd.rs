@@ -0,0 +1,145 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use crate::graphics::{self, rgb, Framebuffer};
+use crate::println;
+use crate::task::key_events;
+use crate::task::timer::timeout;
+use crate::vga_textmode;
+
+/// how long the demo stays on screen without a key press
+const DEMO_TIME: Duration = Duration::from_secs(10);
+
+/// Goes back to the text mode when the command finishes or is cancelled
+struct TextModeGuard;
+
+impl Drop for TextModeGuard {
+    fn drop(&mut self) {
+        graphics::text_mode();
+    }
+}
+
+fn parse_resolution(arg: &str) -> Option<(usize, usize)> {
+    let mut parts = arg.splitn(2, 'x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    Some((width, height))
+}
+
+/// the framebuffer console's resolution when none is given, 128x48 with an 8x16 font
+const CONSOLE_RESOLUTION: (usize, usize) = (1024, 768);
+
+/// Draws bars, boxes and a fan of lines over the whole screen
+fn draw_demo(screen: &mut Framebuffer) {
+    let (width, height) = (screen.width() as i32, screen.height() as i32);
+    screen.clear(rgb(0, 0, 48));
+
+    let bars = [rgb(255, 0, 0), rgb(255, 255, 0), rgb(0, 255, 0), rgb(0, 255, 255),
+                rgb(0, 0, 255), rgb(255, 0, 255), rgb(255, 255, 255)];
+    let bar_width = width as usize / bars.len();
+    for (i, color) in bars.iter().enumerate() {
+        screen.fill_rect((i * bar_width) as i32, 0, bar_width, height as usize / 8, *color);
+    }
+
+    for i in 0..16 {
+        let x = width * i / 16;
+        screen.draw_line(width / 2, height - 1, x, height / 4, rgb(0, 128 + i as u8 * 8, 255));
+    }
+
+    // a gradient, blitted over a frame
+    let size = (height / 4) as usize;
+    let mut gradient = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            gradient.push(rgb((x * 255 / size) as u8, (y * 255 / size) as u8, 128));
+        }
+    }
+    screen.draw_rect(15, height / 4 - 1, size + 2, size + 2, rgb(255, 255, 255));
+    screen.blit(16, height / 4, size, &gradient);
+}
+
+/// `gfx [13h|<width>x<height>]` - shows a graphics mode until a key is pressed
+pub async fn gfx(mode: Option<&str>) {
+    let mut keys = key_events::subscribe();
+    let mut screen = match mode {
+        Some("13h") => match graphics::set_mode_13h() {
+            Ok(screen) => screen,
+            Err(error) => {
+                println!("&cCouldn't set mode 13h: {:?}", error);
+                return;
+            }
+        },
+        None => match graphics::set_mode(800, 600) {
+            Ok(screen) => screen,
+            Err(error) => {
+                println!("&cCouldn't set 800x600: {:?}", error);
+                return;
+            }
+        },
+        Some(arg) => {
+            let (width, height) = match parse_resolution(arg) {
+                Some(resolution) => resolution,
+                None => {
+                    println!("&cUsage: gfx [13h|<width>x<height>]");
+                    return;
+                }
+            };
+            match graphics::set_mode(width, height) {
+                Ok(screen) => screen,
+                Err(error) => {
+                    println!("&cCouldn't set {}x{}: {:?}", width, height, error);
+                    if let Some((max_width, max_height)) = graphics::max_resolution() {
+                        println!("&7the largest mode is {}x{}", max_width, max_height);
+                    }
+                    return;
+                }
+            }
+        }
+    };
+    let _text_mode = TextModeGuard;
+
+    draw_demo(&mut screen);
+    screen.present();
+
+    let key = async {
+        while let Some(event) = keys.next_event().await {
+            if event.is_pressed() {
+                return;
+            }
+        }
+    };
+    timeout(DEMO_TIME, key).await.ok();
+}
+
+/// `fbcon [<width>x<height>|off]` - moves the consoles onto a framebuffer, or back to the text mode
+pub fn fbcon(mode: Option<&str>) {
+    let (width, height) = match mode {
+        Some("off") => {
+            graphics::text_mode();
+            return;
+        }
+        Some(arg) => match parse_resolution(arg) {
+            Some(resolution) => resolution,
+            None => {
+                println!("&cUsage: fbcon [<width>x<height>|off]");
+                return;
+            }
+        },
+        None => CONSOLE_RESOLUTION,
+    };
+    // the font the consoles are drawn in now, read before the mode change draws over it
+    let font = match vga_textmode::console_font() {
+        Some(font) => font,
+        None => {
+            println!("&cA graphics mode has the screen");
+            return;
+        }
+    };
+    match graphics::set_mode(width, height) {
+        Ok(screen) => {
+            let (cols, rows) = vga_textmode::use_framebuffer(screen, font);
+            println!("&fThe console is now &b{}x{}&f characters", cols, rows);
+        }
+        Err(error) => println!("&cCouldn't set {}x{}: {:?}", width, height, error),
+    }
+}
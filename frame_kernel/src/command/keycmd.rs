@@ -0,0 +1,99 @@
+use alloc::format;
+use alloc::string::String;
+
+use crate::fw_cfg::{self, FwCfgError};
+use crate::{println, ps2};
+use crate::task::keymap;
+
+/// `keymap [layout]` - shows the keyboard layouts, or switches to one
+pub fn keymap(layout: Option<&str>) {
+    let name = match layout {
+        Some(name) => name,
+        None => {
+            let current = keymap::current();
+            for name in keymap::available() {
+                if name == current {
+                    println!("&a* {}", name);
+                } else {
+                    println!("&f  {}", name);
+                }
+            }
+            return;
+        }
+    };
+
+    if keymap::set_layout(name) {
+        println!("&fKeyboard layout set to &b{}", name);
+    } else {
+        println!("&cUnknown keyboard layout: &f{}", name);
+    }
+}
+
+/// the biggest layout file `layout` reads
+const MAX_LAYOUT_FILE: u32 = 64 * 1024;
+
+/// `layout <name> [file]` - loads a custom keyboard layout from a file the host passed through
+/// QEMU's fw_cfg (opt/frame/<name>.kbd by default) and switches to it
+pub fn layout(name: Option<&str>, file: Option<&str>) {
+    let name = match name {
+        Some(name) => name,
+        None => {
+            println!("&eUsage: layout <name> [file]");
+            return;
+        }
+    };
+    let file_name = match file {
+        Some(file) => String::from(file),
+        None => format!("opt/frame/{}.kbd", name),
+    };
+
+    let data = match fw_cfg::read(&file_name, MAX_LAYOUT_FILE) {
+        Ok(data) => data,
+        Err(FwCfgError::NotPresent) => {
+            println!("&cThere is no fw_cfg device to load layouts from (only QEMU has one)");
+            return;
+        }
+        Err(FwCfgError::NotFound) => {
+            println!("&cThe host passed no &f{}&c, add &f-fw_cfg name={},file=<layout file>&c to QEMU's arguments",
+                     file_name, file_name);
+            return;
+        }
+        Err(FwCfgError::TooBig(size)) => {
+            println!("&f{}&c is too big for a layout ({} bytes)", file_name, size);
+            return;
+        }
+    };
+    let text = match core::str::from_utf8(&data) {
+        Ok(text) => text,
+        Err(_) => {
+            println!("&f{}&c isn't UTF-8 text", file_name);
+            return;
+        }
+    };
+
+    match keymap::load_layout(name, text) {
+        Ok(()) => {
+            keymap::set_layout(name);
+            println!("&fLoaded &b{}&f, keyboard layout set to &b{}", file_name, name);
+        }
+        Err(err) => println!("&cCouldn't load &f{}&c: {:?}", file_name, err),
+    }
+}
+
+/// `kbdrate <chars per second> <delay ms>` - sets how fast held keys repeat
+pub async fn kbdrate(rate: Option<&str>, delay: Option<&str>) {
+    let rate = rate.and_then(|rate| rate.parse().ok());
+    let delay = delay.and_then(|delay| delay.parse().ok()).unwrap_or(500);
+    let rate = match rate {
+        Some(rate) => rate,
+        None => {
+            println!("&eUsage: kbdrate <chars per second (2-30)> [delay ms (250-1000)]");
+            return;
+        }
+    };
+
+    match ps2::set_typematic(rate, delay).await {
+        Ok(()) => println!("&fKey repeat set to &b{}&f/s after &b{}&fms", rate, delay),
+        Err(err) => println!("&cCouldn't set the key repeat rate: {:?}", err),
+    }
+}
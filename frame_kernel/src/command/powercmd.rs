@@ -0,0 +1,14 @@
+use crate::acpi;
+use crate::println;
+
+/// `shutdown` - turns off the computer
+pub fn shutdown() {
+    println!("&eShutting down...");
+    acpi::shutdown();
+}
+
+/// `reboot` - restarts the computer
+pub fn reboot() {
+    println!("&eRebooting...");
+    acpi::reboot();
+}
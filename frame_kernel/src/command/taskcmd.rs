@@ -0,0 +1,192 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::time::Duration;
+
+use pc_keyboard::KeyCode;
+
+use crate::task::info::{Snapshot, TaskInfo, TaskState};
+use crate::task::timer::sleep;
+use crate::task::TaskId;
+use crate::ui::{Block, Constraint, Event, Focus, Label, List, Menu, ProgressBar, Response, Screen,
+                StatusBar, TextInput, Widget, THEME};
+use crate::{clear_vga, println, time};
+
+fn state_name(state: TaskState) -> &'static str {
+    match state {
+        TaskState::Ready => "&aready  ",
+        TaskState::Pending => "&7pending",
+        TaskState::Finished => "&8done   ",
+    }
+}
+
+fn print_task(task: &TaskInfo, cpu: Option<u64>) {
+    let age_s = time::ticks().saturating_sub(task.spawn_tick) / time::TIMER_HZ;
+    let runtime_ms = time::tsc_to_us(task.runtime) / 1000;
+    match cpu {
+        Some(cpu) => println!("&f{:>4} {:<16} {:?}\t{} &f{:>8} {:>8}ms {:>6}s {:>3}%",
+                              task.id.as_u64(), task.name, task.priority, state_name(task.state),
+                              task.polls, runtime_ms, age_s, cpu),
+        None => println!("&f{:>4} {:<16} {:?}\t{} &f{:>8} {:>8}ms {:>6}s",
+                         task.id.as_u64(), task.name, task.priority, state_name(task.state),
+                         task.polls, runtime_ms, age_s),
+    }
+}
+
+/// `ps` - lists the running and recently finished tasks
+pub fn ps() {
+    let snapshot = Snapshot::take();
+    println!("&b  ID NAME             PRI\tSTATE      POLLS     TIME    AGE");
+    for task in snapshot.tasks.iter() {
+        print_task(task, None);
+    }
+}
+
+/// `top [refreshes]` - shows the tasks and their cpu share, refreshing every second
+pub async fn top(refreshes: u32) {
+    let mut earlier = Snapshot::take();
+    for _ in 0..refreshes {
+        sleep(Duration::from_secs(1)).await;
+        let now = Snapshot::take();
+
+        clear_vga!();
+        println!("&bFrameOS top &8- &fuptime {}s, {} tasks, &a{}% idle",
+                 time::uptime_ms() / 1000, now.tasks.iter().filter(|t| t.state != TaskState::Finished).count(),
+                 now.idle_percent(&earlier));
+        println!("&b  ID NAME             PRI\tSTATE      POLLS     TIME    AGE CPU");
+        for task in now.tasks.iter().filter(|t| t.state != TaskState::Finished) {
+            print_task(task, Some(now.cpu_percent(&earlier, task.id)));
+        }
+        earlier = now;
+    }
+}
+
+/// the orders the monitor can list the tasks in, as named in its menu
+const SORT_ORDERS: [&str; 4] = ["Id", "Name", "Cpu", "Time"];
+
+/// Sorts the tasks in one of SORT_ORDERS, the busiest first for Cpu and Time
+fn sort_tasks(tasks: &mut [(&TaskInfo, u64)], order: usize) {
+    match SORT_ORDERS[order] {
+        "Name" => tasks.sort_by(|a, b| a.0.name.cmp(&b.0.name)),
+        "Cpu" => tasks.sort_by(|a, b| b.1.cmp(&a.1)),
+        "Time" => tasks.sort_by(|a, b| b.0.runtime.cmp(&a.0.runtime)),
+        _ => tasks.sort_by_key(|task| task.0.id.as_u64()),
+    }
+}
+
+/// returns the details of a task for the monitor's side panel
+fn task_details(task: &TaskInfo, cpu: u64) -> String {
+    format!("{}\n\nid       {}\npriority {:?}\nstate    {:?}\npolls    {}\ntime     {}ms\nage      {}s\ncpu      {}%",
+            task.name, task.id.as_u64(), task.priority, task.state, task.polls,
+            time::tsc_to_us(task.runtime) / 1000,
+            time::ticks().saturating_sub(task.spawn_tick) / time::TIMER_HZ, cpu)
+}
+
+/// `monitor` - a full screen task monitor: Tab moves between the task list and the name filter,
+/// F2 changes the order and Esc quits
+pub async fn monitor() {
+    let mut screen = Screen::new();
+    let mut focus = Focus::new(2); // the task list, then the filter
+    let mut list = List::new(Vec::new());
+    let mut filter = TextInput::new();
+    let mut menu: Option<Menu> = None;
+    let mut order = 2; // by cpu
+    let mut cpu = ProgressBar::new(100);
+    let status = StatusBar::new("Tab: focus  F2: sort  Esc: quit");
+    let mut earlier = Snapshot::take();
+    let mut now = Snapshot::take();
+    let mut shown: Vec<TaskId> = Vec::new(); // the tasks on the list, in order
+
+    loop {
+        let name = filter.value();
+        let mut tasks: Vec<(&TaskInfo, u64)> = now.tasks.iter()
+            .filter(|task| task.state != TaskState::Finished && task.name.contains(name.as_str()))
+            .map(|task| (task, now.cpu_percent(&earlier, task.id)))
+            .collect();
+        sort_tasks(&mut tasks, order);
+        // keep the same task selected when the list changes order
+        let selected = list.selected().and_then(|index| shown.get(index).copied());
+        shown = tasks.iter().map(|(task, _)| task.id).collect();
+        list.set_items(tasks.iter().map(|(task, cpu)| {
+            format!("{:>4} {:<16} {:>3}% {:>8}ms", task.id.as_u64(), task.name, cpu,
+                    time::tsc_to_us(task.runtime) / 1000)
+        }).collect());
+        if let Some(index) = selected.and_then(|id| shown.iter().position(|shown| *shown == id)) {
+            list.select(index);
+        }
+        cpu.value = 100u64.saturating_sub(now.idle_percent(&earlier));
+
+        let cursor = {
+            let canvas = screen.canvas();
+            canvas.clear(THEME.normal);
+            let rows = canvas.area().rows(&[Constraint::Fill, Constraint::Fixed(3), Constraint::Fixed(3),
+                                            Constraint::Fixed(1)]);
+            let top = rows[0].columns(&[Constraint::Fill, Constraint::Fixed(30)]);
+
+            let tasks_block = Block::new(&format!("Tasks by {}", SORT_ORDERS[order]));
+            tasks_block.draw(canvas, top[0], focus.is(0));
+            let inner = tasks_block.inner(top[0]).rows(&[Constraint::Fixed(1), Constraint::Fill]);
+            let mut header = Label::new(&format!("{:>4} {:<16} {:>4} {:>8}", "ID", "NAME", "CPU", "TIME"));
+            header.style = THEME.title;
+            header.draw(canvas, inner[0], false);
+            list.draw(canvas, inner[1], focus.is(0));
+
+            let details = Block::new("Details");
+            details.draw(canvas, top[1], false);
+            let text = list.selected().and_then(|index| tasks.get(index))
+                .map(|(task, cpu)| task_details(task, *cpu))
+                .unwrap_or_default();
+            Label::new(&text).draw(canvas, details.inner(top[1]), false);
+
+            let filter_block = Block::new("Filter");
+            filter_block.draw(canvas, rows[1], focus.is(1));
+            filter.draw(canvas, filter_block.inner(rows[1]), focus.is(1));
+
+            let cpu_block = Block::new("Cpu");
+            cpu_block.draw(canvas, rows[2], false);
+            cpu.draw(canvas, cpu_block.inner(rows[2]), false);
+
+            status.draw(canvas, rows[3], false);
+
+            if let Some(menu) = &menu {
+                let area = menu.area(canvas.area());
+                menu.draw(canvas, area, true);
+                None
+            } else if focus.is(1) {
+                filter.cursor(filter_block.inner(rows[1]))
+            } else {
+                None
+            }
+        };
+        drop(tasks);
+        screen.present(cursor);
+
+        let key = match screen.next_event().await {
+            Some(Event::Key(key)) => key,
+            Some(Event::Tick) => {
+                earlier = now;
+                now = Snapshot::take();
+                continue;
+            }
+            None => return,
+        };
+        if let Some(open) = &mut menu {
+            match open.handle_key(&key) {
+                Response::Activated => {
+                    order = open.selected();
+                    menu = None;
+                }
+                Response::Cancelled => menu = None,
+                _ => {}
+            }
+            continue;
+        }
+        match key.code {
+            KeyCode::Escape => return,
+            KeyCode::F2 => menu = Some(Menu::new("Sort by", &SORT_ORDERS)),
+            _ if focus.handle_key(&key) => {}
+            _ if focus.is(0) => { list.handle_key(&key); }
+            _ => { filter.handle_key(&key); }
+        }
+    }
+}
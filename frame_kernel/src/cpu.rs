@@ -0,0 +1,277 @@
+use core::arch::x86_64::{__cpuid, __cpuid_count, CpuidResult};
+use core::str;
+
+use lazy_static::lazy_static;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+
+const MAX_CACHES: usize = 8;
+
+/// CPU features that FrameOS cares about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Sse,
+    Sse2,
+    Sse3,
+    Ssse3,
+    Sse41,
+    Sse42,
+    Avx,
+    Avx2,
+    Xsave,
+    Nx,
+    Smep,
+    Smap,
+    Umip,
+    Rdrand,
+    Pcid,
+    HugePages1G, // 1 GiB pages
+    X2Apic,
+    Hypervisor, // running in a virtual machine
+}
+
+impl Feature {
+    /// every feature, in the order they are displayed
+    pub const ALL: [Feature; 18] = [
+        Feature::Sse, Feature::Sse2, Feature::Sse3, Feature::Ssse3, Feature::Sse41,
+        Feature::Sse42, Feature::Avx, Feature::Avx2, Feature::Xsave, Feature::Nx,
+        Feature::Smep, Feature::Smap, Feature::Umip, Feature::Rdrand, Feature::Pcid,
+        Feature::HugePages1G, Feature::X2Apic, Feature::Hypervisor,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Sse => "sse",
+            Feature::Sse2 => "sse2",
+            Feature::Sse3 => "sse3",
+            Feature::Ssse3 => "ssse3",
+            Feature::Sse41 => "sse4.1",
+            Feature::Sse42 => "sse4.2",
+            Feature::Avx => "avx",
+            Feature::Avx2 => "avx2",
+            Feature::Xsave => "xsave",
+            Feature::Nx => "nx",
+            Feature::Smep => "smep",
+            Feature::Smap => "smap",
+            Feature::Umip => "umip",
+            Feature::Rdrand => "rdrand",
+            Feature::Pcid => "pcid",
+            Feature::HugePages1G => "pdpe1gb",
+            Feature::X2Apic => "x2apic",
+            Feature::Hypervisor => "hypervisor",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheType {
+    Data,
+    Instruction,
+    Unified,
+}
+
+/// A single cache level as reported by cpuid
+#[derive(Debug, Clone, Copy)]
+pub struct Cache {
+    pub level: u8,
+    pub kind: CacheType,
+    pub size: usize, // in bytes
+    pub line_size: usize,
+    pub ways: usize,
+}
+
+pub struct CpuInfo {
+    vendor: [u8; 12],
+    brand: [u8; 48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    caches: [Option<Cache>; MAX_CACHES],
+    // raw feature registers, decoded by has(...)
+    leaf1: CpuidResult,
+    leaf7: CpuidResult,
+    ext1: CpuidResult,
+}
+
+lazy_static! {
+    static ref CPU_INFO: CpuInfo = CpuInfo::read();
+}
+
+/// returns the information of the current cpu
+pub fn info() -> &'static CpuInfo {
+    &CPU_INFO
+}
+
+/// shortcut for info().has(feature)
+pub fn has(feature: Feature) -> bool {
+    CPU_INFO.has(feature)
+}
+
+fn bit(reg: u32, bit: u32) -> bool {
+    reg & (1 << bit) != 0
+}
+
+fn empty_cpuid() -> CpuidResult {
+    CpuidResult { eax: 0, ebx: 0, ecx: 0, edx: 0 }
+}
+
+impl CpuInfo {
+    fn read() -> CpuInfo {
+        unsafe {
+            let leaf0 = __cpuid(0);
+            let max_leaf = leaf0.eax;
+            let max_ext_leaf = __cpuid(0x8000_0000).eax;
+
+            // the vendor string is stored in ebx, edx, ecx (in that order)
+            let mut vendor = [0u8; 12];
+            vendor[0..4].copy_from_slice(&leaf0.ebx.to_le_bytes());
+            vendor[4..8].copy_from_slice(&leaf0.edx.to_le_bytes());
+            vendor[8..12].copy_from_slice(&leaf0.ecx.to_le_bytes());
+
+            let leaf1 = __cpuid(1);
+            let leaf7 = if max_leaf >= 7 { __cpuid_count(7, 0) } else { empty_cpuid() };
+            let ext1 = if max_ext_leaf >= 0x8000_0001 { __cpuid(0x8000_0001) } else { empty_cpuid() };
+
+            let mut brand = [0u8; 48];
+            if max_ext_leaf >= 0x8000_0004 {
+                for i in 0..3 {
+                    let r = __cpuid(0x8000_0002 + i as u32);
+                    let regs = [r.eax, r.ebx, r.ecx, r.edx];
+                    for (j, reg) in regs.iter().enumerate() {
+                        let start = i * 16 + j * 4;
+                        brand[start..start + 4].copy_from_slice(&reg.to_le_bytes());
+                    }
+                }
+            }
+
+            // family and model are extended when the base values run out
+            let base_family = (leaf1.eax >> 8) & 0xF;
+            let base_model = (leaf1.eax >> 4) & 0xF;
+            let family = if base_family == 0xF {
+                base_family + ((leaf1.eax >> 20) & 0xFF)
+            } else {
+                base_family
+            };
+            let model = if base_family == 0x6 || base_family == 0xF {
+                base_model | (((leaf1.eax >> 16) & 0xF) << 4)
+            } else {
+                base_model
+            };
+
+            let mut info = CpuInfo {
+                vendor,
+                brand,
+                family,
+                model,
+                stepping: leaf1.eax & 0xF,
+                caches: [None; MAX_CACHES],
+                leaf1,
+                leaf7,
+                ext1,
+            };
+
+            // intel reports caches through leaf 4, amd through leaf 0x8000001D in the same format
+            if &vendor == b"GenuineIntel" && max_leaf >= 4 {
+                info.read_caches(4);
+            } else if max_ext_leaf >= 0x8000_001D && bit(ext1.ecx, 22) {
+                info.read_caches(0x8000_001D);
+            }
+
+            info
+        }
+    }
+
+    /// Reads the deterministic cache parameters from `leaf`
+    unsafe fn read_caches(&mut self, leaf: u32) {
+        for i in 0..MAX_CACHES {
+            let r = __cpuid_count(leaf, i as u32);
+            let kind = match r.eax & 0x1F {
+                1 => CacheType::Data,
+                2 => CacheType::Instruction,
+                3 => CacheType::Unified,
+                _ => return, // no more caches
+            };
+            let ways = ((r.ebx >> 22) & 0x3FF) as usize + 1;
+            let partitions = ((r.ebx >> 12) & 0x3FF) as usize + 1;
+            let line_size = (r.ebx & 0xFFF) as usize + 1;
+            let sets = r.ecx as usize + 1;
+            self.caches[i] = Some(Cache {
+                level: ((r.eax >> 5) & 0x7) as u8,
+                kind,
+                size: ways * partitions * line_size * sets,
+                line_size,
+                ways,
+            });
+        }
+    }
+
+    pub fn vendor(&self) -> &str {
+        str::from_utf8(&self.vendor).unwrap_or("unknown")
+    }
+
+    pub fn brand(&self) -> &str {
+        let end = self.brand.iter().position(|&b| b == 0).unwrap_or(self.brand.len());
+        str::from_utf8(&self.brand[..end]).unwrap_or("unknown").trim()
+    }
+
+    /// returns an iterator over the caches of the cpu
+    pub fn caches(&self) -> impl Iterator<Item = &Cache> {
+        self.caches.iter().filter_map(|c| c.as_ref())
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        match feature {
+            Feature::Sse => bit(self.leaf1.edx, 25),
+            Feature::Sse2 => bit(self.leaf1.edx, 26),
+            Feature::Sse3 => bit(self.leaf1.ecx, 0),
+            Feature::Ssse3 => bit(self.leaf1.ecx, 9),
+            Feature::Sse41 => bit(self.leaf1.ecx, 19),
+            Feature::Sse42 => bit(self.leaf1.ecx, 20),
+            Feature::Avx => bit(self.leaf1.ecx, 28),
+            Feature::Avx2 => bit(self.leaf7.ebx, 5),
+            Feature::Xsave => bit(self.leaf1.ecx, 26),
+            Feature::Nx => bit(self.ext1.edx, 20),
+            Feature::Smep => bit(self.leaf7.ebx, 7),
+            Feature::Smap => bit(self.leaf7.ebx, 20),
+            Feature::Umip => bit(self.leaf7.ecx, 2),
+            Feature::Rdrand => bit(self.leaf1.ecx, 30),
+            Feature::Pcid => bit(self.leaf1.ecx, 17),
+            Feature::HugePages1G => bit(self.ext1.edx, 26),
+            Feature::X2Apic => bit(self.leaf1.ecx, 21),
+            Feature::Hypervisor => bit(self.leaf1.ecx, 31),
+        }
+    }
+}
+
+/// Turns on the protection features supported by the cpu (NX, SMEP, SMAP and UMIP)
+pub fn enable_protection() {
+    unsafe {
+        if has(Feature::Nx) {
+            Efer::update(|flags| *flags |= EferFlags::NO_EXECUTE_ENABLE);
+        }
+
+        let mut cr4 = Cr4::read();
+        if has(Feature::Smep) {
+            cr4 |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+        }
+        if has(Feature::Smap) {
+            cr4 |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+        }
+        if has(Feature::Umip) {
+            cr4 |= Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION;
+        }
+        Cr4::write(cr4);
+    }
+}
+
+/// returns the protection features that are currently turned on
+pub fn enabled_protection() -> [(Feature, bool); 4] {
+    let efer = Efer::read();
+    let cr4 = Cr4::read();
+    [
+        (Feature::Nx, efer.contains(EferFlags::NO_EXECUTE_ENABLE)),
+        (Feature::Smep, cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION)),
+        (Feature::Smap, cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION)),
+        (Feature::Umip, cr4.contains(Cr4Flags::USER_MODE_INSTRUCTION_PREVENTION)),
+    ]
+}
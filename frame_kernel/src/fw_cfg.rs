@@ -0,0 +1,93 @@
+// QEMU's firmware configuration device, which hands files from the host to the kernel
+//
+// QEMU passes a file with `-fw_cfg name=opt/frame/<name>,file=<path on the host>`. Items are read
+// through two I/O ports: writing an item's selector to 0x510 picks it, then every read of 0x511
+// gives its next byte. Item 0x19 is the directory of the files (numbers in it are big endian),
+// item 0x00 the signature "QEMU", which tells the device is there at all.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use x86_64::instructions::{interrupts, port::Port};
+
+const SELECTOR: u16 = 0x510;
+const DATA: u16 = 0x511;
+
+const SIGNATURE_ITEM: u16 = 0x0000;
+const FILE_DIR_ITEM: u16 = 0x0019;
+
+/// the bytes of a file name in the directory, zero padded
+const NAME_SIZE: usize = 56;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FwCfgError {
+    NotPresent, // not running in QEMU
+    NotFound, // the host passed no file of that name
+    TooBig(u32), // the file is bigger than the caller takes
+}
+
+/// A file passed by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct File {
+    pub size: u32,
+    select: u16,
+}
+
+/// Picks an item and reads its first bytes into `buf`
+/// Must be called with interrupts disabled, so nothing picks another item in between
+fn read_item(select: u16, buf: &mut [u8]) {
+    let mut selector: Port<u16> = Port::new(SELECTOR);
+    unsafe { selector.write(select) };
+    read_more(buf);
+}
+
+/// Reads the next bytes of the item picked last
+fn read_more(buf: &mut [u8]) {
+    let mut data: Port<u8> = Port::new(DATA);
+    for byte in buf.iter_mut() {
+        *byte = unsafe { data.read() };
+    }
+}
+
+/// returns true if the device is there
+pub fn is_present() -> bool {
+    let mut signature = [0; 4];
+    interrupts::without_interrupts(|| read_item(SIGNATURE_ITEM, &mut signature));
+    &signature == b"QEMU"
+}
+
+/// Looks for a file the host passed
+pub fn find(name: &str) -> Result<File, FwCfgError> {
+    if !is_present() {
+        return Err(FwCfgError::NotPresent);
+    }
+    interrupts::without_interrupts(|| {
+        let mut count = [0; 4];
+        read_item(FILE_DIR_ITEM, &mut count);
+        for _ in 0..u32::from_be_bytes(count) {
+            // size (4), select (2), reserved (2), name
+            let mut entry = [0; 8 + NAME_SIZE];
+            read_more(&mut entry);
+            let entry_name = &entry[8..];
+            let length = entry_name.iter().position(|&b| b == 0).unwrap_or(NAME_SIZE);
+            if &entry_name[..length] == name.as_bytes() {
+                return Ok(File {
+                    size: u32::from_be_bytes([entry[0], entry[1], entry[2], entry[3]]),
+                    select: u16::from_be_bytes([entry[4], entry[5]]),
+                });
+            }
+        }
+        Err(FwCfgError::NotFound)
+    })
+}
+
+/// Reads a file the host passed, if it is at most `max_size` bytes
+pub fn read(name: &str, max_size: u32) -> Result<Vec<u8>, FwCfgError> {
+    let file = find(name)?;
+    if file.size > max_size {
+        return Err(FwCfgError::TooBig(file.size));
+    }
+    let mut data = vec![0; file.size as usize];
+    interrupts::without_interrupts(|| read_item(file.select, &mut data));
+    Ok(data)
+}
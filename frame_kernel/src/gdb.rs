@@ -0,0 +1,585 @@
+// GDB remote serial protocol stub
+//
+// Lets `gdb` attach to the kernel through COM2 (QEMU: `-serial stdio -serial pty`, then
+// `target remote /dev/pts/N` in gdb). COM1 stays free for serial_println!().
+// The stub is entered from the breakpoint, debug and COM2 interrupt handlers, and talks
+// to gdb with interrupts disabled until it is told to continue or step.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use uart_16550::SerialPort;
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::PageTableFlags;
+
+use crate::interrupts::TrapFrame;
+use crate::memory;
+
+const COM2: u16 = 0x2F8;
+
+/// the maximum size of a packet, advertised to gdb through qSupported
+const PACKET_SIZE: usize = 1024;
+const MAX_BREAKPOINTS: usize = 32;
+
+const RFLAGS_TF: u64 = 1 << 8; // the trap flag, raises a debug exception after every instruction
+
+// signals reported to gdb in stop replies
+pub const SIGINT: u8 = 2;
+pub const SIGTRAP: u8 = 5;
+
+lazy_static! {
+    static ref SERIAL2: Mutex<SerialPort> = {
+        let mut serial_port = unsafe { SerialPort::new(COM2) };
+        serial_port.init();
+        Mutex::new(serial_port)
+    };
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, Copy)]
+struct Breakpoint {
+    addr: u64,
+    original: u8, // the byte replaced by int3
+}
+
+struct State {
+    signal: u8, // the signal of the last stop, for `?`
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    stepping_over: Option<u64>, // a breakpoint removed to single step past it
+    continue_after_step: bool, // true if the step over a breakpoint was part of a `c`
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    signal: SIGTRAP,
+    breakpoints: [None; MAX_BREAKPOINTS],
+    stepping_over: None,
+    continue_after_step: false,
+});
+
+/// Initializes COM2 and lets the breakpoint/debug handlers hand control to gdb
+pub fn init() {
+    lazy_static::initialize(&SERIAL2);
+
+    // unmask IRQ3 on the master PIC so gdb can interrupt the kernel with Ctrl-C
+    unsafe {
+        let mut mask: Port<u8> = Port::new(0x21);
+        let current = mask.read();
+        mask.write(current & !(1 << 3));
+    }
+
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// returns true if gdb::init() has been called
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Stops the kernel and waits for gdb to attach / continue
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+/// Called from the COM2 interrupt handler
+/// returns true if gdb sent an interrupt request (Ctrl-C)
+pub(crate) fn poll_interrupt() -> bool {
+    let mut interrupted = false;
+    while let Some(byte) = try_receive() {
+        if byte == 0x03 {
+            interrupted = true;
+        }
+    }
+    interrupted
+}
+
+/// Enters the stub after a breakpoint, single step or interrupt request
+/// Must be called with interrupts disabled (from an interrupt handler)
+pub(crate) fn handle_trap(frame: &mut TrapFrame, signal: u8) {
+    {
+        let mut state = STATE.lock();
+
+        // finished stepping over a breakpoint, put it back
+        if let Some(addr) = state.stepping_over.take() {
+            unsafe { write_code_byte(addr, 0xCC) };
+            if state.continue_after_step {
+                state.continue_after_step = false;
+                frame.rflags &= !RFLAGS_TF;
+                return; // gdb asked to continue, so don't report the step
+            }
+        }
+
+        state.signal = signal;
+
+        // int3 leaves rip after the instruction, move it back onto the breakpoint
+        if frame.vector == 3 && frame.rip > 0 && state.find(frame.rip - 1).is_some() {
+            frame.rip -= 1;
+        }
+    }
+
+    send_stop_reply(signal);
+    command_loop(frame);
+}
+
+impl State {
+    fn find(&self, addr: u64) -> Option<usize> {
+        self.breakpoints.iter().position(|b| match b {
+            Some(b) => b.addr == addr,
+            None => false,
+        })
+    }
+
+    fn insert(&mut self, addr: u64) -> bool {
+        if self.find(addr).is_some() {
+            return true;
+        }
+        let slot = match self.breakpoints.iter().position(|b| b.is_none()) {
+            Some(slot) => slot,
+            None => return false, // no free breakpoints
+        };
+        if memory::page_flags(addr).is_none() {
+            return false; // not mapped, reading it would fault
+        }
+        let original = unsafe { *(addr as *const u8) };
+        unsafe { write_code_byte(addr, 0xCC) };
+        self.breakpoints[slot] = Some(Breakpoint { addr, original });
+        true
+    }
+
+    fn remove(&mut self, addr: u64) -> bool {
+        match self.find(addr) {
+            Some(slot) => {
+                let bp = self.breakpoints[slot].take().unwrap();
+                unsafe { write_code_byte(bp.addr, bp.original) };
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn original_byte(&self, addr: u64) -> Option<u8> {
+        self.find(addr).map(|slot| self.breakpoints[slot].unwrap().original)
+    }
+}
+
+/// Writes a byte into (possibly read only) kernel code
+///
+/// Unsafe because the address must be mapped and writing code can break the kernel
+unsafe fn write_code_byte(addr: u64, byte: u8) {
+    use x86_64::registers::control::{Cr0, Cr0Flags};
+
+    // kernel code is mapped read only, so turn off write protection for the write
+    let cr0 = Cr0::read();
+    Cr0::write(cr0 & !Cr0Flags::WRITE_PROTECT);
+    core::ptr::write_volatile(addr as *mut u8, byte);
+    Cr0::write(cr0);
+}
+
+// ================= SERIAL IO
+
+fn try_receive() -> Option<u8> {
+    // bit 0 of the line status register is set when a byte is waiting
+    let mut line_status: Port<u8> = Port::new(COM2 + 5);
+    if unsafe { line_status.read() } & 1 == 0 {
+        return None;
+    }
+    Some(SERIAL2.lock().receive())
+}
+
+fn receive() -> u8 {
+    SERIAL2.lock().receive()
+}
+
+fn send(byte: u8) {
+    SERIAL2.lock().send(byte);
+}
+
+// ================= PACKETS
+
+/// A fixed size buffer, as the stub can't rely on the heap allocator
+/// (the interrupted code may be holding its lock)
+struct Packet {
+    data: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+impl Packet {
+    fn new() -> Packet {
+        Packet {
+            data: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.len < PACKET_SIZE {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for b in s.bytes() {
+            self.push(b);
+        }
+    }
+
+    /// pushes `value` as little endian hex, `bytes` bytes long (how gdb expects registers)
+    fn push_hex_le(&mut self, value: u64, bytes: usize) {
+        for i in 0..bytes {
+            self.push_hex_byte((value >> (i * 8)) as u8);
+        }
+    }
+
+    fn push_hex_byte(&mut self, byte: u8) {
+        self.push(HEX[(byte >> 4) as usize]);
+        self.push(HEX[(byte & 0xF) as usize]);
+    }
+
+    fn as_slice(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
+
+const HEX: &[u8; 16] = b"0123456789abcdef";
+
+fn hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// parses a big endian hex number (addresses and lengths)
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for &c in s {
+        value = (value << 4) | hex_digit(c)? as u64;
+    }
+    Some(value)
+}
+
+/// parses a little endian hex value `bytes` bytes long (registers)
+fn parse_hex_le(s: &[u8], bytes: usize) -> Option<u64> {
+    if s.len() < bytes * 2 {
+        return None;
+    }
+    let mut value: u64 = 0;
+    for i in 0..bytes {
+        let byte = (hex_digit(s[i * 2])? << 4) | hex_digit(s[i * 2 + 1])?;
+        value |= (byte as u64) << (i * 8);
+    }
+    Some(value)
+}
+
+/// Waits for a packet (`$data#checksum`) from gdb and acknowledges it
+fn receive_packet(packet: &mut Packet) {
+    loop {
+        while receive() != b'$' {} // skip acks and anything else until a packet starts
+
+        packet.len = 0;
+        let mut checksum: u8 = 0;
+        let mut byte = receive();
+        while byte != b'#' {
+            packet.push(byte);
+            checksum = checksum.wrapping_add(byte);
+            byte = receive();
+        }
+
+        let expected = (hex_digit(receive()).unwrap_or(0) << 4) | hex_digit(receive()).unwrap_or(0);
+        if expected == checksum {
+            send(b'+');
+            return;
+        }
+        send(b'-'); // ask gdb to resend
+    }
+}
+
+/// Sends a packet to gdb, resending until it is acknowledged
+fn send_packet(data: &[u8]) {
+    let checksum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    loop {
+        send(b'$');
+        for &b in data {
+            send(b);
+        }
+        send(b'#');
+        send(HEX[(checksum >> 4) as usize]);
+        send(HEX[(checksum & 0xF) as usize]);
+
+        match receive() {
+            b'+' => return,
+            _ => continue,
+        }
+    }
+}
+
+fn send_stop_reply(signal: u8) {
+    let mut reply = Packet::new();
+    reply.push(b'S');
+    reply.push_hex_byte(signal);
+    send_packet(reply.as_slice());
+}
+
+// ================= REGISTERS
+
+// gdb's amd64 register numbers: rax, rbx, rcx, rdx, rsi, rdi, rbp, rsp, r8-r15 (64 bit),
+// then rip (64 bit), eflags, cs, ss, ds, es, fs, gs (32 bit)
+const REGISTER_COUNT: usize = 24;
+
+fn register_size(n: usize) -> usize {
+    if n <= 16 { 8 } else { 4 }
+}
+
+fn read_register(frame: &TrapFrame, n: usize) -> u64 {
+    match n {
+        0 => frame.rax,
+        1 => frame.rbx,
+        2 => frame.rcx,
+        3 => frame.rdx,
+        4 => frame.rsi,
+        5 => frame.rdi,
+        6 => frame.rbp,
+        7 => frame.rsp,
+        8 => frame.r8,
+        9 => frame.r9,
+        10 => frame.r10,
+        11 => frame.r11,
+        12 => frame.r12,
+        13 => frame.r13,
+        14 => frame.r14,
+        15 => frame.r15,
+        16 => frame.rip,
+        17 => frame.rflags,
+        18 => frame.cs,
+        19 => frame.ss,
+        _ => 0, // data segments are unused in long mode
+    }
+}
+
+/// returns false if the register can't be written
+fn write_register(frame: &mut TrapFrame, n: usize, value: u64) -> bool {
+    let reg = match n {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        _ => return false, // rsp and the segments belong to the interrupt frame and must not change
+    };
+    *reg = value;
+    true
+}
+
+// ================= COMMANDS
+
+/// returns true if every page of `len` bytes at `addr` is mapped, and writable if `write`
+/// (code can't be written with M, breakpoints are set with Z)
+fn accessible(addr: u64, len: u64, write: bool) -> bool {
+    let end = match addr.checked_add(len.max(1) - 1) {
+        Some(end) => end,
+        None => return false,
+    };
+    let mut page = addr & !0xFFF;
+    loop {
+        match memory::page_flags(page) {
+            Some(flags) if !write || flags.contains(PageTableFlags::WRITABLE) => {}
+            _ => return false,
+        }
+        match page.checked_add(0x1000) {
+            Some(next) if next <= end => page = next,
+            _ => return true,
+        }
+    }
+}
+
+/// splits `s` at the first `sep`
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let pos = s.iter().position(|&b| b == sep)?;
+    Some((&s[..pos], &s[pos + 1..]))
+}
+
+/// Handles packets from gdb until it asks the kernel to resume
+fn command_loop(frame: &mut TrapFrame) {
+    let mut packet = Packet::new();
+    let mut reply = Packet::new();
+
+    loop {
+        receive_packet(&mut packet);
+        reply.len = 0;
+
+        let data = packet.as_slice();
+        let (cmd, args) = match data.split_first() {
+            Some((cmd, args)) => (*cmd, args),
+            None => continue,
+        };
+
+        match cmd {
+            b'?' => {
+                reply.push(b'S');
+                reply.push_hex_byte(STATE.lock().signal);
+            }
+            b'g' => {
+                for n in 0..REGISTER_COUNT {
+                    reply.push_hex_le(read_register(frame, n), register_size(n));
+                }
+            }
+            b'G' => {
+                let mut pos = 0;
+                for n in 0..REGISTER_COUNT {
+                    let size = register_size(n);
+                    if let Some(value) = parse_hex_le(&args[pos.min(args.len())..], size) {
+                        write_register(frame, n, value);
+                    }
+                    pos += size * 2;
+                }
+                reply.push_str("OK");
+            }
+            b'p' => {
+                match parse_hex(args) {
+                    Some(n) if (n as usize) < REGISTER_COUNT => {
+                        let n = n as usize;
+                        reply.push_hex_le(read_register(frame, n), register_size(n));
+                    }
+                    _ => reply.push_str("E01"),
+                }
+            }
+            b'P' => {
+                let written = split(args, b'=').and_then(|(n, value)| {
+                    let n = parse_hex(n)? as usize;
+                    let value = parse_hex_le(value, register_size(n))?;
+                    Some(write_register(frame, n, value))
+                });
+                match written {
+                    Some(true) => reply.push_str("OK"),
+                    _ => reply.push_str("E01"),
+                }
+            }
+            b'm' => {
+                match split(args, b',').and_then(|(a, l)| Some((parse_hex(a)?, parse_hex(l)?))) {
+                    Some((addr, len)) if accessible(addr, len.min((PACKET_SIZE / 2) as u64), false) => {
+                        let state = STATE.lock();
+                        for i in 0..len.min((PACKET_SIZE / 2) as u64) {
+                            let a = addr + i;
+                            // show the original code instead of the int3 bytes of breakpoints
+                            let byte = state.original_byte(a)
+                                .unwrap_or_else(|| unsafe { core::ptr::read_volatile(a as *const u8) });
+                            reply.push_hex_byte(byte);
+                        }
+                    }
+                    _ => reply.push_str("E14"), // refuse unmapped memory instead of faulting
+                }
+            }
+            b'M' => {
+                let parsed = split(args, b':').and_then(|(header, bytes)| {
+                    let (a, l) = split(header, b',')?;
+                    Some((parse_hex(a)?, parse_hex(l)?.min((PACKET_SIZE / 2) as u64), bytes))
+                });
+                let fits = |bytes: &[u8], len: u64| len.checked_mul(2).map_or(false, |n| bytes.len() as u64 >= n);
+                match parsed {
+                    Some((addr, len, bytes)) if fits(bytes, len) && accessible(addr, len, true) => {
+                        for i in 0..len as usize {
+                            let byte = parse_hex_le(&bytes[i * 2..], 1).unwrap_or(0) as u8;
+                            unsafe { core::ptr::write_volatile((addr + i as u64) as *mut u8, byte) };
+                        }
+                        reply.push_str("OK");
+                    }
+                    _ => reply.push_str("E14"),
+                }
+            }
+            b'Z' | b'z' => {
+                // only software breakpoints (type 0) are supported
+                let addr = match args.split_first() {
+                    Some((b'0', rest)) if rest.first() == Some(&b',') => {
+                        split(&rest[1..], b',').and_then(|(a, _)| parse_hex(a))
+                    }
+                    _ => None,
+                };
+                match addr {
+                    Some(addr) => {
+                        let mut state = STATE.lock();
+                        let ok = if cmd == b'Z' { state.insert(addr) } else { state.remove(addr) };
+                        reply.push_str(if ok { "OK" } else { "E01" });
+                    }
+                    None => {} // empty reply, unsupported
+                }
+            }
+            b'c' | b's' => {
+                if let Some(addr) = parse_hex(args) {
+                    frame.rip = addr;
+                }
+                resume(frame, cmd == b's');
+                return;
+            }
+            b'D' | b'k' => {
+                // detach: remove all breakpoints and let the kernel run freely
+                let mut state = STATE.lock();
+                for slot in 0..MAX_BREAKPOINTS {
+                    if let Some(bp) = state.breakpoints[slot] {
+                        state.remove(bp.addr);
+                    }
+                }
+                drop(state);
+                if cmd == b'D' {
+                    send_packet(b"OK");
+                }
+                frame.rflags &= !RFLAGS_TF;
+                return;
+            }
+            b'H' => {
+                reply.push_str("OK"); // there is only one thread
+            }
+            b'q' => {
+                if args.starts_with(b"Supported") {
+                    reply.push_str("PacketSize=400"); // PACKET_SIZE in hex
+                } else if args.starts_with(b"Attached") {
+                    reply.push_str("1");
+                } else if args.starts_with(b"C") {
+                    reply.push_str("QC1");
+                }
+            }
+            _ => {} // empty reply, unsupported
+        }
+
+        send_packet(reply.as_slice());
+    }
+}
+
+/// Sets up the frame to continue or single step when the handler returns
+fn resume(frame: &mut TrapFrame, step: bool) {
+    let mut state = STATE.lock();
+
+    // continuing from a breakpoint: remove it, step over it, then put it back in handle_trap
+    if state.find(frame.rip).is_some() {
+        let original = state.original_byte(frame.rip).unwrap();
+        unsafe { write_code_byte(frame.rip, original) };
+        state.stepping_over = Some(frame.rip);
+        state.continue_after_step = !step;
+        frame.rflags |= RFLAGS_TF;
+        return;
+    }
+
+    if step {
+        frame.rflags |= RFLAGS_TF;
+    } else {
+        frame.rflags &= !RFLAGS_TF;
+    }
+}
@@ -0,0 +1,160 @@
+// Graphics modes on QEMU's std-VGA (and Bochs)
+//
+// Mode 13h is set through the standard VGA registers. Higher resolutions go through the Bochs
+// VBE dispi interface, with a linear framebuffer at the address in BAR0 of the PCI display
+// device, mapped into the device memory region the first time. Either way drawing is done on a
+// Framebuffer, which shows its back buffer with present().
+//
+// The text mode (registers, palette and font) is saved when the first graphics mode is set, and
+// the consoles stop drawing until text_mode() puts it back (they still take output meanwhile),
+// unless vga_textmode::use_framebuffer(...) moves them onto the framebuffer.
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::memory::{self, phys_to_virt};
+use crate::vga_textmode;
+
+pub use self::framebuffer::{rgb, Framebuffer, PixelFormat, Rgb};
+
+mod bochs;
+mod framebuffer;
+mod vga;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphicsError {
+    NoDevice, // no Bochs compatible display
+    UnsupportedMode, // the display is too small or refused the mode
+    OutOfMemory, // the heap has no room for the back buffer
+    MapFailed, // the linear framebuffer couldn't be mapped
+}
+
+/// bits per pixel of the Bochs modes
+const BPP: u16 = 32;
+
+const MODE_13H_WIDTH: usize = 320;
+const MODE_13H_HEIGHT: usize = 200;
+const MODE_13H_ADDRESS: u64 = 0xA0000;
+
+// counts the mode switches, a framebuffer only presents in the mode it was made for
+static MODE: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    // the text mode while a graphics mode is set
+    static ref SAVED: Mutex<Option<vga::TextState>> = Mutex::new(None);
+    // the linear framebuffer as (physical address, mapped bytes, mapped at)
+    static ref LFB: Mutex<Option<(u64, u64, VirtAddr)>> = Mutex::new(None);
+}
+
+/// returns true if a graphics mode is set
+pub fn is_active() -> bool {
+    SAVED.lock().is_some()
+}
+
+/// Takes the screen from the consoles, saving the text mode if it is still set
+fn leave_text_mode() {
+    vga_textmode::suspend_drawing(); // the console may be on a framebuffer of the last mode
+    let mut saved = SAVED.lock();
+    if saved.is_none() {
+        *saved = Some(vga::TextState::save());
+    }
+}
+
+/// returns the largest (width, height) a mode can have, None without a Bochs compatible display
+pub fn max_resolution() -> Option<(usize, usize)> {
+    bochs::max_mode().map(|(width, height, _)| (width as usize, height as usize))
+}
+
+/// Maps the linear framebuffer, big enough for the largest mode so it is only mapped once
+/// returns where it is mapped
+fn map_framebuffer(address: u64, size: u64) -> Result<*mut u8, GraphicsError> {
+    let mut lfb = LFB.lock();
+    if let Some((mapped, mapped_size, virt)) = *lfb {
+        if mapped == address && mapped_size >= size {
+            return Ok(virt.as_mut_ptr());
+        }
+    }
+    let size = bochs::max_mode()
+        .map(|(width, height, bpp)| width as u64 * height as u64 * (bpp as u64 / 8))
+        .unwrap_or(0)
+        .max(size);
+    let virt = memory::map_device_memory(PhysAddr::new(address), size)
+        .map_err(|_| GraphicsError::MapFailed)?;
+    *lfb = Some((address, size, virt));
+    Ok(virt.as_mut_ptr())
+}
+
+/// Switches to a 32 bit color mode through the Bochs VBE interface
+pub fn set_mode(width: usize, height: usize) -> Result<Framebuffer, GraphicsError> {
+    if bochs::version().is_none() {
+        return Err(GraphicsError::NoDevice);
+    }
+    if let Some((max_width, max_height, max_bpp)) = bochs::max_mode() {
+        if width > max_width as usize || height > max_height as usize || BPP > max_bpp {
+            return Err(GraphicsError::UnsupportedMode);
+        }
+    }
+    if width == 0 || height == 0 || width > u16::max_value() as usize
+        || height > u16::max_value() as usize {
+        return Err(GraphicsError::UnsupportedMode);
+    }
+
+    let front = map_framebuffer(bochs::framebuffer_address(), (width * height * 4) as u64)?;
+    interrupts::without_interrupts(|| {
+        leave_text_mode(); // frees the framebuffer console's back buffer, if it had one
+        if !bochs::set_mode(width as u16, height as u16, BPP) {
+            text_mode();
+            return Err(GraphicsError::UnsupportedMode);
+        }
+        let mode = MODE.fetch_add(1, Ordering::SeqCst) + 1;
+        let screen = unsafe { Framebuffer::new(front, mode, width, height, width * 4, PixelFormat::Bgrx32) };
+        if screen.is_err() {
+            text_mode();
+        }
+        screen
+    })
+}
+
+/// Switches to mode 13h, 320x200 with colors rounded to a 3-3-2 palette
+pub fn set_mode_13h() -> Result<Framebuffer, GraphicsError> {
+    interrupts::without_interrupts(|| {
+        leave_text_mode();
+        bochs::disable(); // in case a Bochs mode is set
+        vga::set_mode_13h();
+        vga::write_palette(&framebuffer::palette());
+        // the legacy VGA window is in the first megabyte, which the bootloader always maps
+        let front = phys_to_virt(PhysAddr::new(MODE_13H_ADDRESS)).as_mut_ptr();
+        let mode = MODE.fetch_add(1, Ordering::SeqCst) + 1;
+        let screen = unsafe {
+            Framebuffer::new(front, mode, MODE_13H_WIDTH, MODE_13H_HEIGHT, MODE_13H_WIDTH,
+                             PixelFormat::Indexed8)
+        };
+        if screen.is_err() {
+            text_mode();
+        }
+        screen
+    })
+}
+
+/// returns the mode set last, to check a framebuffer is still on the screen
+fn current_mode() -> usize {
+    MODE.load(Ordering::SeqCst)
+}
+
+/// Goes back to the text mode and redraws the active console
+/// (framebuffers of the graphics mode stop presenting)
+pub fn text_mode() {
+    interrupts::without_interrupts(|| {
+        let saved = SAVED.lock().take();
+        if let Some(state) = saved {
+            MODE.fetch_add(1, Ordering::SeqCst);
+            bochs::disable();
+            state.restore();
+            vga_textmode::resume_drawing();
+        }
+    });
+}
@@ -0,0 +1,97 @@
+// The Bochs VBE "dispi" interface of QEMU's std-VGA (and Bochs)
+//
+// Modes are set by writing the resolution and depth to the dispi registers, after which the
+// whole screen is a linear framebuffer at the address in BAR0 of the PCI display device.
+
+use x86_64::instructions::port::Port;
+
+use crate::pci;
+
+const INDEX_PORT: u16 = 0x1CE;
+const DATA_PORT: u16 = 0x1CF;
+
+// registers
+const ID: u16 = 0;
+const XRES: u16 = 1;
+const YRES: u16 = 2;
+const BPP: u16 = 3;
+const ENABLE: u16 = 4;
+const VIRT_WIDTH: u16 = 6;
+const X_OFFSET: u16 = 8;
+const Y_OFFSET: u16 = 9;
+
+// ENABLE flags
+const ENABLED: u16 = 0x01;
+const GET_CAPS: u16 = 0x02; // XRES/YRES/BPP read back the largest values supported
+const LFB_ENABLED: u16 = 0x40;
+
+/// the oldest interface version with GET_CAPS
+const ID_GET_CAPS: u16 = 0xB0C2;
+const ID_LATEST: u16 = 0xB0C5;
+
+const PCI_VENDOR: u16 = 0x1234;
+const PCI_DEVICE: u16 = 0x1111;
+
+/// where the framebuffer is on old ISA cards without a PCI BAR
+const ISA_FRAMEBUFFER: u64 = 0xE000_0000;
+
+fn read(register: u16) -> u16 {
+    unsafe {
+        Port::<u16>::new(INDEX_PORT).write(register);
+        Port::<u16>::new(DATA_PORT).read()
+    }
+}
+
+fn write(register: u16, value: u16) {
+    unsafe {
+        Port::<u16>::new(INDEX_PORT).write(register);
+        Port::<u16>::new(DATA_PORT).write(value);
+    }
+}
+
+/// returns the version of the interface, None if there is no Bochs compatible display
+pub fn version() -> Option<u16> {
+    write(ID, ID_LATEST); // asking for the latest version returns the best one supported
+    match read(ID) {
+        id @ 0xB0C0..=0xB0C5 => Some(id),
+        _ => None,
+    }
+}
+
+/// returns the largest (width, height, bits per pixel) the display supports
+pub fn max_mode() -> Option<(u16, u16, u16)> {
+    if version()? < ID_GET_CAPS {
+        return None;
+    }
+    let enable = read(ENABLE);
+    write(ENABLE, GET_CAPS);
+    let caps = (read(XRES), read(YRES), read(BPP));
+    write(ENABLE, enable);
+    Some(caps)
+}
+
+/// returns the physical address of the linear framebuffer
+pub fn framebuffer_address() -> u64 {
+    pci::find_device(PCI_VENDOR, PCI_DEVICE)
+        .and_then(|device| device.memory_bar(0))
+        .unwrap_or(ISA_FRAMEBUFFER)
+}
+
+/// Switches to a mode with a linear framebuffer
+/// returns false if the display didn't accept the mode
+pub fn set_mode(width: u16, height: u16, bpp: u16) -> bool {
+    write(ENABLE, 0); // the mode registers can only change while disabled
+    write(XRES, width);
+    write(YRES, height);
+    write(BPP, bpp);
+    write(VIRT_WIDTH, width);
+    write(X_OFFSET, 0);
+    write(Y_OFFSET, 0);
+    write(ENABLE, ENABLED | LFB_ENABLED);
+    read(XRES) == width && read(YRES) == height && read(BPP) == bpp
+}
+
+/// Goes back to the standard VGA modes
+pub fn disable() {
+    write(ENABLE, 0);
+}
@@ -0,0 +1,259 @@
+use alloc::alloc::{alloc_zeroed, Layout};
+use alloc::vec::Vec;
+use core::ptr;
+
+use super::GraphicsError;
+
+/// A color as 0x00RRGGBB
+pub type Rgb = u32;
+
+/// returns the color with red, green and blue components
+pub const fn rgb(r: u8, g: u8, b: u8) -> Rgb {
+    (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+/// How pixels are stored in video memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    Indexed8, // a byte per pixel, an index into the 3-3-2 palette (mode 13h)
+    Bgrx32, // 4 bytes per pixel: blue, green, red, unused
+}
+
+/// A screen in a graphics mode
+///
+/// Everything is drawn into a back buffer in memory, and only shown once present() copies it
+/// to video memory, so a frame is never seen half drawn. Coordinates outside the screen are
+/// clipped, so shapes can be partly off screen.
+pub struct Framebuffer {
+    front: *mut u8, // video memory
+    mode: usize, // the mode switch that made it
+    back: Vec<Rgb>,
+    width: usize,
+    height: usize,
+    pitch: usize, // the bytes between the start of two lines in video memory
+    format: PixelFormat,
+}
+
+// the video memory is only written through present(), which takes &mut self and checks the mode
+unsafe impl Send for Framebuffer {}
+
+impl Framebuffer {
+    /// Unsafe because `front` must point to `height` lines of `pitch` bytes of video memory
+    /// returns OutOfMemory if the heap has no room for the back buffer
+    pub(super) unsafe fn new(front: *mut u8, mode: usize, width: usize, height: usize,
+                             pitch: usize, format: PixelFormat) -> Result<Framebuffer, GraphicsError> {
+        Ok(Framebuffer {
+            front,
+            mode,
+            back: back_buffer(width * height).ok_or(GraphicsError::OutOfMemory)?,
+            width,
+            height,
+            pitch,
+            format,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn clear(&mut self, color: Rgb) {
+        for pixel in self.back.iter_mut() {
+            *pixel = color;
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Rgb) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            self.back[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    /// returns the color of a pixel in the back buffer, None if it is off screen
+    pub fn pixel(&self, x: i32, y: i32) -> Option<Rgb> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height {
+            Some(self.back[y as usize * self.width + x as usize])
+        } else {
+            None
+        }
+    }
+
+    /// returns the part of a rectangle that is on screen as (x, y, width, height)
+    fn clip(&self, x: i32, y: i32, width: usize, height: usize) -> Option<(usize, usize, usize, usize)> {
+        let left = x.max(0) as i64;
+        let top = y.max(0) as i64;
+        let right = (x as i64 + width as i64).min(self.width as i64);
+        let bottom = (y as i64 + height as i64).min(self.height as i64);
+        if left >= right || top >= bottom {
+            return None;
+        }
+        Some((left as usize, top as usize, (right - left) as usize, (bottom - top) as usize))
+    }
+
+    pub fn fill_rect(&mut self, x: i32, y: i32, width: usize, height: usize, color: Rgb) {
+        if let Some((x, y, width, height)) = self.clip(x, y, width, height) {
+            for row in y..y + height {
+                let start = row * self.width + x;
+                for pixel in self.back[start..start + width].iter_mut() {
+                    *pixel = color;
+                }
+            }
+        }
+    }
+
+    /// Draws the outline of a rectangle, a pixel wide
+    pub fn draw_rect(&mut self, x: i32, y: i32, width: usize, height: usize, color: Rgb) {
+        if width == 0 || height == 0 {
+            return;
+        }
+        self.fill_rect(x, y, width, 1, color);
+        self.fill_rect(x, y + height as i32 - 1, width, 1, color);
+        self.fill_rect(x, y, 1, height, color);
+        self.fill_rect(x + width as i32 - 1, y, 1, height, color);
+    }
+
+    /// Draws a line between two points, both included (Bresenham's algorithm)
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Rgb) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut error = dx + dy;
+        loop {
+            self.set_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let error2 = 2 * error;
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    /// Copies an image of `width` pixels per line onto the screen, with its top left at (x, y)
+    pub fn blit(&mut self, x: i32, y: i32, width: usize, pixels: &[Rgb]) {
+        if width == 0 {
+            return;
+        }
+        let height = pixels.len() / width;
+        if let Some((left, top, visible_width, visible_height)) = self.clip(x, y, width, height) {
+            // where the visible part starts in the image
+            let skip_x = (left as i64 - x as i64) as usize;
+            let skip_y = (top as i64 - y as i64) as usize;
+            for row in 0..visible_height {
+                let source = (skip_y + row) * width + skip_x;
+                let dest = (top + row) * self.width + left;
+                self.back[dest..dest + visible_width]
+                    .copy_from_slice(&pixels[source..source + visible_width]);
+            }
+        }
+    }
+
+    /// Draws a 1 bit per pixel image, such as a glyph, in two colors
+    /// (`bits` has `height` lines of `width` bits, each line padded to a whole byte)
+    pub fn draw_bitmap(&mut self, x: i32, y: i32, width: usize, height: usize, bits: &[u8],
+                       fg: Rgb, bg: Rgb) {
+        let line_bytes = (width + 7) / 8;
+        let height = height.min(bits.len() / line_bytes.max(1));
+        if let Some((left, top, visible_width, visible_height)) = self.clip(x, y, width, height) {
+            let skip_x = (left as i64 - x as i64) as usize;
+            let skip_y = (top as i64 - y as i64) as usize;
+            for row in 0..visible_height {
+                let line = &bits[(skip_y + row) * line_bytes..];
+                let dest = (top + row) * self.width + left;
+                for (i, pixel) in self.back[dest..dest + visible_width].iter_mut().enumerate() {
+                    let bit = skip_x + i;
+                    *pixel = if line[bit / 8] & (0x80 >> (bit % 8)) != 0 { fg } else { bg };
+                }
+            }
+        }
+    }
+
+    /// Copies `count` lines of pixels starting at line `from` to line `to`, to scroll
+    pub fn copy_lines(&mut self, from: usize, to: usize, count: usize) {
+        let count = count.min(self.height.saturating_sub(from.max(to)));
+        self.back.copy_within(from * self.width..(from + count) * self.width, to * self.width);
+    }
+
+    /// Shows the back buffer on the screen
+    /// returns false if the screen has switched to another mode since
+    pub fn present(&mut self) -> bool {
+        self.present_lines(0, self.height)
+    }
+
+    /// Shows `count` lines of the back buffer starting at line `top`, when only they changed
+    pub fn present_lines(&mut self, top: usize, count: usize) -> bool {
+        if self.mode != super::current_mode() {
+            return false;
+        }
+        for row in top.min(self.height)..(top + count).min(self.height) {
+            let line = &self.back[row * self.width..(row + 1) * self.width];
+            let dest = unsafe { self.front.add(row * self.pitch) };
+            match self.format {
+                PixelFormat::Bgrx32 => unsafe {
+                    // 0x00RRGGBB in little endian is already blue, green, red, unused
+                    ptr::copy_nonoverlapping(line.as_ptr() as *const u8, dest, self.width * 4);
+                },
+                PixelFormat::Indexed8 => {
+                    for (x, color) in line.iter().enumerate() {
+                        unsafe { ptr::write_volatile(dest.add(x), palette_index(*color)) };
+                    }
+                }
+            }
+        }
+        true
+    }
+}
+
+/// returns the index of the closest color in the 3-3-2 palette
+fn palette_index(color: Rgb) -> u8 {
+    let r = (color >> 16) as u8;
+    let g = (color >> 8) as u8;
+    let b = color as u8;
+    (r & 0xE0) | (g & 0xE0) >> 3 | b >> 6
+}
+
+/// returns the DAC palette (6 bit red, green, blue) that makes byte rrrgggbb show that color
+pub(super) fn palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(256 * 3);
+    for index in 0..=255u8 {
+        let r = index >> 5;
+        let g = (index >> 2) & 0x7;
+        let b = index & 0x3;
+        palette.push(r * 63 / 7);
+        palette.push(g * 63 / 7);
+        palette.push(b * 63 / 3);
+    }
+    palette
+}
+
+/// Allocates a black back buffer, None if the heap has no room for it (rather than the allocation
+/// failure taking the kernel down, big modes need most of the heap)
+fn back_buffer(pixels: usize) -> Option<Vec<Rgb>> {
+    let layout = Layout::array::<Rgb>(pixels).ok()?;
+    if layout.size() == 0 {
+        return Some(Vec::new());
+    }
+    unsafe {
+        let buffer = alloc_zeroed(layout) as *mut Rgb;
+        if buffer.is_null() {
+            return None;
+        }
+        Some(Vec::from_raw_parts(buffer, pixels, pixels))
+    }
+}
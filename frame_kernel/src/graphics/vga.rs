@@ -0,0 +1,187 @@
+// The standard VGA registers, to switch into mode 13h and back to text mode
+//
+// Rather than keeping a register table for the text mode, the whole state (registers, palette
+// and font) is saved before leaving it, so whatever the text mode was (9 dot clock, blink...)
+// comes back as it was.
+
+use alloc::vec::Vec;
+
+use x86_64::instructions::port::Port;
+
+use crate::vga_textmode;
+
+const MISC_READ: u16 = 0x3CC;
+const MISC_WRITE: u16 = 0x3C2;
+const SEQUENCER: u16 = 0x3C4;
+const CRTC: u16 = 0x3D4;
+const GRAPHICS: u16 = 0x3CE;
+const ATTRIBUTE: u16 = 0x3C0;
+const INPUT_STATUS: u16 = 0x3DA;
+const DAC_READ_INDEX: u16 = 0x3C7;
+const DAC_WRITE_INDEX: u16 = 0x3C8;
+const DAC_DATA: u16 = 0x3C9;
+
+const SEQUENCER_COUNT: usize = 5;
+const CRTC_COUNT: usize = 25;
+const GRAPHICS_COUNT: usize = 9;
+const ATTRIBUTE_COUNT: usize = 21;
+
+/// The registers of a mode, in the order they are programmed
+struct Registers {
+    misc: u8,
+    sequencer: [u8; SEQUENCER_COUNT],
+    crtc: [u8; CRTC_COUNT],
+    graphics: [u8; GRAPHICS_COUNT],
+    attribute: [u8; ATTRIBUTE_COUNT],
+}
+
+/// 320x200 with 256 colors, a byte per pixel at 0xA0000
+const MODE_13H: Registers = Registers {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00,
+           0x00, 0x00, 0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C,
+                0x0D, 0x0E, 0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00],
+};
+
+fn read_indexed(port: u16, index: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(port).write(index);
+        Port::<u8>::new(port + 1).read()
+    }
+}
+
+fn write_indexed(port: u16, index: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(port).write(index);
+        Port::<u8>::new(port + 1).write(value);
+    }
+}
+
+fn read_attribute(index: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(INPUT_STATUS).read(); // the attribute controller now expects an index
+        Port::<u8>::new(ATTRIBUTE).write(index);
+        Port::<u8>::new(ATTRIBUTE + 1).read()
+    }
+}
+
+fn write_attribute(index: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(INPUT_STATUS).read();
+        Port::<u8>::new(ATTRIBUTE).write(index);
+        Port::<u8>::new(ATTRIBUTE).write(value);
+    }
+}
+
+fn read_registers() -> Registers {
+    let mut registers = Registers {
+        misc: unsafe { Port::<u8>::new(MISC_READ).read() },
+        sequencer: [0; SEQUENCER_COUNT],
+        crtc: [0; CRTC_COUNT],
+        graphics: [0; GRAPHICS_COUNT],
+        attribute: [0; ATTRIBUTE_COUNT],
+    };
+    for (i, value) in registers.sequencer.iter_mut().enumerate() {
+        *value = read_indexed(SEQUENCER, i as u8);
+    }
+    for (i, value) in registers.crtc.iter_mut().enumerate() {
+        *value = read_indexed(CRTC, i as u8);
+    }
+    for (i, value) in registers.graphics.iter_mut().enumerate() {
+        *value = read_indexed(GRAPHICS, i as u8);
+    }
+    for (i, value) in registers.attribute.iter_mut().enumerate() {
+        *value = read_attribute(i as u8);
+    }
+    unsafe {
+        Port::<u8>::new(INPUT_STATUS).read();
+        Port::<u8>::new(ATTRIBUTE).write(0x20); // selecting them blanked the screen, turn it back on
+    }
+    registers
+}
+
+fn write_registers(registers: &Registers) {
+    unsafe { Port::<u8>::new(MISC_WRITE).write(registers.misc) };
+    for (i, value) in registers.sequencer.iter().enumerate() {
+        write_indexed(SEQUENCER, i as u8, *value);
+    }
+
+    // the first CRTC registers are write protected by bit 7 of register 0x11
+    write_indexed(CRTC, 0x03, read_indexed(CRTC, 0x03) | 0x80);
+    write_indexed(CRTC, 0x11, read_indexed(CRTC, 0x11) & !0x80);
+    for (i, value) in registers.crtc.iter().enumerate() {
+        let value = match i {
+            0x03 => value | 0x80,
+            0x11 => value & !0x80,
+            _ => *value,
+        };
+        write_indexed(CRTC, i as u8, value);
+    }
+    write_indexed(CRTC, 0x11, registers.crtc[0x11]); // protect them again if they were
+
+    for (i, value) in registers.graphics.iter().enumerate() {
+        write_indexed(GRAPHICS, i as u8, *value);
+    }
+    for (i, value) in registers.attribute.iter().enumerate() {
+        write_attribute(i as u8, *value);
+    }
+    unsafe {
+        Port::<u8>::new(INPUT_STATUS).read();
+        Port::<u8>::new(ATTRIBUTE).write(0x20); // turn the screen back on
+    }
+}
+
+fn read_palette() -> Vec<u8> {
+    let mut palette = Vec::with_capacity(256 * 3);
+    unsafe {
+        Port::<u8>::new(DAC_READ_INDEX).write(0);
+        for _ in 0..256 * 3 {
+            palette.push(Port::<u8>::new(DAC_DATA).read());
+        }
+    }
+    palette
+}
+
+/// Sets the DAC palette from (red, green, blue) triples of 6 bit values
+pub fn write_palette(palette: &[u8]) {
+    unsafe {
+        Port::<u8>::new(DAC_WRITE_INDEX).write(0);
+        for value in palette.iter().take(256 * 3) {
+            Port::<u8>::new(DAC_DATA).write(*value);
+        }
+    }
+}
+
+/// The text mode, saved when leaving it
+pub struct TextState {
+    registers: Registers,
+    palette: Vec<u8>,
+    font: Vec<u8>,
+}
+
+impl TextState {
+    /// Saves the text mode, must be called while it is still set
+    pub fn save() -> TextState {
+        TextState {
+            registers: read_registers(),
+            palette: read_palette(),
+            font: vga_textmode::read_glyphs(vga_textmode::GLYPH_STRIDE),
+        }
+    }
+
+    /// Goes back to the text mode, the screen is blank until a console draws on it
+    pub fn restore(&self) {
+        write_registers(&self.registers);
+        write_palette(&self.palette);
+        // graphics modes draw over the font
+        vga_textmode::restore_glyphs(&self.font, vga_textmode::GLYPH_STRIDE).ok();
+    }
+}
+
+/// Switches to mode 13h, the palette has to be set afterwards
+pub fn set_mode_13h() {
+    write_registers(&MODE_13H);
+}
@@ -1,9 +1,9 @@
 use lazy_static::lazy_static;
 use pic8259_simple::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::idt::{HandlerFunc, InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-use crate::{gdt, hlt_loop, println};
+use crate::{backtrace, gdb, gdt, hlt_loop, println, serial_println, time, watchdog};
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -13,6 +13,8 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Com2 = PIC_1_OFFSET + 3,
+    Mouse = PIC_2_OFFSET + 4,
 }
 
 impl InterruptIndex {
@@ -31,7 +33,12 @@ pub static PICS: spin::Mutex<ChainedPics> =
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        unsafe {
+            // these need every register of the interrupted code (for gdb), so they use trap_entry stubs
+            idt.breakpoint.set_handler_fn(trap_handler(breakpoint_entry));
+            idt.debug.set_handler_fn(trap_handler(debug_entry));
+            idt[InterruptIndex::Com2.as_usize()].set_handler_fn(trap_handler(com2_entry));
+        }
         idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
             idt.double_fault
@@ -40,6 +47,7 @@ lazy_static! {
         }
         idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
         idt
     };
 }
@@ -48,8 +56,112 @@ pub fn init_idt() {
     IDT.load();
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: &mut InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+// ================= TRAP ENTRY STUBS
+
+/// The full register state of interrupted code, saved by the trap_entry stubs
+/// Changes made to it are restored when the handler returns
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rdx: u64,
+    pub rsi: u64,
+    pub rdi: u64,
+    pub vector: u64, // the interrupt vector, pushed by the stub
+    // pushed by the cpu
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+/// Creates an entry stub for an interrupt without an error code
+/// The stub saves all registers as a TrapFrame and calls `$handler(&mut TrapFrame)`
+macro_rules! trap_entry {
+    ($entry:ident, $handler:ident, $vector:expr) => {
+        global_asm!(concat!(
+            ".global ", stringify!($entry), "\n",
+            stringify!($entry), ":\n",
+            "    push ", stringify!($vector), "\n",
+            "    push rdi\n    push rsi\n    push rdx\n    push rcx\n    push rax\n",
+            "    push r8\n    push r9\n    push r10\n    push r11\n    push rbx\n",
+            "    push rbp\n    push r12\n    push r13\n    push r14\n    push r15\n",
+            "    mov rdi, rsp\n",
+            "    sub rsp, 8\n", // align the stack to 16 bytes for the call
+            "    cld\n",
+            "    call ", stringify!($handler), "\n",
+            "    add rsp, 8\n",
+            "    pop r15\n    pop r14\n    pop r13\n    pop r12\n    pop rbp\n",
+            "    pop rbx\n    pop r11\n    pop r10\n    pop r9\n    pop r8\n",
+            "    pop rax\n    pop rcx\n    pop rdx\n    pop rsi\n    pop rdi\n",
+            "    add rsp, 8\n", // the vector
+            "    iretq\n",
+        ));
+    };
+}
+
+trap_entry!(debug_entry, debug_handler, 1);
+trap_entry!(breakpoint_entry, breakpoint_handler, 3);
+trap_entry!(com2_entry, com2_interrupt_handler, 35); // PIC_1_OFFSET + 3
+
+extern "C" {
+    fn debug_entry();
+    fn breakpoint_entry();
+    fn com2_entry();
+}
+
+/// Lets a trap_entry stub be used as an IDT handler
+///
+/// Unsafe because `entry` must be a stub created by trap_entry!(...)
+unsafe fn trap_handler(entry: unsafe extern "C" fn()) -> HandlerFunc {
+    core::mem::transmute(entry)
+}
+
+// ================= HANDLERS
+
+#[no_mangle]
+extern "C" fn breakpoint_handler(frame: &mut TrapFrame) {
+    if gdb::is_enabled() {
+        gdb::handle_trap(frame, gdb::SIGTRAP);
+        return;
+    }
+    println!("EXCEPTION: BREAKPOINT\n{:#?}", frame);
+}
+
+#[no_mangle]
+extern "C" fn debug_handler(frame: &mut TrapFrame) {
+    if gdb::is_enabled() {
+        gdb::handle_trap(frame, gdb::SIGTRAP);
+        return;
+    }
+    println!("EXCEPTION: DEBUG\n{:#?}", frame);
+    frame.rflags &= !(1 << 8); // stop single stepping
+}
+
+#[no_mangle]
+extern "C" fn com2_interrupt_handler(frame: &mut TrapFrame) {
+    let interrupted = gdb::poll_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Com2.as_u8());
+    }
+
+    if interrupted && gdb::is_enabled() {
+        gdb::handle_trap(frame, gdb::SIGINT);
+    }
 }
 
 extern "x86-interrupt" fn page_fault_handler(
@@ -62,15 +174,25 @@ extern "x86-interrupt" fn page_fault_handler(
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
+    serial_println!("EXCEPTION: PAGE FAULT at {:?} ({:?})", Cr2::read(), error_code);
+    backtrace::print_exception_backtrace(stack_frame);
     hlt_loop();
 }
 
 extern "x86-interrupt" fn double_fault_handler(stack_frame: &mut InterruptStackFrame, _error_code: u64, ) -> ! {
-    panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    // not a panic, as the panic handler would print a backtrace of the double fault stack instead
+    println!("&4EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    serial_println!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
+    backtrace::print_exception_backtrace(stack_frame);
+    hlt_loop();
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+extern "x86-interrupt" fn timer_interrupt_handler(stack_frame: &mut InterruptStackFrame) {
     // print!("."); used as debug to show interrupts are working
+    time::tick();
+    crate::task::timer::on_tick(time::ticks());
+    watchdog::check(stack_frame);
+
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
@@ -80,12 +202,31 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: &mut InterruptSt
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: &mut InterruptStackFrame) { // TODO: Rework
     use x86_64::instructions::port::Port;
 
+    let _timer = watchdog::InterruptTimer::new("keyboard");
+
     let mut port = Port::new(0x60);
     let scancode: u8 = unsafe { port.read() };
-    crate::task::keyboard::add_scancode(scancode);
+    if !crate::ps2::keyboard_byte(scancode) { // responses to keyboard commands aren't scancodes
+        crate::task::keyboard::add_scancode(scancode);
+    }
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
 }
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: &mut InterruptStackFrame) {
+    use x86_64::instructions::port::Port;
+
+    let _timer = watchdog::InterruptTimer::new("mouse");
+
+    let mut port = Port::new(0x60);
+    let byte: u8 = unsafe { port.read() };
+    crate::task::mouse::add_byte(byte);
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
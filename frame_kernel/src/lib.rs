@@ -1,12 +1,15 @@
 #![no_std]
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 #![feature(abi_x86_interrupt)]
 #![feature(alloc_error_handler)]
 #![feature(const_fn)]
 #![feature(const_in_array_repeat_expressions)]
 #![feature(wake_trait)]
 #![feature(asm)]
+#![feature(global_asm)]
 #![feature(c_variadic)]
 
 extern crate alloc;
@@ -29,17 +32,34 @@ pub mod syscalls;
 pub mod logo_print;
 pub mod ram_file;
 pub mod command;
+pub mod backtrace;
+pub mod gdb;
+pub mod cpu;
+pub mod acpi;
+pub mod time;
+pub mod rtc;
+pub mod watchdog;
+pub mod ps2;
+pub mod pci;
+pub mod fw_cfg;
+pub mod graphics;
+pub mod ui;
 
 // ================= HEAP ALLOCATION
 
 pub const HEAP_START: usize = 0x_4444_4444_0000; // TODO: Handle this by not just setting it to a 'random' location
-pub const HEAP_SIZE: usize = 500 * 1024; // 500 KiB
+// 24 MiB: all six consoles together at MAX_SCROLLBACK take ~16 MiB (a line is 264 bytes), plus 768
+// bytes per colored line, the rest is for a framebuffer back buffer (set_mode fails with
+// OutOfMemory if it doesn't fit, and scrollback stops growing when the heap is full)
+pub const HEAP_SIZE: usize = 24 * 1024 * 1024;
 
 // ================= INITIALIZATION
 
 pub fn init() {
+    cpu::enable_protection();
     gdt::init();
     interrupts::init_idt();
+    time::init();
     unsafe { interrupts::PICS.lock().initialize() };
     x86_64::instructions::interrupts::enable();
 }
@@ -63,6 +83,7 @@ pub enum QemuExitCode {
 /**
     Will Exit the QEMU VM
     NOT FOR USE IN MAIN OS THREADS - TESTS AND DEBUGGING ONLY
+    (use acpi::shutdown() or acpi::reboot() instead)
 **/
 // For use in debugging and testing ONLY! Not for use in main OS threads.
 pub fn exit_qemu(exit_code: QemuExitCode) {
@@ -90,11 +111,68 @@ pub unsafe fn inb(port: u16) -> u16 {
     p.read()
 }
 
+// ================= TESTING
+
+/// A test run by test_runner(...), which prints its name and whether it passed to serial
+pub trait Testable {
+    fn run(&self);
+}
+
+impl<T: Fn()> Testable for T {
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+/// Runs the #[test_case]s in QEMU (`cargo test`), exiting it once they all passed
+pub fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+/// Reports a failed test and exits QEMU, for the panic handler of tests
+pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("Error: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    hlt_loop();
+}
+
+#[cfg(test)]
+bootloader::entry_point!(test_kernel_main);
+
+/// The entry point of `cargo test` for the library, with the heap set up for the tests
+#[cfg(test)]
+fn test_kernel_main(boot_info: &'static bootloader::BootInfo) -> ! {
+    init();
+    let phys_mem_offset = x86_64::VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator = unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    test_panic_handler(info)
+}
+
 // ================= CUSTOM PANIC IMPLIMENTATION
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! { // TODO: Timestamps
     println!("&4{}", _info);
+    serial_println!("{}", _info);
+    backtrace::print_backtrace();
 
     hlt_loop(); // halt the os
 }
@@ -1,8 +1,4 @@
-use alloc::string::{String, ToString};
-
-use crate::{print, println};
-use crate::vga_textmode::{ScreenChar, vga_write};
-use crate::write_channel::WriteChannel;
+use crate::vga_textmode;
 
 // 0 =   black
 // 1 = # aqua
@@ -26,20 +22,61 @@ const color_map: [[u8; 24]; 16] = [
     [0,0,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,1,2,0,0],  //   ###################%
 ];
 
-pub fn print_logo() { // extremely inefficient because of double for loops with print!() in each, as well as expensive color change calls
-    for row in 0..16 {
-        for col in 5..29 {
+// the logo is drawn with its own glyphs, in place of the ones of ☺☻♥♦♣ (bytes 0x01-0x05), so
+// those characters are written as the replacement character until another font is loaded
+const LOGO_GLYPHS: u8 = 0x01;
 
-            match color_map[row][col] {
-                0 => print!("&0"),
-                1 => print!("&b"),
-                2 => print!("&3"),
-                _ => print!("&0")
-            }
+// a solid cell and the 4 rounded corners (lines top to bottom, bits are pixels left to right)
+const GLYPH_LINES: [[u8; 16]; 5] = [
+    [0xff; 16],
+    [0x03, 0x07, 0x0f, 0x1f, 0x3f, 0x3f, 0x3f, 0x7f, 0x7f, 0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], // top left
+    [0xc0, 0xe0, 0xf0, 0xf8, 0xfc, 0xfc, 0xfc, 0xfe, 0xfe, 0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], // top right
+    [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x7f, 0x7f, 0x7f, 0x3f, 0x3f, 0x3f, 0x1f, 0x0f, 0x07, 0x03], // bottom left
+    [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe, 0xfe, 0xfe, 0xfc, 0xfc, 0xfc, 0xf8, 0xf0, 0xe0, 0xc0], // bottom right
+];
 
-            print!("{}", 0xfe as char);
-            //print!(" ");
-        }
-        println!();
+/// returns which logo glyph a cell is drawn with, rounding off the corners of the shapes
+fn cell_glyph(row: usize, col: usize) -> u8 {
+    let empty = |row: Option<usize>, col: Option<usize>| match (row, col) {
+        (Some(row), Some(col)) if row < 16 && col < 24 => color_map[row][col] == 0,
+        _ => true,
+    };
+    let up = empty(row.checked_sub(1), Some(col));
+    let down = empty(Some(row + 1), Some(col));
+    let left = empty(Some(row), col.checked_sub(1));
+    let right = empty(Some(row), Some(col + 1));
+
+    LOGO_GLYPHS + match (up, down, left, right) {
+        (true, _, true, _) => 1,
+        (true, _, _, true) => 2,
+        (_, true, true, _) => 3,
+        (_, true, _, true) => 4,
+        _ => 0,
     }
-}
\ No newline at end of file
+}
+
+/// Draws the logo on the console print! writes to
+///
+/// Needs the physical memory mapping (memory::init) to upload its glyphs, and the heap to save
+/// the BIOS font first.
+pub fn print_logo() {
+    use x86_64::instructions::interrupts;
+
+    for (i, glyph) in GLYPH_LINES.iter().enumerate() {
+        vga_textmode::set_glyph(LOGO_GLYPHS + i as u8, glyph);
+    }
+
+    interrupts::without_interrupts(|| {
+        let mut writer = vga_textmode::console(vga_textmode::output_console()).lock();
+        for row in 0..16 {
+            for col in 0..24 {
+                match color_map[row][col] {
+                    1 => writer.write_byte_colored(cell_glyph(row, col), 0x0b), // aqua on black
+                    2 => writer.write_byte_colored(cell_glyph(row, col), 0x03), // cyan on black
+                    _ => writer.write_byte_colored(b' ', 0x00),
+                }
+            }
+            writer.write_byte(b'\n');
+        }
+    });
+}
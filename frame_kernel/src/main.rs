@@ -1,11 +1,14 @@
 // main.rs - Entry point for FrameOS
 #![no_std]
 #![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(frame_kernel::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 extern crate alloc;
 extern crate rlibc;
 
-use alloc::{boxed::Box, rc::Rc, vec, vec::Vec};
+use alloc::{boxed::Box, format, rc::Rc, vec, vec::Vec};
 use alloc::string::ToString;
 
 use bootloader::{BootInfo, entry_point};
@@ -13,12 +16,15 @@ use x86_64::VirtAddr;
 
 use frame_kernel::{
     clear_vga, print, println, serial_println,
-    task::{executor::Executor, Task},
+    task::{executor::Executor, Priority, Task},
 };
 use frame_kernel::logger::Logger;
 use frame_kernel::logo_print::print_logo;
-use frame_kernel::task::keyboard;
-use frame_kernel::write_channel::{ChannelSTDOUT, stdout};
+use frame_kernel::command;
+use frame_kernel::task::{keyboard, mouse, timer};
+use frame_kernel::task::sync::mpsc;
+use frame_kernel::vga_textmode::LOG_CONSOLE;
+use frame_kernel::write_channel::{ChannelLOG, klog};
 
 // define the entry point as kmain() instead of _start()
 entry_point!(kmain);
@@ -28,7 +34,7 @@ const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
 
 // create the logger
-const logger: Logger<ChannelSTDOUT> = Logger::new(&stdout);
+const logger: Logger<ChannelLOG> = Logger::new(&klog); // Alt+F6
 
 // ================= ENTRY POINT
 
@@ -38,10 +44,11 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
 
     println!("&bFrame&3OS &5v&d{} &9By &3Eric (Sk3pz) &9&& &3Matthew (MooCow9M)\n", VERSION);
     println!();
-    print_logo();
-    print!("&7%0"); // reset colors
     frame_kernel::init(); // initialize the interrupt handlers
 
+    #[cfg(test)]
+    test_main(); // the tests of main.rs, `cargo test` (there are none, lib.rs has them)
+
     // the physical memory offset
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     // used for translating virtual addresses (mapper.translate_addr(virtual address))
@@ -53,11 +60,50 @@ fn kmain(boot_info: &'static BootInfo) -> ! {
     allocator::init_heap(&mut mapper, &mut frame_allocator)
         .expect("FrameOS Heap initialization failed.");
 
+    print_logo(); // after the heap, the BIOS font is saved before the logo's glyphs replace some
+    print!("&7%0"); // reset colors
+
+    // find the ACPI tables (used for shutdown and reboot)
+    if let Err(err) = frame_kernel::acpi::init() {
+        println!("&eACPI initialization failed: {:?}", err);
+    }
+
+    // self-test the PS/2 controller and set up the keyboard (and the port of the mouse)
+    if let Err(err) = frame_kernel::ps2::init() {
+        println!("&ePS/2 controller initialization failed: {:?}", err);
+    }
+
+    // for mapping device memory (like the framebuffer of a graphics mode) later on
+    memory::keep_paging(mapper, frame_allocator);
+
     // ================= MAIN RUNTIME CODE
 
+    // frame_kernel::gdb::init(); // uncomment to debug with gdb over COM2 (see gdb.rs)
+    // frame_kernel::gdb::breakpoint(); // wait for gdb to attach
+
     let mut executor = Executor::new();
 
-    executor.spawn(Task::new(keyboard::handle_keypresses())); // enables keyboard input
+    // a shell on every virtual console but the kernel log's, each reading the lines typed on it
+    let mut line_senders = Vec::new();
+    for console in 0..LOG_CONSOLE {
+        let (line_sender, lines) = mpsc::unbounded();
+        line_senders.push(line_sender);
+        executor.spawn(Task::new(command::shell(lines, executor.spawner()))
+            .with_name(&format!("shell{}", console + 1)).with_console(console));
+    }
+
+    // input is latency sensitive, so it is polled ahead of normal tasks
+    executor.spawn(Task::with_priority(keyboard::handle_keypresses(line_senders), Priority::High)
+        .with_name("keyboard")); // enables keyboard input
+    executor.spawn(Task::with_priority(timer::run_timers(), Priority::High)
+        .with_name("timers")); // enables sleep, interval and timeout
+    match mouse::init() {
+        Ok(_) => {
+            executor.spawn(Task::with_priority(mouse::handle_mouse(), Priority::High)
+                .with_name("mouse")); // enables the mouse pointer, selection and scrolling
+        }
+        Err(err) => println!("&eMouse initialization failed: {:?}", err),
+    }
 
     executor.run();
 
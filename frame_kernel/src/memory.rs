@@ -1,11 +1,19 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use lazy_static::lazy_static;
+use spin::Mutex;
 use x86_64::{
     structures::paging::{
-        FrameAllocator, Mapper, OffsetPageTable, Page, PageTable, PhysFrame, Size4KiB,
+        FrameAllocator, Mapper, mapper::MapToError, OffsetPageTable, Page, PageTable,
+        PageTableFlags, PhysFrame, Size4KiB,
     },
     PhysAddr, VirtAddr,
 };
 
+/// where device memory (like a linear framebuffer) is mapped, see map_device_memory(...)
+pub const DEVICE_MEMORY_START: u64 = 0x_5555_0000_0000;
+
 /// Initialize a new OffsetPageTable.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -13,10 +21,101 @@ use x86_64::{
 /// `physical_memory_offset`. Also, this function must be only called once
 /// to avoid aliasing `&mut` references (which is undefined behavior).
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::SeqCst);
     let level_4_table = active_level_4_table(physical_memory_offset);
     OffsetPageTable::new(level_4_table, physical_memory_offset)
 }
 
+/// The virtual address the bootloader mapped all physical memory to, set by `init`
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the virtual address a physical address is mapped to.
+///
+/// Only valid after `init` has been called.
+pub fn phys_to_virt(addr: PhysAddr) -> VirtAddr {
+    VirtAddr::new(PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) + addr.as_u64())
+}
+
+/// Looks up the page an address is in, in the active page table
+/// returns the flags of its entry, WRITABLE only if every level above allows writes too,
+/// or None if the address isn't mapped (or is not canonical)
+///
+/// Walks the table itself instead of locking the one kept by keep_paging(...), so exception
+/// handlers (the gdb stub) can check an address before touching it.
+pub fn page_flags(addr: u64) -> Option<PageTableFlags> {
+    use x86_64::registers::control::Cr3;
+
+    let addr = VirtAddr::try_new(addr).ok()?;
+    if PHYSICAL_MEMORY_OFFSET.load(Ordering::SeqCst) == 0 {
+        return None; // init hasn't been called
+    }
+    let (level_4_table_frame, _) = Cr3::read();
+    let mut table: &PageTable = unsafe { &*phys_to_virt(level_4_table_frame.start_address()).as_ptr() };
+
+    let indexes = [addr.p4_index(), addr.p3_index(), addr.p2_index(), addr.p1_index()];
+    let mut writable = true;
+    for (level, &index) in indexes.iter().enumerate() {
+        let mut flags = table[index].flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            return None;
+        }
+        writable &= flags.contains(PageTableFlags::WRITABLE);
+        // p3 and p2 entries can map a 1GiB or 2MiB page directly, p1 entries always map a page
+        if level == 3 || (level > 0 && flags.contains(PageTableFlags::HUGE_PAGE)) {
+            flags.set(PageTableFlags::WRITABLE, writable);
+            return Some(flags);
+        }
+        table = unsafe { &*phys_to_virt(table[index].addr()).as_ptr() };
+    }
+    None
+}
+
+lazy_static! {
+    /// the page table and frame allocator after boot, for mapping memory later on
+    static ref PAGING: Mutex<Option<(OffsetPageTable<'static>, BootInfoFrameAllocator)>> = Mutex::new(None);
+}
+
+/// the next free address in the device memory region
+static NEXT_DEVICE_ADDR: AtomicU64 = AtomicU64::new(DEVICE_MEMORY_START);
+
+/// Keeps the page table and frame allocator once boot is done with them, so memory can be mapped
+/// (and addresses translated) later
+pub fn keep_paging(mapper: OffsetPageTable<'static>, frame_allocator: BootInfoFrameAllocator) {
+    *PAGING.lock() = Some((mapper, frame_allocator));
+}
+
+/// Runs `f` with the page table kept by keep_paging(...)
+/// returns None before it was kept
+pub fn with_paging<R>(f: impl FnOnce(&mut OffsetPageTable<'static>, &mut BootInfoFrameAllocator) -> R) -> Option<R> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut paging = PAGING.lock();
+        let (mapper, frame_allocator) = paging.as_mut()?;
+        Some(f(mapper, frame_allocator))
+    })
+}
+
+/// Maps `size` bytes of device memory at a physical address into the device memory region,
+/// uncached
+/// returns the virtual address of `addr`
+pub fn map_device_memory(addr: PhysAddr, size: u64) -> Result<VirtAddr, MapToError<Size4KiB>> {
+    let first = PhysFrame::<Size4KiB>::containing_address(addr);
+    let last = PhysFrame::<Size4KiB>::containing_address(addr + size.max(1) - 1u64);
+    let offset = addr - first.start_address();
+    let pages = last.start_address() - first.start_address() + 4096;
+    let start = VirtAddr::new(NEXT_DEVICE_ADDR.fetch_add(pages, Ordering::SeqCst));
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_CACHE;
+    with_paging(|mapper, frame_allocator| -> Result<VirtAddr, MapToError<Size4KiB>> {
+        for (i, frame) in PhysFrame::range_inclusive(first, last).enumerate() {
+            let page = Page::containing_address(start + i as u64 * 4096);
+            unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+        }
+        Ok(start + offset)
+    }).unwrap_or(Err(MapToError::FrameAllocationFailed)) // nothing to map with yet
+}
+
 /// Returns a mutable reference to the active level 4 table.
 ///
 /// This function is unsafe because the caller must guarantee that the
@@ -63,9 +162,13 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
 }
 
 /// A FrameAllocator that returns usable frames from the bootloader's memory map.
+///
+/// Frames are handed out in order, the allocator remembers where it is in the map so each
+/// allocation is O(1) instead of walking the map from the start.
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
-    next: usize,
+    region: usize, // the index in the memory map of the region frames are taken from
+    next: u64, // the address of the next frame in it
 }
 
 impl BootInfoFrameAllocator {
@@ -77,28 +180,26 @@ impl BootInfoFrameAllocator {
     pub unsafe fn init(memory_map: &'static MemoryMap) -> Self {
         BootInfoFrameAllocator {
             memory_map,
+            region: 0,
             next: 0,
         }
     }
-
-    /// Returns an iterator over the usable frames specified in the memory map.
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        // get usable regions from memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-        // map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-        // transform to an iterator of frame start addresses
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(4096));
-        // create `PhysFrame` types from the start addresses
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.region_type == MemoryRegionType::Usable {
+                // regions are page aligned, next is 0 until the first frame of a region is taken
+                let next = self.next.max(region.range.start_addr());
+                if next + 4096 <= region.range.end_addr() {
+                    self.next = next + 4096;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(next)));
+                }
+            }
+            self.region += 1;
+            self.next = 0;
+        }
+        None
     }
 }
@@ -0,0 +1,93 @@
+// PCI configuration space access through the legacy 0xCF8/0xCFC ports
+//
+// Devices are found by brute force: every device slot of every bus is checked for a vendor id.
+// That's 8192 reads without functions, fast enough for the few lookups done at boot.
+
+use x86_64::instructions::{interrupts, port::Port};
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+/// A function of a device on the PCI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+}
+
+/// Reads a 32 bit register from the configuration space of a device
+/// (`offset` is rounded down to a multiple of 4)
+pub fn read_config(bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+    let address = 0x8000_0000 | (bus as u32) << 16 | ((device & 0x1F) as u32) << 11
+        | ((function & 0x7) as u32) << 8 | (offset & 0xFC) as u32;
+    interrupts::without_interrupts(|| unsafe {
+        Port::<u32>::new(CONFIG_ADDRESS).write(address);
+        Port::<u32>::new(CONFIG_DATA).read()
+    })
+}
+
+impl PciDevice {
+    fn at(bus: u8, device: u8, function: u8) -> Option<PciDevice> {
+        let id = read_config(bus, device, function, 0x00);
+        match id & 0xFFFF {
+            0xFFFF => None, // nothing there
+            vendor_id => Some(PciDevice {
+                bus,
+                device,
+                function,
+                vendor_id: vendor_id as u16,
+                device_id: (id >> 16) as u16,
+            }),
+        }
+    }
+
+    pub fn read_config(&self, offset: u8) -> u32 {
+        read_config(self.bus, self.device, self.function, offset)
+    }
+
+    /// returns the (class, subclass) of the device
+    pub fn class(&self) -> (u8, u8) {
+        let class = self.read_config(0x08);
+        ((class >> 24) as u8, (class >> 16) as u8)
+    }
+
+    /// returns the physical address of a memory BAR (base address register)
+    /// None for I/O port BARs
+    pub fn memory_bar(&self, index: u8) -> Option<u64> {
+        let bar = self.read_config(0x10 + index * 4);
+        if bar & 0x1 != 0 {
+            return None;
+        }
+        let address = (bar & 0xFFFF_FFF0) as u64;
+        if (bar >> 1) & 0x3 == 0x2 { // 64 bit, the next BAR holds the upper half
+            Some(address | (self.read_config(0x14 + index * 4) as u64) << 32)
+        } else {
+            Some(address)
+        }
+    }
+}
+
+/// Finds the first function with a vendor and device id
+pub fn find_device(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            let first = match PciDevice::at(bus, device, 0) {
+                Some(first) => first,
+                None => continue,
+            };
+            // only multi-function devices have functions past 0
+            let functions = if (first.read_config(0x0C) >> 16) & 0x80 != 0 { 8 } else { 1 };
+            for function in 0..functions {
+                if let Some(found) = PciDevice::at(bus, device, function) {
+                    if found.vendor_id == vendor_id && found.device_id == device_id {
+                        return Some(found);
+                    }
+                }
+            }
+        }
+    }
+    None
+}
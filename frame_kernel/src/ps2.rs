@@ -0,0 +1,335 @@
+// The 8042 PS/2 controller and the keyboard on its first port
+//
+// init() self-tests the controller and its ports, resets the keyboard and puts it in scancode
+// set 2 (falling back to translated set 1 if it refuses). Commands sent to the keyboard later
+// are acknowledged through IRQ1, so the keyboard interrupt handler passes every byte through
+// keyboard_byte(...) to pick out the ACK/resend responses before they reach the scancode queue.
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+use futures_util::{future::poll_fn, task::AtomicWaker};
+use lazy_static::lazy_static;
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::task::sync::Mutex;
+use crate::task::timer;
+use crate::time;
+
+const DATA_PORT: u16 = 0x60;
+const STATUS_PORT: u16 = 0x64; // the command port when written
+
+/// how many times the status register is read before giving up on the controller
+const TIMEOUT: u32 = 100_000;
+
+/// how many times a byte is sent again when the device asks for a resend
+const RETRIES: usize = 3;
+
+/// how long a device gets to acknowledge a command sent from a task
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// how long the keyboard gets to finish its self-test after a reset
+const RESET_TIMEOUT: Duration = Duration::from_millis(1000);
+
+const ACK: u8 = 0xFA;
+const RESEND: u8 = 0xFE;
+
+// configuration byte bits
+const CONFIG_PORT1_IRQ: u8 = 1 << 0;
+const CONFIG_PORT2_IRQ: u8 = 1 << 1;
+const CONFIG_PORT1_CLOCK_DISABLED: u8 = 1 << 4;
+const CONFIG_PORT2_CLOCK_DISABLED: u8 = 1 << 5;
+const CONFIG_TRANSLATION: u8 = 1 << 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout, // the controller or device didn't respond
+    SelfTestFailed(u8), // the controller's self-test response
+    PortTestFailed(u8), // the port test response of the keyboard port
+    NoAck(u8), // the device answered a command with this instead of an ACK
+    NoAuxPort, // the controller has no second port for a mouse
+}
+
+static SCANCODE_SET: AtomicU8 = AtomicU8::new(1);
+static HAS_AUX_PORT: AtomicBool = AtomicBool::new(false);
+
+// ================= POLLED I/O (used while initialising, with the device's IRQ off)
+
+fn wait_write() -> Result<(), Ps2Error> {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & 0b10 == 0 { // input buffer empty
+            return Ok(());
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+fn wait_read() -> Result<(), Ps2Error> {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    for _ in 0..TIMEOUT {
+        if unsafe { status.read() } & 0b1 != 0 { // output buffer full
+            return Ok(());
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Sends a command to the controller itself
+pub(crate) fn controller_command(command: u8) -> Result<(), Ps2Error> {
+    wait_write()?;
+    unsafe { Port::new(STATUS_PORT).write(command) };
+    Ok(())
+}
+
+pub(crate) fn write_data(byte: u8) -> Result<(), Ps2Error> {
+    wait_write()?;
+    unsafe { Port::new(DATA_PORT).write(byte) };
+    Ok(())
+}
+
+pub(crate) fn read_data() -> Result<u8, Ps2Error> {
+    wait_read()?;
+    Ok(unsafe { Port::new(DATA_PORT).read() })
+}
+
+/// Waits for a byte from a device for up to `timeout`, counted with the timer
+///
+/// Interrupts must be on for the timer to count, so the ports' IRQs have to be off in the
+/// configuration byte (or a handler would take the byte).
+fn read_data_timeout(timeout: Duration) -> Result<u8, Ps2Error> {
+    let deadline = time::ticks() + time::ms_to_ticks(timeout.as_millis() as u64);
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    while time::ticks() <= deadline {
+        if unsafe { status.read() } & 0b1 != 0 { // output buffer full
+            return Ok(unsafe { Port::new(DATA_PORT).read() });
+        }
+    }
+    Err(Ps2Error::Timeout)
+}
+
+/// Throws away anything left in the output buffer
+fn flush() {
+    let mut status: Port<u8> = Port::new(STATUS_PORT);
+    let mut data: Port<u8> = Port::new(DATA_PORT);
+    while unsafe { status.read() } & 0b1 != 0 {
+        unsafe { data.read() };
+    }
+}
+
+fn read_config() -> Result<u8, Ps2Error> {
+    controller_command(0x20)?;
+    read_data()
+}
+
+fn write_config(config: u8) -> Result<(), Ps2Error> {
+    controller_command(0x60)?;
+    write_data(config)
+}
+
+/// Sends a byte to a device and waits for its ACK, resending it when asked to
+/// `aux` sends it to the second (mouse) port
+pub(crate) fn write_device(aux: bool, byte: u8) -> Result<(), Ps2Error> {
+    let mut response = 0;
+    for _ in 0..RETRIES {
+        if aux {
+            controller_command(0xD4)?; // the next data byte goes to the second port
+        }
+        write_data(byte)?;
+        response = read_data()?;
+        match response {
+            ACK => return Ok(()),
+            RESEND => continue,
+            _ => break,
+        }
+    }
+    Err(Ps2Error::NoAck(response))
+}
+
+/// returns true if the controller has a second port for a mouse
+pub fn has_aux_port() -> bool {
+    HAS_AUX_PORT.load(Ordering::SeqCst)
+}
+
+/// returns the scancode set the keyboard task should decode (1 or 2)
+pub fn scancode_set() -> u8 {
+    SCANCODE_SET.load(Ordering::SeqCst)
+}
+
+/// Turns on the IRQ of the second port, once the mouse is set up
+pub(crate) fn enable_aux_interrupt() -> Result<(), Ps2Error> {
+    interrupts::without_interrupts(|| {
+        let config = read_config()?;
+        write_config((config | CONFIG_PORT2_IRQ) & !CONFIG_PORT2_CLOCK_DISABLED)
+    })
+}
+
+/// Self-tests and initialises the controller and the keyboard
+pub fn init() -> Result<(), Ps2Error> {
+    let config = interrupts::without_interrupts(|| -> Result<u8, Ps2Error> {
+        // disable both ports so nothing gets in the way of the tests
+        controller_command(0xAD)?;
+        controller_command(0xA7)?;
+        flush();
+
+        let config = read_config()? & !(CONFIG_PORT1_IRQ | CONFIG_PORT2_IRQ | CONFIG_TRANSLATION);
+        write_config(config)?;
+
+        controller_command(0xAA)?; // self-test
+        match read_data()? {
+            0x55 => {}
+            response => return Err(Ps2Error::SelfTestFailed(response)),
+        }
+        write_config(config)?; // some controllers reset themselves during the self-test
+
+        // the second port exists if enabling it starts its clock
+        controller_command(0xA8)?;
+        let aux = read_config()? & CONFIG_PORT2_CLOCK_DISABLED == 0;
+        controller_command(0xA7)?;
+        if aux {
+            controller_command(0xA9)?; // test the second port
+            HAS_AUX_PORT.store(read_data()? == 0x00, Ordering::SeqCst);
+        }
+
+        controller_command(0xAB)?; // test the first port
+        match read_data()? {
+            0x00 => {}
+            response => return Err(Ps2Error::PortTestFailed(response)),
+        }
+
+        controller_command(0xAE)?; // enable the first port
+        if has_aux_port() {
+            controller_command(0xA8)?;
+        }
+
+        write_device(false, 0xFF)?; // reset the keyboard
+        Ok(config)
+    })?;
+
+    // the keyboard's self-test takes hundreds of milliseconds, wait for it with the timer running
+    // (the IRQs are still off in the configuration byte, so the reply waits in the controller)
+    match read_data_timeout(RESET_TIMEOUT)? {
+        0xAA => {} // self-test passed
+        response => return Err(Ps2Error::SelfTestFailed(response)),
+    }
+
+    interrupts::without_interrupts(|| {
+        // ask for scancode set 2, then check what the keyboard actually uses
+        let mut translate = true;
+        if write_device(false, 0xF0).and_then(|_| write_device(false, 0x02)).is_ok()
+            && write_device(false, 0xF0).and_then(|_| write_device(false, 0x00)).is_ok() {
+            translate = read_data()? != 0x02;
+        }
+        write_device(false, 0xF4)?; // enable scanning
+
+        // the configuration was read with the ports disabled, keep the enabled ones running
+        let mut config = config & !CONFIG_PORT1_CLOCK_DISABLED;
+        if has_aux_port() {
+            config &= !CONFIG_PORT2_CLOCK_DISABLED;
+        }
+        if translate {
+            // set 2 isn't supported, so let the controller translate the keyboard's default set
+            write_config(config | CONFIG_PORT1_IRQ | CONFIG_TRANSLATION)?;
+            SCANCODE_SET.store(1, Ordering::SeqCst);
+        } else {
+            write_config(config | CONFIG_PORT1_IRQ)?;
+            SCANCODE_SET.store(2, Ordering::SeqCst);
+        }
+        Ok(())
+    })
+}
+
+// ================= KEYBOARD COMMANDS (sent from tasks, acknowledged through IRQ1)
+
+static EXPECTING_RESPONSE: AtomicBool = AtomicBool::new(false);
+static RESPONSE: AtomicU8 = AtomicU8::new(0);
+static RESPONSE_WAKER: AtomicWaker = AtomicWaker::new();
+
+lazy_static! {
+    /// only one command can wait for its response at a time
+    static ref COMMAND_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Called by the keyboard interrupt handler with every byte it reads
+/// returns true if the byte was a response to a command, and not a scancode
+///
+/// Must not block or allocate.
+pub(crate) fn keyboard_byte(byte: u8) -> bool {
+    match byte {
+        ACK | RESEND if EXPECTING_RESPONSE.load(Ordering::SeqCst) => {
+            RESPONSE.store(byte, Ordering::SeqCst);
+            EXPECTING_RESPONSE.store(false, Ordering::SeqCst);
+            RESPONSE_WAKER.wake();
+            true
+        }
+        0x00 | 0xFF => true, // key detection errors / buffer overruns, never scancodes
+        _ => false,
+    }
+}
+
+/// Sends one byte to the keyboard and waits for the interrupt handler to see its response
+async fn send_keyboard_byte(byte: u8) -> Result<(), Ps2Error> {
+    for _ in 0..RETRIES {
+        RESPONSE.store(0, Ordering::SeqCst);
+        EXPECTING_RESPONSE.store(true, Ordering::SeqCst);
+        interrupts::without_interrupts(|| write_data(byte))?;
+
+        let response = poll_fn(|cx| {
+            RESPONSE_WAKER.register(cx.waker());
+            match RESPONSE.load(Ordering::SeqCst) {
+                0 => Poll::Pending,
+                response => Poll::Ready(response),
+            }
+        });
+        match timer::timeout(ACK_TIMEOUT, response).await {
+            Ok(ACK) => return Ok(()),
+            Ok(_) => continue, // resend
+            Err(_) => {
+                EXPECTING_RESPONSE.store(false, Ordering::SeqCst);
+                return Err(Ps2Error::Timeout);
+            }
+        }
+    }
+    Err(Ps2Error::NoAck(RESEND))
+}
+
+/// Sends a keyboard command and its data bytes
+async fn keyboard_command(bytes: &[u8]) -> Result<(), Ps2Error> {
+    let _lock = COMMAND_LOCK.lock().await;
+    for byte in bytes {
+        send_keyboard_byte(*byte).await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+/// Turns the keyboard's lock LEDs on or off
+pub async fn set_leds(leds: Leds) -> Result<(), Ps2Error> {
+    let mask = leds.scroll_lock as u8 | (leds.num_lock as u8) << 1 | (leds.caps_lock as u8) << 2;
+    keyboard_command(&[0xED, mask]).await
+}
+
+/// Sets how fast keys repeat while held down
+///
+/// `rate` is in characters per second (2-30), `delay_ms` is the wait before the first repeat
+/// (250-1000ms). Both are rounded to the closest value the keyboard supports.
+pub async fn set_typematic(rate: u32, delay_ms: u32) -> Result<(), Ps2Error> {
+    // the repeat period of rate setting r is (8 + r[0:2]) * 2^r[3:4] * 4.17ms
+    let period_us = |r: u8| (8 + (r & 0b111) as u32) * (1 << ((r >> 3) & 0b11)) * 4170;
+    let target_us = 1_000_000 / rate.max(1);
+    let rate_bits = (0..32u8)
+        .min_by_key(|r| (period_us(*r) as i32 - target_us as i32).abs())
+        .unwrap_or(0);
+
+    let delay_bits = ((delay_ms.max(250).min(1000) + 125) / 250 - 1) as u8;
+    keyboard_command(&[0xF3, delay_bits << 5 | rate_bits]).await
+}
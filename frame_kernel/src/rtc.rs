@@ -0,0 +1,94 @@
+// The real time clock in the CMOS
+//
+// Its registers are read by writing their index to port 0x70 and reading port 0x71. The values
+// are BCD or binary and the hours 12 or 24 hour depending on status register B, and the clock
+// can be in the middle of an update, so the time is read until two reads in a row agree.
+// The RTC keeps whatever time the firmware set, UTC under QEMU by default.
+
+use x86_64::instructions::{interrupts, port::Port};
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const UPDATE_IN_PROGRESS: u8 = 0x80; // status A
+const HOURS_24: u8 = 0x02; // status B
+const BINARY: u8 = 0x04; // status B
+const PM: u8 = 0x80; // the hours in the 12 hour format
+
+/// the most reads before settling for a time that might be torn by an update
+const MAX_READS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+fn read_register(index: u8) -> u8 {
+    let mut index_port: Port<u8> = Port::new(INDEX);
+    let mut data_port: Port<u8> = Port::new(DATA);
+    unsafe {
+        index_port.write(index);
+        data_port.read()
+    }
+}
+
+/// returns the registers of the time, as they are stored
+fn read_raw() -> [u8; 6] {
+    while read_register(STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+    let mut values = [0; 6];
+    for (value, &index) in values.iter_mut().zip([SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].iter()) {
+        *value = read_register(index);
+    }
+    values
+}
+
+fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Reads the date and time from the RTC
+pub fn now() -> DateTime {
+    interrupts::without_interrupts(|| {
+        let mut raw = read_raw();
+        for _ in 0..MAX_READS {
+            let again = read_raw();
+            if again == raw {
+                break;
+            }
+            raw = again;
+        }
+        let status = read_register(STATUS_B);
+
+        let value = |value: u8| if status & BINARY != 0 { value } else { from_bcd(value) };
+        let [second, minute, hours, day, month, year] = raw;
+        let mut hour = value(hours & !PM);
+        if status & HOURS_24 == 0 {
+            hour %= 12; // 12 AM is 0
+            if hours & PM != 0 {
+                hour += 12;
+            }
+        }
+        DateTime {
+            year: 2000 + value(year) as u16, // no century register without ACPI's FADT
+            month: value(month),
+            day: value(day),
+            hour,
+            minute: value(minute),
+            second: value(second),
+        }
+    })
+}
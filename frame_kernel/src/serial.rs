@@ -25,6 +25,21 @@ pub fn _print(args: ::core::fmt::Arguments) {
     });
 }
 
+/// Prints to serial unless the port is locked, for interrupt handlers that could have interrupted
+/// the code holding the lock
+/// returns false if nothing was printed
+pub fn try_print(args: ::core::fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        match SERIAL1.try_lock() {
+            Some(mut serial) => serial.write_fmt(args).is_ok(),
+            None => false,
+        }
+    })
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -1,82 +1,231 @@
-use super::{Task, TaskId};
-use alloc::{collections::BTreeMap, sync::Arc, task::Wake};
+use super::{Priority, Task, TaskId};
+use super::info::{self, TaskState};
+use super::spawner::{SpawnQueue, Spawner};
+use alloc::{collections::{BTreeMap, VecDeque}, sync::Arc, task::Wake};
+use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::{Waker, Context, Poll};
 use crossbeam_queue::ArrayQueue;
 
+use crate::{time, vga_textmode, watchdog};
+
+/// how long a task may run in a single poll before it is charged extra (fairness accounting)
+const TIME_SLICE_US: u64 = 10_000;
+
+/// the capacity of the wake queue, tasks woken while it is full are found by a scan instead
+const WAKE_QUEUE_SIZE: usize = 100;
+
+/// how many polls each priority level gets per round, so low priorities can't starve
+const PRIORITY_WEIGHTS: [u32; Priority::COUNT] = [1, 2, 4]; // Low, Normal, High
+
+struct TaskEntry {
+    task: Task,
+    waker: Waker,
+    woken: Arc<AtomicBool>, // set by the waker, cleared before each poll
+    queued: bool, // true if the task is in one of the ready queues
+}
+
+/// Task ids woken by wakers, which may run in interrupt handlers and so must not allocate
+struct WakeQueue {
+    queue: ArrayQueue<TaskId>,
+    overflow: AtomicBool, // true if a wake did not fit in the queue
+}
+
 pub struct Executor {
-    tasks: BTreeMap<TaskId, Task>,
-    task_queue: Arc<ArrayQueue<TaskId>>,
-    waker_cache: BTreeMap<TaskId, Waker>,
+    tasks: BTreeMap<TaskId, TaskEntry>,
+    ready: [VecDeque<TaskId>; Priority::COUNT], // indexed by priority, grows as needed
+    credits: [u32; Priority::COUNT], // polls left for each priority in this round
+    wake_queue: Arc<WakeQueue>,
+    spawn_queue: SpawnQueue, // tasks spawned through a Spawner
 }
 
 impl Executor {
     pub fn new() -> Self {
         Executor {
             tasks: BTreeMap::new(),
-            task_queue: Arc::new(ArrayQueue::new(100)),
-            waker_cache: BTreeMap::new(),
+            ready: [VecDeque::new(), VecDeque::new(), VecDeque::new()],
+            credits: PRIORITY_WEIGHTS,
+            wake_queue: Arc::new(WakeQueue {
+                queue: ArrayQueue::new(WAKE_QUEUE_SIZE),
+                overflow: AtomicBool::new(false),
+            }),
+            spawn_queue: Arc::new(spin::Mutex::new(VecDeque::new())),
         }
     }
 
     pub fn spawn(&mut self, task: Task) -> TaskId {
         let task_id = task.id;
-        if self.tasks.insert(task.id, task).is_some() {
+        let priority = task.priority;
+        let woken = Arc::new(AtomicBool::new(false));
+        let entry = TaskEntry {
+            waker: TaskWaker::new(task_id, woken.clone(), self.wake_queue.clone()),
+            task,
+            woken,
+            queued: true,
+        };
+        info::register(task_id, entry.task.name(), priority);
+        if self.tasks.insert(task_id, entry).is_some() {
             panic!("task with same ID already in tasks");
         }
-        self.task_queue.push(task_id).expect("queue full");
+        self.ready[priority as usize].push_back(task_id);
         task_id
     }
 
-    fn run_ready_tasks(&mut self) {
-        // destructure `self` to avoid borrow checker errors
+    /// returns a handle that can spawn tasks on this executor from inside running tasks
+    pub fn spawner(&self) -> Spawner {
+        Spawner::new(self.spawn_queue.clone())
+    }
+
+    /// Moves woken and newly spawned tasks into the ready queues of their priority
+    fn collect_woken(&mut self) {
+        loop {
+            let task = self.spawn_queue.lock().pop_front(); // not held while spawning
+            match task {
+                Some(task) => { self.spawn(task); }
+                None => break,
+            }
+        }
+
         let Self {
             tasks,
-            task_queue,
-            waker_cache,
+            ready,
+            wake_queue,
+            ..
         } = self;
 
-        while let Ok(task_id) = task_queue.pop() {
-            let task = match tasks.get_mut(&task_id) {
-                Some(task) => task,
+        let mut enqueue = |task_id: TaskId, tasks: &mut BTreeMap<TaskId, TaskEntry>| {
+            if let Some(entry) = tasks.get_mut(&task_id) {
+                if !entry.queued {
+                    entry.queued = true;
+                    ready[entry.task.priority as usize].push_back(task_id);
+                    info::set_state(task_id, TaskState::Ready);
+                }
+            }
+        };
+
+        while let Ok(task_id) = wake_queue.queue.pop() {
+            enqueue(task_id, tasks);
+        }
+
+        // some wakes didn't fit in the queue, find them through their flags
+        if wake_queue.overflow.swap(false, Ordering::SeqCst) {
+            let woken: alloc::vec::Vec<TaskId> = tasks.iter()
+                .filter(|(_, entry)| entry.woken.load(Ordering::SeqCst) && !entry.queued)
+                .map(|(id, _)| *id)
+                .collect();
+            for task_id in woken {
+                enqueue(task_id, tasks);
+            }
+        }
+    }
+
+    /// Picks the next task to poll, from the highest priority that still has credits this round
+    fn next_task(&mut self) -> Option<TaskId> {
+        if self.ready.iter().all(|queue| queue.is_empty()) {
+            return None;
+        }
+        loop {
+            for priority in (0..Priority::COUNT).rev() {
+                if self.credits[priority] > 0 && !self.ready[priority].is_empty() {
+                    self.credits[priority] -= 1;
+                    return self.ready[priority].pop_front();
+                }
+            }
+            // every level with ready tasks is out of credits, start a new round
+            self.credits = PRIORITY_WEIGHTS;
+        }
+    }
+
+    fn run_ready_tasks(&mut self) {
+        self.collect_woken();
+
+        while let Some(task_id) = self.next_task() {
+            let entry = match self.tasks.get_mut(&task_id) {
+                Some(entry) => entry,
                 None => continue, // task no longer exists
             };
-            let waker = waker_cache
-                .entry(task_id)
-                .or_insert_with(|| TaskWaker::new(task_id, task_queue.clone()));
-            let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+            entry.queued = false;
+            entry.woken.store(false, Ordering::SeqCst); // wakes during the poll queue it again
+
+            let mut context = Context::from_waker(&entry.waker);
+            vga_textmode::set_output_console(entry.task.console());
+            watchdog::poll_start(task_id.as_u64());
+            let start = time::rdtsc();
+            let result = entry.task.poll(&mut context);
+            let elapsed = time::rdtsc().wrapping_sub(start);
+            watchdog::poll_end();
+
+            // a task that hogged the cpu pays for it with the credits of its priority
+            let overrun = time::tsc_to_us(elapsed) / TIME_SLICE_US;
+            if overrun > 0 {
+                let priority = entry.task.priority as usize;
+                self.credits[priority] = self.credits[priority].saturating_sub(overrun as u32);
+            }
+
+            // the run time accounting is kept in task::info, for ps and top
+            info::record_poll(task_id, elapsed, overrun > 0, result.is_ready());
+
+            match result {
                 Poll::Ready(()) => {
-                    // task done -> remove it and its cached waker
-                    tasks.remove(&task_id);
-                    waker_cache.remove(&task_id);
+                    // task done -> remove it and its waker
+                    self.tasks.remove(&task_id);
                 }
                 Poll::Pending => {}
             }
+
+            self.collect_woken();
+        }
+    }
+
+    /// Halts the cpu until the next interrupt if no task is ready
+    fn sleep_if_idle(&mut self) {
+        use x86_64::instructions::interrupts::{self, enable_and_hlt};
+
+        // interrupts are disabled for the check, so a wake can't slip in between it and the hlt
+        interrupts::disable();
+        let idle = self.wake_queue.queue.is_empty()
+            && !self.wake_queue.overflow.load(Ordering::SeqCst)
+            && self.spawn_queue.lock().is_empty();
+        if idle {
+            let start = time::rdtsc();
+            enable_and_hlt(); // sti takes effect after hlt, so no interrupt is missed
+            info::add_idle_cycles(time::rdtsc().wrapping_sub(start));
+        } else {
+            interrupts::enable();
         }
     }
 
     pub fn run(&mut self) -> ! {
         loop {
+            watchdog::pet();
             self.run_ready_tasks();
+            self.sleep_if_idle();
         }
     }
 }
 
 struct TaskWaker {
     task_id: TaskId,
-    task_queue: Arc<ArrayQueue<TaskId>>,
+    woken: Arc<AtomicBool>,
+    wake_queue: Arc<WakeQueue>,
 }
 
 impl TaskWaker {
-    fn new(task_id: TaskId, task_queue: Arc<ArrayQueue<TaskId>>) -> Waker {
+    fn new(task_id: TaskId, woken: Arc<AtomicBool>, wake_queue: Arc<WakeQueue>) -> Waker {
         Waker::from(Arc::new(TaskWaker {
             task_id,
-            task_queue,
+            woken,
+            wake_queue,
         }))
     }
 
     fn wake_task(&self) {
-        self.task_queue.push(self.task_id).expect("task_queue full");
+        if self.woken.swap(true, Ordering::SeqCst) {
+            return; // already woken, don't queue it twice
+        }
+        if self.wake_queue.queue.push(self.task_id).is_err() {
+            // the executor finds the task through its woken flag
+            self.wake_queue.overflow.store(true, Ordering::SeqCst);
+        }
     }
 }
 
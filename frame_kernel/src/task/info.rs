@@ -0,0 +1,145 @@
+// Task introspection
+//
+// The executor publishes the state and run time of every task here, so tasks (such as the
+// `ps` and `top` commands) can see what is running without access to the executor itself.
+
+use alloc::{collections::{BTreeMap, VecDeque}, string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use super::{Priority, TaskId};
+use crate::time;
+
+/// how many finished tasks are kept for diagnostics
+const FINISHED_HISTORY: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskState {
+    Ready, // waiting in a ready queue
+    Pending, // waiting to be woken
+    Finished,
+}
+
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    pub name: String,
+    pub priority: Priority,
+    pub state: TaskState,
+    pub spawn_tick: u64, // time::ticks() when the task was spawned
+    pub polls: u64,
+    pub runtime: u64, // tsc cycles spent polling the task
+    pub overruns: u64, // polls that took longer than the executor's time slice
+}
+
+struct Registry {
+    tasks: BTreeMap<TaskId, TaskInfo>,
+    finished: VecDeque<TaskInfo>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        tasks: BTreeMap::new(),
+        finished: VecDeque::new(),
+    });
+}
+
+static IDLE_CYCLES: AtomicU64 = AtomicU64::new(0);
+
+// ================= EXECUTOR SIDE
+
+pub(crate) fn register(id: TaskId, name: &str, priority: Priority) {
+    REGISTRY.lock().tasks.insert(id, TaskInfo {
+        id,
+        name: String::from(name),
+        priority,
+        state: TaskState::Ready,
+        spawn_tick: time::ticks(),
+        polls: 0,
+        runtime: 0,
+        overruns: 0,
+    });
+}
+
+pub(crate) fn set_state(id: TaskId, state: TaskState) {
+    if let Some(info) = REGISTRY.lock().tasks.get_mut(&id) {
+        info.state = state;
+    }
+}
+
+/// Adds a poll to the run time of a task, `overrun` if it took longer than a time slice
+pub(crate) fn record_poll(id: TaskId, cycles: u64, overrun: bool, finished: bool) {
+    let mut registry = REGISTRY.lock();
+    let info = match registry.tasks.get_mut(&id) {
+        Some(info) => info,
+        None => return,
+    };
+    info.polls += 1;
+    info.runtime += cycles;
+    if overrun {
+        info.overruns += 1;
+    }
+    info.state = if finished { TaskState::Finished } else { TaskState::Pending };
+
+    if finished {
+        let info = registry.tasks.remove(&id).unwrap();
+        if registry.finished.len() >= FINISHED_HISTORY {
+            registry.finished.pop_front();
+        }
+        registry.finished.push_back(info);
+    }
+}
+
+pub(crate) fn add_idle_cycles(cycles: u64) {
+    IDLE_CYCLES.fetch_add(cycles, Ordering::Relaxed);
+}
+
+// ================= SHELL SIDE
+
+/// returns how many tasks are alive
+pub fn task_count() -> usize {
+    REGISTRY.lock().tasks.len()
+}
+
+/// A copy of the task table at one point in time
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub tasks: Vec<TaskInfo>, // live tasks followed by recently finished ones
+    pub idle_cycles: u64, // tsc cycles the executor spent halted since boot
+    pub tsc: u64, // the tsc when the snapshot was taken
+}
+
+impl Snapshot {
+    pub fn take() -> Snapshot {
+        let registry = REGISTRY.lock();
+        let mut tasks: Vec<TaskInfo> = registry.tasks.values().cloned().collect();
+        tasks.extend(registry.finished.iter().cloned());
+        Snapshot {
+            tasks,
+            idle_cycles: IDLE_CYCLES.load(Ordering::Relaxed),
+            tsc: time::rdtsc(),
+        }
+    }
+
+    /// returns the share of the cpu (0-100%) `id` used since `earlier`
+    pub fn cpu_percent(&self, earlier: &Snapshot, id: TaskId) -> u64 {
+        let elapsed = self.tsc.wrapping_sub(earlier.tsc);
+        if elapsed == 0 {
+            return 0;
+        }
+        let now = self.tasks.iter().find(|t| t.id == id).map(|t| t.runtime).unwrap_or(0);
+        let before = earlier.tasks.iter().find(|t| t.id == id).map(|t| t.runtime).unwrap_or(0);
+        now.saturating_sub(before) * 100 / elapsed
+    }
+
+    /// returns the share of the cpu (0-100%) spent idle since `earlier`
+    pub fn idle_percent(&self, earlier: &Snapshot) -> u64 {
+        let elapsed = self.tsc.wrapping_sub(earlier.tsc);
+        if elapsed == 0 {
+            return 0;
+        }
+        self.idle_cycles.saturating_sub(earlier.idle_cycles) * 100 / elapsed
+    }
+}
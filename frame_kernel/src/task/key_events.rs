@@ -0,0 +1,207 @@
+// Structured keyboard input for applications
+//
+// The keyboard task publishes every key press and release here. Any number of tasks can
+// subscribe to the events, and Ctrl-C/Ctrl-D/Ctrl-Z are turned into signals for the task in
+// the foreground (the last one to claim it). Both only see keys typed while their virtual
+// console is on the screen. A full screen program can also grab the keyboard of its console, so
+// keys stop going to the line editor.
+
+use alloc::vec::Vec;
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+};
+
+use futures_util::stream::Stream;
+use lazy_static::lazy_static;
+use pc_keyboard::KeyCode;
+use spin::Mutex;
+
+use super::sync::mpsc::{self, Receiver, Sender, TrySendError, UnboundedSender};
+use crate::vga_textmode;
+
+/// The modifier keys held down when a key event happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyModifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool, // either alt key, including AltGr
+    pub meta: bool, // the windows keys
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEvent {
+    Pressed(KeyInfo),
+    Released(KeyInfo),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyInfo {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+    pub char: Option<char>, // the character typed with the current layout, None for releases
+}
+
+impl KeyEvent {
+    pub fn info(&self) -> &KeyInfo {
+        match self {
+            KeyEvent::Pressed(info) | KeyEvent::Released(info) => info,
+        }
+    }
+
+    pub fn is_pressed(&self) -> bool {
+        match self {
+            KeyEvent::Pressed(_) => true,
+            KeyEvent::Released(_) => false,
+        }
+    }
+}
+
+/// Control keys delivered to the foreground task instead of being typed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    Interrupt, // Ctrl-C
+    Eof, // Ctrl-D
+    Suspend, // Ctrl-Z
+}
+
+impl Signal {
+    /// returns the signal a ctrl + `c` key press stands for
+    pub fn from_ctrl_char(c: char) -> Option<Signal> {
+        match c.to_ascii_lowercase() {
+            'c' => Some(Signal::Interrupt),
+            'd' => Some(Signal::Eof),
+            'z' => Some(Signal::Suspend),
+            _ => None,
+        }
+    }
+}
+
+lazy_static! {
+    // (console, sender)
+    static ref SUBSCRIBERS: Mutex<Vec<(usize, Sender<KeyEvent>)>> = Mutex::new(Vec::new());
+    // (claim id, console, sender)
+    static ref FOREGROUND: Mutex<Vec<(u64, usize, UnboundedSender<Signal>)>> = Mutex::new(Vec::new());
+    // (grab id, console)
+    static ref GRABS: Mutex<Vec<(u64, usize)>> = Mutex::new(Vec::new());
+}
+
+/// how many key events a subscriber can fall behind, later ones are dropped until it reads
+const SUBSCRIBER_BUFFER: usize = 128;
+
+static NEXT_FOREGROUND_ID: AtomicU64 = AtomicU64::new(0);
+static NEXT_GRAB_ID: AtomicU64 = AtomicU64::new(0);
+
+// ================= KEYBOARD SIDE
+
+/// Sends a key event typed on a console to its subscribers, forgetting the ones that are gone
+/// and skipping the ones that are SUBSCRIBER_BUFFER events behind
+pub(crate) fn publish(console: usize, event: KeyEvent) {
+    SUBSCRIBERS.lock().retain(|(on, sender)| {
+        *on != console || !matches!(sender.try_send(event), Err(TrySendError::Closed(_)))
+    });
+}
+
+/// Delivers a signal to the foreground task of a console
+/// returns false if no task on the console has claimed the foreground
+pub(crate) fn send_signal(console: usize, signal: Signal) -> bool {
+    let mut foreground = FOREGROUND.lock();
+    while let Some(i) = foreground.iter().rposition(|(_, on, _)| *on == console) {
+        if foreground[i].2.send(signal).is_ok() {
+            return true;
+        }
+        foreground.remove(i); // its Foreground was dropped without cleaning up
+    }
+    false
+}
+
+/// returns true if a task has claimed the foreground of a console
+pub(crate) fn has_foreground(console: usize) -> bool {
+    FOREGROUND.lock().iter().any(|(_, on, _)| *on == console)
+}
+
+/// returns true if a task has grabbed the keyboard of a console
+pub(crate) fn is_grabbed(console: usize) -> bool {
+    GRABS.lock().iter().any(|(_, on)| *on == console)
+}
+
+// ================= CONSUMER SIDE
+
+/// A stream of the key events after the call to subscribe()
+pub struct KeyEventStream {
+    events: Receiver<KeyEvent>,
+}
+
+/// Subscribes to the key events of the console the calling task is on
+/// a subscriber that stops reading misses the events after the first SUBSCRIBER_BUFFER ones
+pub fn subscribe() -> KeyEventStream {
+    let (sender, events) = mpsc::channel(SUBSCRIBER_BUFFER);
+    SUBSCRIBERS.lock().push((vga_textmode::output_console(), sender));
+    KeyEventStream { events }
+}
+
+impl KeyEventStream {
+    pub async fn next_event(&mut self) -> Option<KeyEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+/// The foreground claim of a task, signals go to the most recent claim until it is dropped
+pub struct Foreground {
+    id: u64,
+    signals: Receiver<Signal>,
+}
+
+/// Makes the calling task the foreground task of its console
+pub fn claim_foreground() -> Foreground {
+    let id = NEXT_FOREGROUND_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, signals) = mpsc::unbounded();
+    FOREGROUND.lock().push((id, vga_textmode::output_console(), sender));
+    Foreground { id, signals }
+}
+
+impl Foreground {
+    /// Waits for the next signal
+    pub async fn signal(&mut self) -> Option<Signal> {
+        self.signals.recv().await
+    }
+
+    pub fn try_signal(&mut self) -> Option<Signal> {
+        self.signals.try_recv()
+    }
+}
+
+impl Drop for Foreground {
+    fn drop(&mut self) {
+        FOREGROUND.lock().retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+/// A grab of the keyboard, keys typed on the console only go to key event subscribers and
+/// signals (and Alt+F1..F6 still switches consoles) until it is dropped
+pub struct Grab {
+    id: u64,
+}
+
+/// Grabs the keyboard of the console the calling task is on, for programs that read every key
+/// themselves instead of lines (like the ones made with crate::ui)
+pub fn grab_keyboard() -> Grab {
+    let id = NEXT_GRAB_ID.fetch_add(1, Ordering::Relaxed);
+    GRABS.lock().push((id, vga_textmode::output_console()));
+    Grab { id }
+}
+
+impl Drop for Grab {
+    fn drop(&mut self) {
+        GRABS.lock().retain(|(id, _)| *id != self.id);
+    }
+}
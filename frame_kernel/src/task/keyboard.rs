@@ -1,3 +1,4 @@
+use alloc::{string::String, vec::Vec};
 use core::{
     pin::Pin,
     task::{Context, Poll},
@@ -9,12 +10,15 @@ use futures_util::{
     stream::{Stream, StreamExt},
     task::AtomicWaker,
 };
-use lazy_static::lazy_static;
-use pc_keyboard::{DecodedKey, HandleControl, Keyboard, KeyCode, layouts, ScancodeSet1};
-use spin::Mutex;
+use pc_keyboard::{DecodedKey, HandleControl, Keyboard, KeyCode, KeyState, layouts, ScancodeSet1, ScancodeSet2};
+use x86_64::instructions::interrupts;
 
-use crate::{clear_vga, print, println};
-use crate::vga_textmode::get_writer;
+use crate::{println, ps2};
+use crate::task::key_events::{self, KeyEvent, KeyInfo, Signal};
+use crate::task::keymap::KeyDecoder;
+use crate::task::line_editor::LineEditor;
+use crate::task::sync::mpsc::UnboundedSender;
+use crate::vga_textmode::{self, active_writer, Scope, CONSOLE_COUNT, Writer};
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -71,74 +75,150 @@ impl Stream for ScancodeStream {
     }
 }
 
-struct KSBool {
-    ks: bool
+/// Turns scancodes of the set the keyboard was put in by ps2::init() into key events
+///
+/// Only the scancode half of pc-keyboard is used, the KeyDecoder applies the selected layout.
+enum ScancodeDecoder {
+    Set1(Keyboard<layouts::Us104Key, ScancodeSet1>),
+    Set2(Keyboard<layouts::Us104Key, ScancodeSet2>),
 }
 
-impl KSBool {
-    pub fn new(val: bool) -> KSBool {
-        KSBool {
-            ks: val
+impl ScancodeDecoder {
+    fn new() -> ScancodeDecoder {
+        match ps2::scancode_set() {
+            2 => ScancodeDecoder::Set2(Keyboard::new(layouts::Us104Key, ScancodeSet2, HandleControl::Ignore)),
+            _ => ScancodeDecoder::Set1(Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)),
         }
     }
 
-    pub fn set(&mut self, val: bool) {
-        self.ks = val;
+    fn add_byte(&mut self, scancode: u8) -> Result<Option<pc_keyboard::KeyEvent>, pc_keyboard::Error> {
+        match self {
+            ScancodeDecoder::Set1(keyboard) => keyboard.add_byte(scancode),
+            ScancodeDecoder::Set2(keyboard) => keyboard.add_byte(scancode),
+        }
     }
 }
 
-lazy_static! {
-    pub static ref reset_keystrokes: Mutex<KSBool> = Mutex::new(KSBool::new(false)); // a mutex to allow other screen changing functions to reset the keystroke count of the user
-    // probably a horrible way of doing this. TODO: Redo.
+/// the keys that switch virtual consoles with Alt
+const CONSOLE_KEYS: [KeyCode; CONSOLE_COUNT] = [KeyCode::F1, KeyCode::F2, KeyCode::F3,
+                                                 KeyCode::F4, KeyCode::F5, KeyCode::F6];
+
+/// Handles a key typed while searching the scrollback of the console on the screen
+///
+/// Typing edits the query, Up/Ctrl-F go to older matches and Down to newer ones,
+/// Enter stays where the match is and Esc goes back to where the screen was.
+fn handle_search_key(writer: &mut Writer, key: DecodedKey, ctrl: bool) {
+    match key {
+        DecodedKey::Unicode('\x1b') | DecodedKey::RawKey(KeyCode::Escape) => writer.end_search(false),
+        DecodedKey::Unicode('\n') => writer.end_search(true),
+        DecodedKey::Unicode('\x08') => writer.search_pop(),
+        DecodedKey::Unicode('f') | DecodedKey::Unicode('F') if ctrl => { writer.search_next(true); }
+        DecodedKey::RawKey(KeyCode::ArrowUp) => { writer.search_next(true); }
+        DecodedKey::RawKey(KeyCode::ArrowDown) => { writer.search_next(false); }
+        DecodedKey::Unicode(c) if !ctrl => writer.search_push(c),
+        _ => {}
+    }
 }
 
-pub async fn handle_keypresses() {
+/// Decodes keyboard input for the console on the screen, editing a line on it and sending the
+/// line to `lines[console]` on Enter (consoles without a sender don't take typed input)
+pub async fn handle_keypresses(lines: Vec<UnboundedSender<String>>) {
     let mut scancodes = ScancodeStream::new();
-    let mut keyboard = Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore);
+    let mut keyboard = ScancodeDecoder::new();
+    let mut decoder = KeyDecoder::new();
+    let mut editors: Vec<LineEditor> = (0..lines.len()).map(LineEditor::new).collect();
 
-    let mut keystrokes = 0; // keystrokes tracks how many characters the user has typed to allow backspacing to be handled
+    let mut leds = decoder.leds();
+    if let Err(err) = ps2::set_leds(leds).await {
+        println!("&eWARNING: couldn't set the keyboard LEDs: {:?}", err);
+    }
 
     while let Some(scancode) = scancodes.next().await {
-        if reset_keystrokes { // check if other section requested reset of keystrokes, and accommodate
-            keystrokes = 0;
-            reset_keystrokes.lock().set(true);
-        }
         if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-            if let Some(key) = keyboard.process_keyevent(key_event) {
-                match key {
-                    DecodedKey::Unicode(character) => {
-                        print!("{}", character);
-                        keystrokes += 1;
-                    },
-                    DecodedKey::RawKey(key) => {
-                        match key {
-                            KeyCode::ArrowDown => {
-                                get_writer().lock().move_screen_down();
-                            }
-                            KeyCode::ArrowUp => {
-                                get_writer().lock().move_screen_up();
-                            }
-                            KeyCode::ArrowLeft => {
-                                get_writer().lock().move_cursor_left(true);
-                            }
-                            KeyCode::ArrowRight => {
-                                get_writer().lock().move_cursor_right(true);
-                            }
-                            KeyCode::Backspace => { // handle backspacing based on keystrokes
-                                if keystrokes != 0 {
-                                    // Probably bad, as if the keystrokes gets cleared right before this, it still backspaces... TODO: redo keystrokes
-                                    print!("{:?}", key); // print backspace
-                                    keystrokes -= 1; // remove one key
-                                }
-                            }
-                            key => {
-                                print!("{:?}", key);
-                                keystrokes += 1;
-                            }
+            let code = key_event.code;
+            let pressed = key_event.state == KeyState::Down;
+            let keys = decoder.process(key_event);
+            let modifiers = decoder.key_modifiers();
+            let console = vga_textmode::active_console();
+
+            let info = KeyInfo {
+                code,
+                modifiers,
+                char: keys.iter().filter_map(|key| match key {
+                    DecodedKey::Unicode(c) => Some(*c),
+                    DecodedKey::RawKey(_) => None,
+                }).next(),
+            };
+            key_events::publish(console, if pressed { KeyEvent::Pressed(info) } else { KeyEvent::Released(info) });
+
+            // a debug hotkey, so tests can get the screen without typing over it
+            if pressed && code == KeyCode::F12 && modifiers.ctrl {
+                vga_textmode::take_snapshot(console, Scope::Screen).write_serial();
+            }
+
+            if decoder.leds() != leds {
+                leds = decoder.leds();
+                ps2::set_leds(leds).await.ok(); // a missed LED update isn't worth a warning per key
+            }
+
+            let grabbed = key_events::is_grabbed(console);
+            let busy = key_events::has_foreground(console);
+            for key in keys {
+                let searching = interrupts::without_interrupts(|| active_writer().lock().is_searching());
+                if searching && !grabbed {
+                    interrupts::without_interrupts(|| {
+                        handle_search_key(&mut active_writer().lock(), key, modifiers.ctrl)
+                    });
+                    continue;
+                }
+
+                let line = match key {
+                    DecodedKey::RawKey(code) if modifiers.alt && CONSOLE_KEYS.contains(&code) => {
+                        let index = CONSOLE_KEYS.iter().position(|key| *key == code).unwrap();
+                        vga_textmode::switch_console(index);
+                        None
+                    }
+                    DecodedKey::Unicode(c) if modifiers.ctrl && Signal::from_ctrl_char(c).is_some() => {
+                        let signal = Signal::from_ctrl_char(c).unwrap();
+                        if key_events::send_signal(console, signal) {
+                            None
+                        } else {
+                            // nothing in the foreground, it's for the shell
+                            editors.get_mut(console).and_then(|editor| editor.handle_signal(signal))
                         }
                     }
+                    // a program grabbed the keyboard, it gets the keys from key_events
+                    _ if grabbed => None,
+                    // scroll through the console's history, a page at a time with shift
+                    DecodedKey::RawKey(KeyCode::PageUp) | DecodedKey::RawKey(KeyCode::PageDown) => {
+                        let lines = if modifiers.shift { vga_textmode::screen_size().1 as isize - 1 } else { 1 };
+                        let lines = if code == KeyCode::PageUp { -lines } else { lines };
+                        interrupts::without_interrupts(|| active_writer().lock().scroll_view(lines));
+                        None
+                    }
+                    DecodedKey::RawKey(KeyCode::Home) if modifiers.shift => {
+                        interrupts::without_interrupts(|| active_writer().lock().scroll_to_top());
+                        None
+                    }
+                    DecodedKey::RawKey(KeyCode::End) if modifiers.shift => {
+                        interrupts::without_interrupts(|| active_writer().lock().scroll_to_bottom());
+                        None
+                    }
+                    DecodedKey::Unicode('f') | DecodedKey::Unicode('F') if modifiers.ctrl => {
+                        interrupts::without_interrupts(|| active_writer().lock().start_search());
+                        None
+                    }
+                    // a command is running, typing would echo over its output (like top's)
+                    _ if busy => None,
+                    key => editors.get_mut(console).and_then(|editor| editor.handle_key(key, modifiers.ctrl)),
+                };
+
+                if let Some(line) = line {
+                    if lines[console].send(line).is_err() {
+                        println!("&eWARNING: nothing is reading input lines");
+                    }
                 }
             }
         }
     }
-}
\ No newline at end of file
+}
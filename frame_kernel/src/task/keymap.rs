@@ -0,0 +1,398 @@
+// Keyboard layouts, selectable at runtime
+//
+// pc-keyboard only supports a layout chosen at compile time, so the keyboard task only uses it
+// to turn scancodes into key events, and KeyDecoder turns those into characters with the
+// current layout. Layouts can be one of pc-keyboard's or a custom table loaded from a file.
+//
+// Custom layout files have one key per line, `#` starts a comment:
+//     <scancode> <normal> <shifted> [altgr]
+// The scancode is the (scancode set 1, hex) make code of the key, and each value is a character,
+// `U+XXXX` for any unicode character, or `dead:<accent>` for a dead key. For example:
+//     0x10 q Q @
+//     0x0D dead:´ dead:`
+
+use alloc::{collections::BTreeMap, string::{String, ToString}, vec, vec::Vec};
+
+use lazy_static::lazy_static;
+use pc_keyboard::{DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, KeyboardLayout,
+                  layouts, Modifiers, ScancodeSet, ScancodeSet1};
+use spin::Mutex;
+
+use super::key_events::KeyModifiers;
+use crate::ps2::Leds;
+
+/// The layouts built into pc-keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Us,
+    Uk,
+    De,
+    Dvorak,
+    Azerty,
+    Jis,
+}
+
+impl Builtin {
+    pub const ALL: [Builtin; 6] = [Builtin::Us, Builtin::Uk, Builtin::De, Builtin::Dvorak,
+                                   Builtin::Azerty, Builtin::Jis];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Us => "us",
+            Builtin::Uk => "uk",
+            Builtin::De => "de",
+            Builtin::Dvorak => "dvorak",
+            Builtin::Azerty => "azerty",
+            Builtin::Jis => "jis",
+        }
+    }
+
+    fn map_keycode(self, code: KeyCode, modifiers: &Modifiers) -> DecodedKey {
+        let ctrl = HandleControl::Ignore;
+        match self {
+            Builtin::Us => layouts::Us104Key::map_keycode(code, modifiers, ctrl),
+            Builtin::Uk => layouts::Uk105Key::map_keycode(code, modifiers, ctrl),
+            Builtin::De => layouts::De105Key::map_keycode(code, modifiers, ctrl),
+            Builtin::Dvorak => layouts::Dvorak104Key::map_keycode(code, modifiers, ctrl),
+            Builtin::Azerty => layouts::Azerty::map_keycode(code, modifiers, ctrl),
+            Builtin::Jis => layouts::Jis109Key::map_keycode(code, modifiers, ctrl),
+        }
+    }
+
+    /// returns true if `c` is a dead key in this layout
+    fn is_dead(self, c: char) -> bool {
+        match self {
+            Builtin::De => c == '^' || c == '´' || c == '`',
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeyValue {
+    Char(char),
+    Dead(char), // an accent combined with the next character
+    Raw(KeyCode), // a key without a character, like the arrows or the function keys
+}
+
+#[derive(Debug, Clone, Copy)]
+struct KeyMapping {
+    code: KeyCode,
+    normal: KeyValue,
+    shifted: KeyValue,
+    alt_gr: Option<KeyValue>,
+}
+
+/// A layout loaded from a file, keys it doesn't list fall back to the US layout
+#[derive(Debug, Clone)]
+pub struct CustomLayout {
+    keys: Vec<KeyMapping>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    BadScancode(usize), // the line number
+    BadValue(usize),
+    MissingValue(usize),
+}
+
+impl CustomLayout {
+    /// Parses a layout table (see the top of keymap.rs for the format)
+    pub fn parse(text: &str) -> Result<CustomLayout, ParseError> {
+        let mut keys = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.split_whitespace();
+            let scancode = parts.next().ok_or(ParseError::MissingValue(line_no))?;
+            let scancode = u8::from_str_radix(scancode.trim_start_matches("0x"), 16)
+                .map_err(|_| ParseError::BadScancode(line_no))?;
+            let code = ScancodeSet1::map_scancode(scancode).map_err(|_| ParseError::BadScancode(line_no))?;
+
+            let normal = parse_value(parts.next().ok_or(ParseError::MissingValue(line_no))?, line_no)?;
+            let shifted = parse_value(parts.next().ok_or(ParseError::MissingValue(line_no))?, line_no)?;
+            let alt_gr = match parts.next() {
+                Some(value) => Some(parse_value(value, line_no)?),
+                None => None,
+            };
+
+            keys.push(KeyMapping { code, normal, shifted, alt_gr });
+        }
+        Ok(CustomLayout { keys })
+    }
+
+    fn map_keycode(&self, code: KeyCode, modifiers: &Modifiers) -> KeyValue {
+        let mapping = match self.keys.iter().find(|k| k.code == code) {
+            Some(mapping) => mapping,
+            None => return to_key_value(Builtin::Us, Builtin::Us.map_keycode(code, modifiers)),
+        };
+
+        if modifiers.alt_gr {
+            if let Some(value) = mapping.alt_gr {
+                return value;
+            }
+        }
+
+        // caps lock only affects letters
+        let mut shifted = modifiers.is_shifted();
+        if let KeyValue::Char(c) = mapping.normal {
+            if modifiers.capslock && c.is_alphabetic() {
+                shifted = !shifted;
+            }
+        }
+        if shifted { mapping.shifted } else { mapping.normal }
+    }
+}
+
+fn parse_value(value: &str, line_no: usize) -> Result<KeyValue, ParseError> {
+    let (dead, value) = if value.starts_with("dead:") {
+        (true, &value[5..])
+    } else {
+        (false, value)
+    };
+
+    let c = if value.starts_with("U+") {
+        u32::from_str_radix(&value[2..], 16).ok().and_then(core::char::from_u32)
+    } else {
+        let mut chars = value.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+
+    match c {
+        Some(c) if dead => Ok(KeyValue::Dead(c)),
+        Some(c) => Ok(KeyValue::Char(c)),
+        None => Err(ParseError::BadValue(line_no)),
+    }
+}
+
+fn to_key_value(layout: Builtin, key: DecodedKey) -> KeyValue {
+    match key {
+        DecodedKey::Unicode(c) if layout.is_dead(c) => KeyValue::Dead(c),
+        DecodedKey::Unicode(c) => KeyValue::Char(c),
+        DecodedKey::RawKey(code) => KeyValue::Raw(code),
+    }
+}
+
+// ================= LAYOUT SELECTION
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    Builtin(Builtin),
+    Custom(String),
+}
+
+lazy_static! {
+    static ref CURRENT: Mutex<Layout> = Mutex::new(Layout::Builtin(Builtin::Us));
+    static ref CUSTOM: Mutex<BTreeMap<String, CustomLayout>> = Mutex::new(BTreeMap::new());
+}
+
+/// returns the name of the current layout
+pub fn current() -> String {
+    match &*CURRENT.lock() {
+        Layout::Builtin(layout) => layout.name().to_string(),
+        Layout::Custom(name) => name.clone(),
+    }
+}
+
+/// returns the names of every available layout
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = Builtin::ALL.iter().map(|l| l.name().to_string()).collect();
+    names.extend(CUSTOM.lock().keys().cloned());
+    names
+}
+
+/// Switches to the layout with the given name
+/// returns false if there is no such layout
+pub fn set_layout(name: &str) -> bool {
+    if let Some(layout) = Builtin::ALL.iter().find(|l| l.name() == name) {
+        *CURRENT.lock() = Layout::Builtin(*layout);
+        return true;
+    }
+    if CUSTOM.lock().contains_key(name) {
+        *CURRENT.lock() = Layout::Custom(name.to_string());
+        return true;
+    }
+    false
+}
+
+/// Adds a custom layout, replacing any custom layout with the same name
+pub fn register_layout(name: &str, layout: CustomLayout) {
+    CUSTOM.lock().insert(name.to_string(), layout);
+}
+
+/// Parses a layout table and adds it as a custom layout
+pub fn load_layout(name: &str, text: &str) -> Result<(), ParseError> {
+    register_layout(name, CustomLayout::parse(text)?);
+    Ok(())
+}
+
+// ================= DECODING
+
+/// Turns key events into characters with the current layout, composing dead keys
+pub struct KeyDecoder {
+    modifiers: Modifiers,
+    alt: bool, // left alt, right alt is AltGr in `modifiers`
+    meta: bool,
+    scroll_lock: bool, // not a pc-keyboard modifier, only kept for the LED
+    dead: Option<char>, // a dead key waiting for the next character
+}
+
+impl KeyDecoder {
+    pub fn new() -> KeyDecoder {
+        KeyDecoder {
+            modifiers: Modifiers {
+                lshift: false,
+                rshift: false,
+                lctrl: false,
+                rctrl: false,
+                numlock: true,
+                capslock: false,
+                alt_gr: false,
+            },
+            alt: false,
+            meta: false,
+            scroll_lock: false,
+            dead: None,
+        }
+    }
+
+    pub fn modifiers(&self) -> &Modifiers {
+        &self.modifiers
+    }
+
+    /// returns which lock LEDs should be on
+    pub fn leds(&self) -> Leds {
+        Leds {
+            scroll_lock: self.scroll_lock,
+            num_lock: self.modifiers.numlock,
+            caps_lock: self.modifiers.capslock,
+        }
+    }
+
+    /// returns the modifier state in the form published with key events
+    pub fn key_modifiers(&self) -> KeyModifiers {
+        KeyModifiers {
+            shift: self.modifiers.is_shifted(),
+            ctrl: self.modifiers.is_ctrl(),
+            alt: self.alt || self.modifiers.alt_gr,
+            meta: self.meta,
+        }
+    }
+
+    /// Handles a key event
+    /// returns the decoded keys (two if a dead key could not be combined, none for modifiers)
+    pub fn process(&mut self, event: KeyEvent) -> Vec<DecodedKey> {
+        let down = event.state == KeyState::Down;
+        match event.code {
+            KeyCode::ShiftLeft => self.modifiers.lshift = down,
+            KeyCode::ShiftRight => self.modifiers.rshift = down,
+            KeyCode::ControlLeft => self.modifiers.lctrl = down,
+            KeyCode::ControlRight => self.modifiers.rctrl = down,
+            KeyCode::AltLeft => self.alt = down,
+            KeyCode::AltRight => self.modifiers.alt_gr = down,
+            KeyCode::WindowsLeft | KeyCode::WindowsRight => self.meta = down,
+            KeyCode::CapsLock if down => self.modifiers.capslock = !self.modifiers.capslock,
+            KeyCode::NumpadLock if down => self.modifiers.numlock = !self.modifiers.numlock,
+            KeyCode::ScrollLock if down => self.scroll_lock = !self.scroll_lock,
+            _ if down => return self.decode(event.code),
+            _ => {}
+        }
+        Vec::new()
+    }
+
+    fn decode(&mut self, code: KeyCode) -> Vec<DecodedKey> {
+        let value = match &*CURRENT.lock() {
+            Layout::Builtin(layout) => to_key_value(*layout, layout.map_keycode(code, &self.modifiers)),
+            Layout::Custom(name) => match CUSTOM.lock().get(name) {
+                Some(layout) => layout.map_keycode(code, &self.modifiers),
+                None => KeyValue::Char('\0'),
+            },
+        };
+
+        match (self.dead.take(), value) {
+            (_, KeyValue::Char('\0')) => Vec::new(), // unmapped key
+            (dead, KeyValue::Raw(code)) => {
+                self.dead = dead; // a dead key waits for the next character
+                vec![DecodedKey::RawKey(code)]
+            }
+            (None, KeyValue::Char(c)) => vec![DecodedKey::Unicode(c)],
+            (None, KeyValue::Dead(accent)) => {
+                self.dead = Some(accent);
+                Vec::new()
+            }
+            (Some(accent), KeyValue::Char(' ')) => vec![DecodedKey::Unicode(accent)],
+            (Some(accent), KeyValue::Char(c)) => match compose(accent, c) {
+                Some(composed) => vec![DecodedKey::Unicode(composed)],
+                None => vec![DecodedKey::Unicode(accent), DecodedKey::Unicode(c)],
+            },
+            (Some(accent), KeyValue::Dead(next)) => {
+                if next != accent {
+                    self.dead = Some(next);
+                }
+                vec![DecodedKey::Unicode(accent)] // pressing a dead key twice types the accent
+            }
+        }
+    }
+}
+
+/// Combines an accent from a dead key with a letter
+fn compose(accent: char, c: char) -> Option<char> {
+    let composed = match accent {
+        '`' => match c {
+            'a' => 'à', 'e' => 'è', 'i' => 'ì', 'o' => 'ò', 'u' => 'ù',
+            'A' => 'À', 'E' => 'È', 'I' => 'Ì', 'O' => 'Ò', 'U' => 'Ù',
+            _ => return None,
+        },
+        '´' | '\'' => match c {
+            'a' => 'á', 'e' => 'é', 'i' => 'í', 'o' => 'ó', 'u' => 'ú', 'y' => 'ý',
+            'A' => 'Á', 'E' => 'É', 'I' => 'Í', 'O' => 'Ó', 'U' => 'Ú', 'Y' => 'Ý',
+            _ => return None,
+        },
+        '^' => match c {
+            'a' => 'â', 'e' => 'ê', 'i' => 'î', 'o' => 'ô', 'u' => 'û',
+            'A' => 'Â', 'E' => 'Ê', 'I' => 'Î', 'O' => 'Ô', 'U' => 'Û',
+            _ => return None,
+        },
+        '¨' | '"' => match c {
+            'a' => 'ä', 'e' => 'ë', 'i' => 'ï', 'o' => 'ö', 'u' => 'ü', 'y' => 'ÿ',
+            'A' => 'Ä', 'E' => 'Ë', 'I' => 'Ï', 'O' => 'Ö', 'U' => 'Ü',
+            _ => return None,
+        },
+        '~' => match c {
+            'a' => 'ã', 'n' => 'ñ', 'o' => 'õ',
+            'A' => 'Ã', 'N' => 'Ñ', 'O' => 'Õ',
+            _ => return None,
+        },
+        '¸' => match c {
+            'c' => 'ç', 'C' => 'Ç',
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(composed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn custom_layout_keeps_raw_keys() {
+        register_layout("test", CustomLayout::parse("0x10 a A").unwrap());
+        assert!(set_layout("test"));
+        let mut decoder = KeyDecoder::new();
+        let up = decoder.process(KeyEvent::new(KeyCode::ArrowUp, KeyState::Down));
+        let q = decoder.process(KeyEvent::new(KeyCode::Q, KeyState::Down));
+        set_layout("us");
+
+        assert_eq!(up, vec![DecodedKey::RawKey(KeyCode::ArrowUp)]);
+        assert_eq!(q, vec![DecodedKey::Unicode('a')]);
+    }
+}
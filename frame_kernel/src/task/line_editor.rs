@@ -0,0 +1,235 @@
+use alloc::{string::String, vec::Vec};
+
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts;
+
+use super::key_events::Signal;
+use crate::vga_textmode::{console, screen_size};
+
+/// how many lines are kept for Up/Down history navigation
+const HISTORY_SIZE: usize = 64;
+
+/// An editable input line with history, drawn in place on the screen
+///
+/// The line starts wherever the cursor is when the first key of it is pressed, so
+/// a consumer can print a prompt before waiting for the next line.
+pub struct LineEditor {
+    console: usize, // the virtual console the line is drawn on
+    buffer: Vec<char>,
+    cursor: usize, // position of the cursor in `buffer`
+    start: Option<(u8, usize)>, // where the line starts in the writer's buffer as (column, row)
+    drawn_len: usize, // how many characters were drawn last time, to erase leftovers
+    history: Vec<String>,
+    history_pos: usize, // history.len() while editing a new line
+    saved: Vec<char>, // the new line being edited while browsing the history
+}
+
+impl LineEditor {
+    pub fn new(console: usize) -> LineEditor {
+        LineEditor {
+            console,
+            buffer: Vec::new(),
+            cursor: 0,
+            start: None,
+            drawn_len: 0,
+            history: Vec::new(),
+            history_pos: 0,
+            saved: Vec::new(),
+        }
+    }
+
+    /// Handles a key press
+    /// returns the line when Enter is pressed
+    pub fn handle_key(&mut self, key: DecodedKey, ctrl: bool) -> Option<String> {
+        if self.start.is_none() {
+            let pos = interrupts::without_interrupts(|| console(self.console).lock().cursor());
+            self.start = Some(pos);
+        }
+
+        match key {
+            DecodedKey::Unicode(c) if ctrl => match c.to_ascii_lowercase() {
+                'a' => self.cursor = 0,
+                'e' => self.cursor = self.buffer.len(),
+                'u' => { // delete to the start of the line
+                    self.buffer.drain(..self.cursor);
+                    self.cursor = 0;
+                }
+                'k' => self.buffer.truncate(self.cursor), // delete to the end of the line
+                'w' => { // delete the word before the cursor
+                    let start = self.word_left();
+                    self.buffer.drain(start..self.cursor);
+                    self.cursor = start;
+                }
+                _ => return None,
+            },
+            DecodedKey::Unicode('\n') => return Some(self.finish_line()),
+            DecodedKey::Unicode('\x08') | DecodedKey::RawKey(KeyCode::Backspace) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode('\x7f') | DecodedKey::RawKey(KeyCode::Delete) => {
+                if self.cursor < self.buffer.len() {
+                    self.buffer.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode(c) if !c.is_control() || c == '\t' => {
+                self.buffer.insert(self.cursor, if c == '\t' { ' ' } else { c });
+                self.cursor += 1;
+            }
+            DecodedKey::Unicode(_) => return None,
+            DecodedKey::RawKey(key) => match key {
+                KeyCode::ArrowLeft if ctrl => self.cursor = self.word_left(),
+                KeyCode::ArrowRight if ctrl => self.cursor = self.word_right(),
+                KeyCode::ArrowLeft => self.cursor = self.cursor.saturating_sub(1),
+                KeyCode::ArrowRight => self.cursor = (self.cursor + 1).min(self.buffer.len()),
+                KeyCode::Home => self.cursor = 0,
+                KeyCode::End => self.cursor = self.buffer.len(),
+                KeyCode::ArrowUp => self.history_prev(),
+                KeyCode::ArrowDown => self.history_next(),
+                _ => return None,
+            },
+        }
+
+        self.redraw();
+        None
+    }
+
+    /// Handles a signal key while no task is in the foreground
+    /// returns an empty line when Ctrl-C discards the current one, so the prompt is shown again
+    pub fn handle_signal(&mut self, signal: Signal) -> Option<String> {
+        match signal {
+            Signal::Interrupt => {
+                self.cursor = self.buffer.len();
+                self.redraw();
+                interrupts::without_interrupts(|| {
+                    let mut writer = console(self.console).lock();
+                    writer.write_byte(b'^');
+                    writer.write_byte(b'C');
+                    writer.newline();
+                });
+                self.buffer.clear();
+                self.reset();
+                Some(String::new())
+            }
+            Signal::Eof | Signal::Suspend => None, // the shell doesn't exit or suspend
+        }
+    }
+
+    /// returns the position of the start of the word before the cursor
+    fn word_left(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos > 0 && self.buffer[pos - 1] == ' ' {
+            pos -= 1;
+        }
+        while pos > 0 && self.buffer[pos - 1] != ' ' {
+            pos -= 1;
+        }
+        pos
+    }
+
+    /// returns the position after the end of the word after the cursor
+    fn word_right(&self) -> usize {
+        let mut pos = self.cursor;
+        while pos < self.buffer.len() && self.buffer[pos] == ' ' {
+            pos += 1;
+        }
+        while pos < self.buffer.len() && self.buffer[pos] != ' ' {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn history_prev(&mut self) {
+        if self.history_pos == 0 {
+            return;
+        }
+        if self.history_pos == self.history.len() {
+            self.saved = self.buffer.clone(); // keep the new line to come back to
+        }
+        self.history_pos -= 1;
+        self.buffer = self.history[self.history_pos].chars().collect();
+        self.cursor = self.buffer.len();
+    }
+
+    fn history_next(&mut self) {
+        if self.history_pos >= self.history.len() {
+            return;
+        }
+        self.history_pos += 1;
+        self.buffer = if self.history_pos == self.history.len() {
+            self.saved.clone()
+        } else {
+            self.history[self.history_pos].chars().collect()
+        };
+        self.cursor = self.buffer.len();
+    }
+
+    /// Ends the current line, adding it to the history
+    fn finish_line(&mut self) -> String {
+        self.cursor = self.buffer.len();
+        self.redraw();
+        interrupts::without_interrupts(|| console(self.console).lock().newline());
+
+        let line: String = self.buffer.drain(..).collect();
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            if self.history.len() >= HISTORY_SIZE {
+                self.history.remove(0);
+            }
+            self.history.push(line.clone());
+        }
+
+        self.reset();
+        line
+    }
+
+    /// Gets ready for a new line
+    fn reset(&mut self) {
+        self.cursor = 0;
+        self.start = None;
+        self.drawn_len = 0;
+        self.history_pos = self.history.len();
+        self.saved.clear();
+    }
+
+    /// returns the (column, row) of the character `offset` characters into the line
+    fn position(start: (u8, usize), offset: usize) -> (u8, usize) {
+        let width = screen_size().0 as usize;
+        let cells = start.0 as usize + offset;
+        ((cells % width) as u8, start.1 + cells / width)
+    }
+
+    /// Draws the line over its previous contents and places the cursor
+    fn redraw(&mut self) {
+        let start = match self.start {
+            Some(start) => start,
+            None => return,
+        };
+        let buffer = &self.buffer;
+        let drawn_len = self.drawn_len;
+
+        let start = interrupts::without_interrupts(|| {
+            let mut writer = console(self.console).lock();
+            writer.set_cursor(start.0, start.1);
+            for c in buffer.iter() {
+                writer.put_char(*c); // without interpreting color codes
+            }
+            for _ in buffer.len()..drawn_len {
+                writer.write_byte(b' '); // erase what is left of a longer line
+            }
+
+            // the writer scrolls its buffer when it fills up, which moves the start of the line
+            let end = Self::position(start, buffer.len().max(drawn_len));
+            let (_, row) = writer.cursor();
+            let start = (start.0, start.1 - end.1.saturating_sub(row).min(start.1));
+
+            let (col, row) = Self::position(start, self.cursor);
+            writer.set_cursor(col, row);
+            start
+        });
+
+        self.start = Some(start);
+        self.drawn_len = self.buffer.len();
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::boxed::Box;
+use alloc::{boxed::Box, string::{String, ToString}};
 use core::{
     future::Future,
     pin::Pin,
@@ -6,22 +6,84 @@ use core::{
     task::{Context, Poll},
 };
 
+use crate::vga_textmode;
+
 pub mod executor;
 pub mod keyboard;
+pub mod timer;
+pub mod sync;
+pub mod spawner;
+pub mod info;
+pub mod line_editor;
+pub mod keymap;
+pub mod key_events;
+pub mod mouse;
+
+/// The priority of a task, higher priorities are polled more often
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low = 0,
+    Normal = 1,
+    High = 2,
+}
+
+impl Priority {
+    pub const COUNT: usize = 3;
+}
 
 pub struct Task {
     id: TaskId,
+    name: String, // shown in diagnostics
+    priority: Priority,
+    console: usize, // the virtual console print! writes to while the task runs
     future: Pin<Box<dyn Future<Output = ()>>>,
 }
 
 impl Task {
     pub fn new(future: impl Future<Output = ()> + 'static) -> Task {
+        Task::with_priority(future, Priority::Normal)
+    }
+
+    /// Creates a task on the console print! is writing to, so tasks spawned by a task share its console
+    pub fn with_priority(future: impl Future<Output = ()> + 'static, priority: Priority) -> Task {
+        let id = TaskId::new();
         Task {
-            id: TaskId::new(),
+            id,
+            name: spawner::default_name(id),
+            priority,
+            console: vga_textmode::output_console(),
             future: Box::pin(future),
         }
     }
 
+    /// Names the task (the default name is `task-<id>`)
+    pub fn with_name(mut self, name: &str) -> Task {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Puts the task on a virtual console
+    pub fn with_console(mut self, console: usize) -> Task {
+        self.console = console;
+        self
+    }
+
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    pub fn console(&self) -> usize {
+        self.console
+    }
+
     fn poll(&mut self, context: &mut Context) -> Poll<()> {
         self.future.as_mut().poll(context)
     }
@@ -35,4 +97,8 @@ impl TaskId {
         static NEXT_ID: AtomicU64 = AtomicU64::new(0);
         TaskId(NEXT_ID.fetch_add(1, Ordering::Relaxed))
     }
+
+    pub fn as_u64(self) -> u64 {
+        self.0
+    }
 }
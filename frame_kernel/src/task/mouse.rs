@@ -0,0 +1,235 @@
+// PS/2 mouse on the 8042's auxiliary port (IRQ12)
+//
+// The interrupt handler only queues the bytes, packets are put together by MouseStream.
+// handle_mouse() uses the events to move a pointer over the console, select text with the
+// left button and scroll with the wheel.
+
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicU8, Ordering},
+    task::{Context, Poll},
+};
+
+use conquer_once::spin::OnceCell;
+use crossbeam_queue::ArrayQueue;
+use futures_util::{
+    stream::{Stream, StreamExt},
+    task::AtomicWaker,
+};
+use x86_64::instructions::{interrupts, port::Port};
+
+use crate::println;
+use crate::ps2::{self, Ps2Error};
+use crate::vga_textmode::{active_writer, screen_size};
+
+static BYTE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
+static WAKER: AtomicWaker = AtomicWaker::new();
+static PACKET_SIZE: AtomicU8 = AtomicU8::new(3); // 4 for IntelliMouse packets with a wheel byte
+
+/// how many mouse counts move the pointer one cell
+const COUNTS_PER_COL: i16 = 8;
+const COUNTS_PER_ROW: i16 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseButtons {
+    pub left: bool,
+    pub right: bool,
+    pub middle: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    pub buttons: MouseButtons, // the buttons held down after the event
+    pub dx: i16, // positive is right
+    pub dy: i16, // positive is up
+    pub wheel: i8, // positive is towards the user (scrolling down)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseType {
+    Standard,
+    IntelliMouse, // has a wheel
+}
+
+fn write_mouse(byte: u8) -> Result<(), Ps2Error> {
+    ps2::write_device(true, byte)
+}
+
+fn set_sample_rate(rate: u8) -> Result<(), Ps2Error> {
+    write_mouse(0xF3)?;
+    write_mouse(rate)
+}
+
+/// Sets up the mouse on the controller's second port and unmasks IRQ12
+///
+/// Must be called after ps2::init().
+pub fn init() -> Result<MouseType, Ps2Error> {
+    if !ps2::has_aux_port() {
+        return Err(Ps2Error::NoAuxPort);
+    }
+    BYTE_QUEUE.try_init_once(|| ArrayQueue::new(256)).ok();
+
+    let mouse_type = interrupts::without_interrupts(|| -> Result<MouseType, Ps2Error> {
+        write_mouse(0xF6)?; // defaults
+
+        // this sample rate sequence turns on the wheel of an IntelliMouse, which then reports id 3
+        set_sample_rate(200)?;
+        set_sample_rate(100)?;
+        set_sample_rate(80)?;
+        write_mouse(0xF2)?; // get id
+        let mouse_type = match ps2::read_data()? {
+            3 => MouseType::IntelliMouse,
+            _ => MouseType::Standard,
+        };
+
+        write_mouse(0xF4)?; // enable reporting
+        Ok(mouse_type)
+    })?;
+    ps2::enable_aux_interrupt()?;
+
+    PACKET_SIZE.store(if mouse_type == MouseType::IntelliMouse { 4 } else { 3 }, Ordering::SeqCst);
+
+    // unmask IRQ2 (the cascade) on the master PIC and IRQ12 on the slave
+    unsafe {
+        let mut master: Port<u8> = Port::new(0x21);
+        let current = master.read();
+        master.write(current & !(1 << 2));
+        let mut slave: Port<u8> = Port::new(0xA1);
+        let current = slave.read();
+        slave.write(current & !(1 << 4));
+    }
+
+    Ok(mouse_type)
+}
+
+// ================= EVENTS
+
+/// Called by the mouse interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn add_byte(byte: u8) {
+    if let Ok(queue) = BYTE_QUEUE.try_get() {
+        if queue.push(byte).is_ok() {
+            WAKER.wake();
+        } // a full queue drops the byte, MouseStream resynchronises on the next packet
+    }
+}
+
+pub struct MouseStream {
+    packet: [u8; 4],
+    len: usize, // bytes of `packet` received so far
+}
+
+impl MouseStream {
+    /// Must be called after init()
+    pub fn new() -> Self {
+        MouseStream {
+            packet: [0; 4],
+            len: 0,
+        }
+    }
+
+    /// Adds a byte to the current packet
+    /// returns the event when the packet is complete
+    fn add(&mut self, byte: u8) -> Option<MouseEvent> {
+        if self.len == 0 && byte & 0b1000 == 0 {
+            return None; // bit 3 is always set in the first byte, we are out of sync
+        }
+        self.packet[self.len] = byte;
+        self.len += 1;
+        if self.len < PACKET_SIZE.load(Ordering::Relaxed) as usize {
+            return None;
+        }
+        self.len = 0;
+
+        let flags = self.packet[0];
+        let mut dx = self.packet[1] as i16 - if flags & 0b1_0000 != 0 { 256 } else { 0 };
+        let mut dy = self.packet[2] as i16 - if flags & 0b10_0000 != 0 { 256 } else { 0 };
+        if flags & 0b1100_0000 != 0 {
+            dx = 0; // overflowed, the motion is meaningless
+            dy = 0;
+        }
+        let wheel = if PACKET_SIZE.load(Ordering::Relaxed) == 4 {
+            self.packet[3] as i8
+        } else {
+            0
+        };
+
+        Some(MouseEvent {
+            buttons: MouseButtons {
+                left: flags & 0b1 != 0,
+                right: flags & 0b10 != 0,
+                middle: flags & 0b100 != 0,
+            },
+            dx,
+            dy,
+            wheel,
+        })
+    }
+}
+
+impl Stream for MouseStream {
+    type Item = MouseEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<MouseEvent>> {
+        let queue = BYTE_QUEUE
+            .try_get()
+            .expect("mouse byte queue not initialized");
+
+        loop {
+            let byte = match queue.pop() {
+                Ok(byte) => byte,
+                Err(_) => {
+                    WAKER.register(&cx.waker());
+                    match queue.pop() {
+                        Ok(byte) => {
+                            WAKER.take();
+                            byte
+                        }
+                        Err(_) => return Poll::Pending,
+                    }
+                }
+            };
+            if let Some(event) = self.add(byte) {
+                return Poll::Ready(Some(event));
+            }
+        }
+    }
+}
+
+// ================= POINTER
+
+/// Moves the console pointer with the mouse, selects text with the left button and scrolls
+/// the console with the wheel
+pub async fn handle_mouse() {
+    let mut events = MouseStream::new();
+    // the pointer position in mouse counts, so slow movements add up
+    let (width, height) = screen_size();
+    let mut x: i16 = (width as i16 / 2) * COUNTS_PER_COL;
+    let mut y: i16 = (height as i16 / 2) * COUNTS_PER_ROW;
+    let mut left_down = false;
+
+    while let Some(event) = events.next().await {
+        let (width, height) = screen_size(); // bigger on a framebuffer
+        x = (x + event.dx).max(0).min(width as i16 * COUNTS_PER_COL - 1);
+        y = (y - event.dy).max(0).min(height as i16 * COUNTS_PER_ROW - 1);
+        let col = (x / COUNTS_PER_COL) as u8;
+        let row = (y / COUNTS_PER_ROW) as u8;
+
+        interrupts::without_interrupts(|| {
+            let mut writer = active_writer().lock();
+            if event.wheel != 0 {
+                writer.scroll_view(event.wheel as isize * 3);
+            }
+            match (left_down, event.buttons.left) {
+                (false, true) => writer.start_selection(col, row),
+                (true, true) => writer.extend_selection(col, row),
+                (true, false) => writer.finish_selection(),
+                (false, false) => {}
+            }
+            writer.set_pointer(col, row);
+        });
+        left_down = event.buttons.left;
+    }
+    println!("&eWARNING: mouse stream ended");
+}
@@ -0,0 +1,152 @@
+use alloc::{collections::VecDeque, string::String, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use futures_util::task::AtomicWaker;
+use spin::Mutex;
+
+use super::{Priority, Task, TaskId};
+
+/// Tasks spawned through a Spawner, picked up by the executor on its next loop
+pub(crate) type SpawnQueue = Arc<Mutex<VecDeque<Task>>>;
+
+/// A handle for spawning tasks on an Executor from inside running tasks
+///
+/// Get one with Executor::spawner() and clone it into the tasks that need it.
+#[derive(Clone)]
+pub struct Spawner {
+    queue: SpawnQueue,
+}
+
+impl Spawner {
+    pub(crate) fn new(queue: SpawnQueue) -> Spawner {
+        Spawner { queue }
+    }
+
+    /// Spawns a task with normal priority
+    pub fn spawn<F, T>(&self, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + 'static, T: 'static {
+        self.spawn_with(None, Priority::Normal, future)
+    }
+
+    /// Spawns a task with a name (shown in diagnostics) and priority
+    pub fn spawn_with<F, T>(&self, name: Option<&str>, priority: Priority, future: F) -> JoinHandle<T>
+        where F: Future<Output = T> + 'static, T: 'static {
+        let state = Arc::new(JoinState {
+            output: Mutex::new(None),
+            finished: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            join_waker: AtomicWaker::new(),
+            task_waker: AtomicWaker::new(),
+        });
+
+        let mut task = Task::with_priority(Abortable { future, state: state.clone() }, priority);
+        if let Some(name) = name {
+            task = task.with_name(name);
+        }
+        let id = task.id();
+        self.queue.lock().push_back(task);
+
+        JoinHandle { id, state }
+    }
+}
+
+// ================= JOIN HANDLE
+
+/// The error returned by a JoinHandle whose task was aborted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    Cancelled,
+}
+
+struct JoinState<T> {
+    output: Mutex<Option<T>>,
+    finished: AtomicBool,
+    aborted: AtomicBool,
+    join_waker: AtomicWaker, // the task awaiting the JoinHandle
+    task_waker: AtomicWaker, // the spawned task, woken to make it notice an abort
+}
+
+/// A future that completes with the output of a spawned task
+///
+/// Dropping the handle detaches the task, it keeps running.
+pub struct JoinHandle<T> {
+    id: TaskId,
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn id(&self) -> TaskId {
+        self.id
+    }
+
+    /// returns true if the task has finished or was aborted
+    pub fn is_finished(&self) -> bool {
+        self.state.finished.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the task, it is dropped the next time the executor gets to it
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::SeqCst);
+        self.state.task_waker.wake();
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, JoinError>> {
+        self.state.join_waker.register(cx.waker());
+        if !self.state.finished.load(Ordering::SeqCst) {
+            return Poll::Pending;
+        }
+        match self.state.output.lock().take() {
+            Some(output) => Poll::Ready(Ok(output)),
+            None => Poll::Ready(Err(JoinError::Cancelled)),
+        }
+    }
+}
+
+/// Wraps a spawned future to store its output and stop it when aborted
+struct Abortable<F: Future> {
+    future: F,
+    state: Arc<JoinState<F::Output>>,
+}
+
+impl<F: Future> Future for Abortable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        // the future is never moved out of the pinned wrapper
+        let this = unsafe { self.get_unchecked_mut() };
+        let state = &this.state;
+        state.task_waker.register(cx.waker());
+
+        if state.aborted.load(Ordering::SeqCst) {
+            // returning drops the future through the executor, cancelling it
+            state.finished.store(true, Ordering::SeqCst);
+            state.join_waker.wake();
+            return Poll::Ready(());
+        }
+
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        match future.poll(cx) {
+            Poll::Ready(output) => {
+                *state.output.lock() = Some(output);
+                state.finished.store(true, Ordering::SeqCst);
+                state.join_waker.wake();
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// returns the name used for tasks that weren't given one
+pub(crate) fn default_name(id: TaskId) -> String {
+    alloc::format!("task-{}", id.as_u64())
+}
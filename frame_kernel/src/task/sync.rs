@@ -0,0 +1,31 @@
+// Async synchronisation primitives for kernel tasks
+//
+// Unlike spin::Mutex, these suspend the waiting task instead of spinning, so they can be held
+// across an `.await` without deadlocking the executor. Their internal state is only locked with
+// interrupts disabled, so the operations documented as interrupt safe can be used from
+// interrupt handlers (they never allocate).
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+pub mod semaphore;
+pub mod mutex;
+pub mod rwlock;
+pub mod notify;
+pub mod oneshot;
+pub mod mpsc;
+
+pub use mutex::{Mutex, MutexGuard};
+pub use notify::Notify;
+pub use rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+pub use semaphore::{Semaphore, SemaphorePermit};
+
+/// Locks a spin::Mutex with interrupts disabled, so interrupt handlers can't deadlock on it
+fn with_lock<T, R>(lock: &spin::Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    x86_64::instructions::interrupts::without_interrupts(|| f(&mut lock.lock()))
+}
+
+/// returns a unique id for a waiter in a wait queue
+fn waiter_id() -> u64 {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
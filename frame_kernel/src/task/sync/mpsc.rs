@@ -0,0 +1,205 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll},
+};
+
+use futures_util::{future::poll_fn, stream::Stream, task::AtomicWaker};
+
+use super::semaphore::Semaphore;
+use super::with_lock;
+
+/// The error returned when sending to a channel whose receiver was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrySendError<T> {
+    Full(T),
+    Closed(T),
+}
+
+struct Chan<T> {
+    queue: spin::Mutex<VecDeque<T>>,
+    waker: AtomicWaker, // the receiver's waker
+    senders: AtomicUsize,
+    closed: AtomicBool, // the receiver was dropped
+    capacity: Option<Semaphore>, // free slots, None for unbounded channels
+}
+
+impl<T> Chan<T> {
+    fn push(&self, value: T) {
+        with_lock(&self.queue, |queue| queue.push_back(value));
+        self.waker.wake();
+    }
+}
+
+fn new_chan<T>(capacity: Option<usize>) -> Arc<Chan<T>> {
+    Arc::new(Chan {
+        // bounded channels allocate up front, so sending never allocates
+        queue: spin::Mutex::new(VecDeque::with_capacity(capacity.unwrap_or(0))),
+        waker: AtomicWaker::new(),
+        senders: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+        capacity: capacity.map(Semaphore::new),
+    })
+}
+
+/// Creates a channel that holds at most `capacity` values, senders wait while it is full
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(capacity > 0, "bounded channel capacity must be at least 1");
+    let chan = new_chan(Some(capacity));
+    (Sender { chan: chan.clone() }, Receiver { chan })
+}
+
+/// Creates a channel without a size limit
+pub fn unbounded<T>() -> (UnboundedSender<T>, Receiver<T>) {
+    let chan = new_chan(None);
+    (UnboundedSender { chan: chan.clone() }, Receiver { chan })
+}
+
+// ================= SENDERS
+
+/// The sending half of a bounded channel, can be cloned for more senders
+pub struct Sender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends a value, waiting for space if the channel is full
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.chan.closed.load(Ordering::SeqCst) {
+            return Err(SendError(value));
+        }
+        let capacity = self.chan.capacity.as_ref().unwrap();
+        capacity.acquire().await.forget(); // the receiver returns the slot
+        if self.chan.closed.load(Ordering::SeqCst) {
+            return Err(SendError(value)); // closed while waiting
+        }
+        self.chan.push(value);
+        Ok(())
+    }
+
+    /// Sends a value if there is space, without waiting
+    ///
+    /// Interrupt safe.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        if self.chan.closed.load(Ordering::SeqCst) {
+            return Err(TrySendError::Closed(value));
+        }
+        match self.chan.capacity.as_ref().unwrap().try_acquire() {
+            Some(permit) => {
+                permit.forget();
+                self.chan.push(value);
+                Ok(())
+            }
+            None => Err(TrySendError::Full(value)),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.closed.load(Ordering::SeqCst)
+    }
+}
+
+/// The sending half of an unbounded channel, can be cloned for more senders
+pub struct UnboundedSender<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> UnboundedSender<T> {
+    /// Sends a value
+    ///
+    /// Not interrupt safe, as the queue may need to allocate.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if self.chan.closed.load(Ordering::SeqCst) {
+            return Err(SendError(value));
+        }
+        self.chan.push(value);
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.chan.closed.load(Ordering::SeqCst)
+    }
+}
+
+macro_rules! impl_sender_handle {
+    ($sender:ident) => {
+        impl<T> Clone for $sender<T> {
+            fn clone(&self) -> Self {
+                self.chan.senders.fetch_add(1, Ordering::SeqCst);
+                $sender { chan: self.chan.clone() }
+            }
+        }
+
+        impl<T> Drop for $sender<T> {
+            fn drop(&mut self) {
+                if self.chan.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    self.chan.waker.wake(); // last sender, let the receiver see the end
+                }
+            }
+        }
+    };
+}
+
+impl_sender_handle!(Sender);
+impl_sender_handle!(UnboundedSender);
+
+// ================= RECEIVER
+
+/// The receiving half of a channel, also a Stream of the received values
+pub struct Receiver<T> {
+    chan: Arc<Chan<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// Waits for the next value, returns None once every sender is dropped and the channel is empty
+    pub async fn recv(&mut self) -> Option<T> {
+        poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    /// returns the next value without waiting
+    pub fn try_recv(&mut self) -> Option<T> {
+        let value = with_lock(&self.chan.queue, |queue| queue.pop_front());
+        if value.is_some() {
+            if let Some(capacity) = &self.chan.capacity {
+                capacity.add_permits(1); // the slot is free again
+            }
+        }
+        value
+    }
+
+    pub fn poll_recv(&mut self, cx: &mut Context) -> Poll<Option<T>> {
+        // fast path
+        if let Some(value) = self.try_recv() {
+            return Poll::Ready(Some(value));
+        }
+
+        self.chan.waker.register(cx.waker());
+        match self.try_recv() {
+            Some(value) => Poll::Ready(Some(value)),
+            None if self.chan.senders.load(Ordering::SeqCst) == 0 => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.chan.closed.store(true, Ordering::SeqCst);
+        if let Some(capacity) = &self.chan.capacity {
+            // release senders waiting for space, they will see the channel is closed
+            capacity.add_permits(usize::max_value() / 2);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use super::{waiter_id, with_lock};
+
+struct Waiter {
+    id: u64,
+    waker: Waker,
+    notified: Arc<AtomicBool>,
+}
+
+struct State {
+    permit: bool, // a notify_one() that happened while nobody was waiting
+    waiters: VecDeque<Waiter>,
+}
+
+impl State {
+    fn notify_one(&mut self) {
+        match self.waiters.pop_front() {
+            Some(waiter) => {
+                waiter.notified.store(true, Ordering::SeqCst);
+                waiter.waker.wake();
+            }
+            None => self.permit = true,
+        }
+    }
+}
+
+/// Wakes waiting tasks when an event happens
+///
+/// A notify_one() without any waiters is remembered, so the next notified() completes
+/// immediately and no notification is lost between checking a condition and waiting.
+pub struct Notify {
+    state: spin::Mutex<State>,
+}
+
+impl Notify {
+    pub fn new() -> Notify {
+        Notify {
+            state: spin::Mutex::new(State {
+                permit: false,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// Waits for a notification
+    pub fn notified(&self) -> Notified {
+        Notified {
+            notify: self,
+            waiter: None,
+            done: false,
+        }
+    }
+
+    /// Wakes the first waiting task, or the next one to wait if there are none
+    ///
+    /// Interrupt safe.
+    pub fn notify_one(&self) {
+        with_lock(&self.state, |state| state.notify_one());
+    }
+
+    /// Wakes every task that is currently waiting
+    ///
+    /// Interrupt safe.
+    pub fn notify_waiters(&self) {
+        with_lock(&self.state, |state| {
+            while let Some(waiter) = state.waiters.pop_front() {
+                waiter.notified.store(true, Ordering::SeqCst);
+                waiter.waker.wake();
+            }
+        });
+    }
+}
+
+/// The future returned by Notify::notified()
+pub struct Notified<'a> {
+    notify: &'a Notify,
+    waiter: Option<(u64, Arc<AtomicBool>)>,
+    done: bool,
+}
+
+impl<'a> Future for Notified<'a> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+        let ready = with_lock(&this.notify.state, |state| {
+            match &this.waiter {
+                None => {
+                    if state.permit {
+                        state.permit = false;
+                        return true;
+                    }
+                    let id = waiter_id();
+                    let notified = Arc::new(AtomicBool::new(false));
+                    state.waiters.push_back(Waiter {
+                        id,
+                        waker: cx.waker().clone(),
+                        notified: notified.clone(),
+                    });
+                    this.waiter = Some((id, notified));
+                    false
+                }
+                Some((id, notified)) => {
+                    if notified.load(Ordering::SeqCst) {
+                        return true;
+                    }
+                    if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == *id) {
+                        if !waiter.waker.will_wake(cx.waker()) {
+                            waiter.waker = cx.waker().clone();
+                        }
+                    }
+                    false
+                }
+            }
+        });
+
+        if ready {
+            this.done = true;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a> Drop for Notified<'a> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some((id, notified)) = self.waiter.take() {
+            with_lock(&self.notify.state, |state| {
+                if notified.load(Ordering::SeqCst) {
+                    state.notify_one(); // notified but cancelled, pass it on so it isn't lost
+                } else {
+                    state.waiters.retain(|w| w.id != id);
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use alloc::sync::Arc;
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll},
+};
+
+use futures_util::task::AtomicWaker;
+
+use super::with_lock;
+
+/// The error returned when the Sender is dropped without sending a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+struct Inner<T> {
+    value: spin::Mutex<Option<T>>,
+    waker: AtomicWaker,
+    complete: AtomicBool, // the sender has sent a value or was dropped
+    receiver_dropped: AtomicBool,
+}
+
+/// Creates a channel that sends a single value
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let inner = Arc::new(Inner {
+        value: spin::Mutex::new(None),
+        waker: AtomicWaker::new(),
+        complete: AtomicBool::new(false),
+        receiver_dropped: AtomicBool::new(false),
+    });
+    (Sender { inner: inner.clone() }, Receiver { inner })
+}
+
+pub struct Sender<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Sends the value, returning it if the receiver was dropped
+    ///
+    /// Interrupt safe.
+    pub fn send(self, value: T) -> Result<(), T> {
+        if self.inner.receiver_dropped.load(Ordering::SeqCst) {
+            return Err(value);
+        }
+        with_lock(&self.inner.value, |slot| *slot = Some(value));
+        self.inner.complete.store(true, Ordering::SeqCst);
+        self.inner.waker.wake();
+        Ok(())
+    }
+
+    /// returns true if the receiver was dropped
+    pub fn is_closed(&self) -> bool {
+        self.inner.receiver_dropped.load(Ordering::SeqCst)
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if !self.inner.complete.swap(true, Ordering::SeqCst) {
+            self.inner.waker.wake(); // dropped without sending, let the receiver fail
+        }
+    }
+}
+
+/// A future that completes with the sent value
+pub struct Receiver<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Receiver<T> {
+    /// returns the value if it has been sent, without waiting
+    pub fn try_recv(&mut self) -> Option<T> {
+        with_lock(&self.inner.value, |slot| slot.take())
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<T, RecvError>> {
+        self.inner.waker.register(cx.waker());
+        if !self.inner.complete.load(Ordering::SeqCst) {
+            return Poll::Pending;
+        }
+        match with_lock(&self.inner.value, |slot| slot.take()) {
+            Some(value) => Poll::Ready(Ok(value)),
+            None => Poll::Ready(Err(RecvError)),
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.inner.receiver_dropped.store(true, Ordering::SeqCst);
+    }
+}
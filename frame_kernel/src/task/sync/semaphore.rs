@@ -0,0 +1,199 @@
+use alloc::{collections::VecDeque, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+use super::{waiter_id, with_lock};
+
+struct Waiter {
+    id: u64,
+    needed: usize,
+    waker: Waker,
+    granted: Arc<AtomicBool>, // set once the permits have been handed to the waiter
+}
+
+struct State {
+    permits: usize,
+    waiters: VecDeque<Waiter>, // first come, first served
+}
+
+impl State {
+    /// hands permits to the waiters at the front of the queue while there are enough
+    fn grant_waiters(&mut self) {
+        while let Some(front) = self.waiters.front() {
+            if front.needed > self.permits {
+                break; // keeps the queue fair, later waiters can't skip ahead
+            }
+            let waiter = self.waiters.pop_front().unwrap();
+            self.permits -= waiter.needed;
+            waiter.granted.store(true, Ordering::SeqCst);
+            waiter.waker.wake();
+        }
+    }
+}
+
+/// A counting semaphore, waiters receive permits in the order they asked for them
+pub struct Semaphore {
+    state: spin::Mutex<State>,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Semaphore {
+        Semaphore {
+            state: spin::Mutex::new(State {
+                permits,
+                waiters: VecDeque::new(),
+            }),
+        }
+    }
+
+    /// returns the amount of permits currently available
+    pub fn available_permits(&self) -> usize {
+        with_lock(&self.state, |state| state.permits)
+    }
+
+    /// Waits for a permit, which is returned when the SemaphorePermit is dropped
+    pub fn acquire(&self) -> Acquire {
+        self.acquire_many(1)
+    }
+
+    /// Waits for `n` permits
+    pub fn acquire_many(&self, n: usize) -> Acquire {
+        Acquire {
+            semaphore: self,
+            needed: n,
+            waiter: None,
+            done: false,
+        }
+    }
+
+    /// Takes a permit if one is available without waiting
+    pub fn try_acquire(&self) -> Option<SemaphorePermit> {
+        self.try_acquire_many(1)
+    }
+
+    /// Takes `n` permits if they are available without waiting
+    ///
+    /// Interrupt safe.
+    pub fn try_acquire_many(&self, n: usize) -> Option<SemaphorePermit> {
+        with_lock(&self.state, |state| {
+            if state.waiters.is_empty() && state.permits >= n {
+                state.permits -= n;
+                Some(SemaphorePermit { semaphore: self, permits: n })
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Adds `n` permits, waking waiters that can now continue
+    ///
+    /// Interrupt safe.
+    pub fn add_permits(&self, n: usize) {
+        with_lock(&self.state, |state| {
+            state.permits += n;
+            state.grant_waiters();
+        });
+    }
+}
+
+/// Permits taken from a Semaphore, returned when dropped
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: usize,
+}
+
+impl<'a> SemaphorePermit<'a> {
+    /// Consumes the permits without returning them to the semaphore
+    pub fn forget(mut self) {
+        self.permits = 0;
+    }
+}
+
+impl<'a> Drop for SemaphorePermit<'a> {
+    fn drop(&mut self) {
+        if self.permits > 0 {
+            self.semaphore.add_permits(self.permits);
+        }
+    }
+}
+
+/// The future returned by Semaphore::acquire(...)
+pub struct Acquire<'a> {
+    semaphore: &'a Semaphore,
+    needed: usize,
+    waiter: Option<(u64, Arc<AtomicBool>)>, // set once the future is in the wait queue
+    done: bool,
+}
+
+impl<'a> Future for Acquire<'a> {
+    type Output = SemaphorePermit<'a>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<SemaphorePermit<'a>> {
+        let this = &mut *self;
+        let semaphore = this.semaphore;
+        let needed = this.needed;
+
+        let ready = with_lock(&semaphore.state, |state| {
+            match &this.waiter {
+                None => {
+                    if state.waiters.is_empty() && state.permits >= needed {
+                        state.permits -= needed;
+                        return true;
+                    }
+                    let id = waiter_id();
+                    let granted = Arc::new(AtomicBool::new(false));
+                    state.waiters.push_back(Waiter {
+                        id,
+                        needed,
+                        waker: cx.waker().clone(),
+                        granted: granted.clone(),
+                    });
+                    this.waiter = Some((id, granted));
+                    false
+                }
+                Some((id, granted)) => {
+                    if granted.load(Ordering::SeqCst) {
+                        return true;
+                    }
+                    // still waiting, make sure the latest waker is the one woken
+                    if let Some(waiter) = state.waiters.iter_mut().find(|w| w.id == *id) {
+                        if !waiter.waker.will_wake(cx.waker()) {
+                            waiter.waker = cx.waker().clone();
+                        }
+                    }
+                    false
+                }
+            }
+        });
+
+        if ready {
+            this.done = true;
+            Poll::Ready(SemaphorePermit { semaphore, permits: needed })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<'a> Drop for Acquire<'a> {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Some((id, granted)) = self.waiter.take() {
+            let needed = self.needed;
+            with_lock(&self.semaphore.state, |state| {
+                if granted.load(Ordering::SeqCst) {
+                    state.permits += needed; // cancelled after being granted, give them back
+                } else {
+                    state.waiters.retain(|w| w.id != id);
+                }
+                state.grant_waiters(); // this waiter may have been blocking the queue
+            });
+        }
+    }
+}
@@ -0,0 +1,230 @@
+// Async timers: sleep(...), interval(...) and timeout(...)
+//
+// Pending timers are kept in a map sorted by deadline. The timer interrupt only checks whether
+// the earliest deadline has passed and wakes run_timers(), which wakes the expired timers from
+// task context (so the interrupt handler never touches the heap).
+
+use alloc::{boxed::Box, collections::BTreeMap};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use futures_util::{future::poll_fn, stream::Stream, task::AtomicWaker};
+use lazy_static::lazy_static;
+use spin::Mutex;
+
+use crate::time;
+
+lazy_static! {
+    static ref TIMERS: Mutex<BTreeMap<(u64, u64), Waker>> = Mutex::new(BTreeMap::new()); // (deadline, id) -> waker
+}
+
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::max_value());
+static DUE: AtomicBool = AtomicBool::new(false);
+static WAKER: AtomicWaker = AtomicWaker::new();
+
+/// Called by the timer interrupt handler
+///
+/// Must not block or allocate.
+pub(crate) fn on_tick(now: u64) {
+    if now >= NEXT_DEADLINE.load(Ordering::Relaxed) {
+        DUE.store(true, Ordering::SeqCst);
+        WAKER.wake();
+    }
+}
+
+/// Wakes the tasks waiting on expired timers, must be spawned for timers to work
+pub async fn run_timers() {
+    loop {
+        poll_fn(|cx| {
+            WAKER.register(cx.waker());
+            if DUE.swap(false, Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }).await;
+
+        let now = time::ticks();
+        let mut timers = TIMERS.lock();
+        while let Some(&key) = timers.keys().next() {
+            if key.0 > now {
+                break;
+            }
+            if let Some(waker) = timers.remove(&key) {
+                waker.wake();
+            }
+        }
+        update_next_deadline(&timers);
+    }
+}
+
+fn update_next_deadline(timers: &BTreeMap<(u64, u64), Waker>) {
+    let next = timers.keys().next().map(|key| key.0).unwrap_or(u64::max_value());
+    NEXT_DEADLINE.store(next, Ordering::SeqCst);
+}
+
+fn duration_to_ticks(duration: Duration) -> u64 {
+    time::ms_to_ticks(duration.as_millis() as u64)
+}
+
+/// A registration in the timer map, removed when dropped
+struct TimerEntry {
+    id: u64,
+    deadline: u64, // in ticks
+}
+
+impl TimerEntry {
+    fn new(deadline: u64) -> TimerEntry {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        TimerEntry {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            deadline,
+        }
+    }
+
+    /// returns true if the deadline has passed, otherwise registers the waker
+    fn poll_expired(&self, cx: &mut Context) -> bool {
+        if time::ticks() >= self.deadline {
+            self.cancel();
+            return true;
+        }
+
+        let mut timers = TIMERS.lock();
+        timers.insert((self.deadline, self.id), cx.waker().clone());
+        update_next_deadline(&timers);
+        false
+    }
+
+    fn cancel(&self) {
+        let mut timers = TIMERS.lock();
+        if timers.remove(&(self.deadline, self.id)).is_some() {
+            update_next_deadline(&timers);
+        }
+    }
+
+    /// moves the deadline, removing the old registration
+    fn reset(&mut self, deadline: u64) {
+        self.cancel();
+        self.deadline = deadline;
+    }
+}
+
+impl Drop for TimerEntry {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// ================= SLEEP
+
+/// A future that completes once its deadline has passed
+pub struct Sleep {
+    entry: TimerEntry,
+}
+
+/// Waits for `duration` to pass (with the precision of a timer tick)
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(time::ticks() + duration_to_ticks(duration))
+}
+
+/// Waits until the given tick (see time::ticks())
+pub fn sleep_until(tick: u64) -> Sleep {
+    Sleep {
+        entry: TimerEntry::new(tick),
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        if self.entry.poll_expired(cx) {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+// ================= INTERVAL
+
+/// A stream that yields the current tick every `period`
+///
+/// Missed ticks are skipped instead of being yielded in a burst
+pub struct Interval {
+    entry: TimerEntry,
+    period: u64, // in ticks
+}
+
+/// Creates an interval that first fires after `period`
+pub fn interval(period: Duration) -> Interval {
+    let period = duration_to_ticks(period).max(1);
+    Interval {
+        entry: TimerEntry::new(time::ticks() + period),
+        period,
+    }
+}
+
+impl Interval {
+    /// Waits for the next tick of the interval
+    pub async fn tick(&mut self) -> u64 {
+        poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await.unwrap()
+    }
+}
+
+impl Stream for Interval {
+    type Item = u64;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<u64>> {
+        if !self.entry.poll_expired(cx) {
+            return Poll::Pending;
+        }
+
+        let now = time::ticks();
+        let mut next = self.entry.deadline + self.period;
+        if next <= now {
+            next = now + self.period; // fell behind, skip the missed ticks
+        }
+        self.entry.reset(next);
+        Poll::Ready(Some(now))
+    }
+}
+
+// ================= TIMEOUT
+
+/// The error returned when a timeout expires before its future completes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+/// A future that completes with the output of its inner future, or Elapsed if it takes too long
+pub struct Timeout<F: Future> {
+    future: Pin<Box<F>>,
+    sleep: Sleep,
+}
+
+/// Runs `future`, giving up after `duration`
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future: Box::pin(future),
+        sleep: sleep(duration),
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        if let Poll::Ready(output) = self.future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        match Pin::new(&mut self.sleep).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use core::arch::x86_64::_rdtsc;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use x86_64::instructions::port::Port;
+
+/// how many times per second the timer interrupt fires
+pub const TIMER_HZ: u64 = 100;
+
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+static LAST_TSC: AtomicU64 = AtomicU64::new(0);
+static TSC_PER_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// Programs the PIT to fire the timer interrupt TIMER_HZ times per second
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TIMER_HZ) as u16;
+    unsafe {
+        Port::<u8>::new(0x43).write(0x36); // channel 0, lo/hi byte, square wave
+        let mut data: Port<u8> = Port::new(0x40);
+        data.write((divisor & 0xFF) as u8);
+        data.write((divisor >> 8) as u8);
+    }
+}
+
+/// Called by the timer interrupt handler
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+
+    // measure the tsc frequency against the timer, smoothing out interrupt latency
+    let now = rdtsc();
+    let last = LAST_TSC.swap(now, Ordering::Relaxed);
+    if last != 0 && now > last {
+        let old = TSC_PER_TICK.load(Ordering::Relaxed);
+        let new = if old == 0 { now - last } else { (old * 7 + (now - last)) / 8 };
+        TSC_PER_TICK.store(new, Ordering::Relaxed);
+    }
+}
+
+/// returns the amount of timer ticks since boot
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// returns the time since boot in milliseconds (with the precision of a tick)
+pub fn uptime_ms() -> u64 {
+    ticks() * 1000 / TIMER_HZ
+}
+
+/// converts milliseconds into timer ticks, rounding up
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TIMER_HZ + 999) / 1000
+}
+
+/// Reads the cpu's timestamp counter
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// returns the amount of tsc cycles per millisecond, or 0 if not measured yet
+pub fn tsc_per_ms() -> u64 {
+    TSC_PER_TICK.load(Ordering::Relaxed) * TIMER_HZ / 1000
+}
+
+/// converts a tsc cycle count into microseconds, or 0 if the tsc has not been measured yet
+pub fn tsc_to_us(cycles: u64) -> u64 {
+    let per_ms = tsc_per_ms();
+    if per_ms == 0 {
+        return 0;
+    }
+    cycles * 1000 / per_ms
+}
@@ -0,0 +1,53 @@
+// A widget toolkit for full screen programs on the consoles
+//
+// A program draws its widgets into a Canvas (a grid of cells the size of the screen), in areas
+// cut out with Rect::rows(...) and Rect::columns(...), and shows it with Screen::present(...).
+// The whole screen is drawn again for every frame, widgets only keep their own state (the
+// selected item, the text typed so far), so there is nothing to invalidate.
+//
+// Screen::next_event() gives the key presses on the console (the screen grabs the keyboard, so
+// the shell doesn't see them) and a tick every second for the clock. Keys are handed to the
+// widget with the focus, which Focus moves with Tab.
+//
+// Everything is drawn in the styles of THEME, so the tools built on this look alike.
+
+use crate::task::key_events::KeyInfo;
+
+pub use self::canvas::Canvas;
+pub use self::focus::Focus;
+pub use self::layout::{Constraint, Rect};
+pub use self::screen::{Event, Screen};
+pub use self::theme::{Style, Theme, THEME};
+pub use self::widgets::{Align, Block, Label, List, Menu, ProgressBar, StatusBar, TextInput};
+
+mod canvas;
+mod focus;
+mod layout;
+mod screen;
+mod theme;
+mod widgets;
+
+/// What a widget did with a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Response {
+    Ignored, // the key is not for the widget
+    Handled,
+    Activated, // Enter on the widget (a list item was picked, an input submitted...)
+    Cancelled, // Esc on a widget that can be closed, like a menu
+}
+
+/// Something drawn on a Canvas
+pub trait Widget {
+    /// Draws the widget into an area of the canvas, `focused` if it has the keyboard focus
+    fn draw(&self, canvas: &mut Canvas, area: Rect, focused: bool);
+
+    /// Handles a key press while the widget has the focus
+    fn handle_key(&mut self, _key: &KeyInfo) -> Response {
+        Response::Ignored
+    }
+
+    /// returns where the cursor goes while the widget has the focus, when drawn in `area`
+    fn cursor(&self, _area: Rect) -> Option<(u8, u8)> {
+        None
+    }
+}
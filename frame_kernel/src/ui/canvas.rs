@@ -0,0 +1,99 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::vga_textmode::{glyph, ScreenChar};
+
+use super::{Rect, Style};
+
+/// A grid of cells a frame is drawn into before it is shown
+///
+/// Characters are stored as their glyph in the code page, like print! writes them, and drawing
+/// outside of the grid is cut off.
+pub struct Canvas {
+    cells: Vec<ScreenChar>,
+    width: u8,
+    height: u8,
+}
+
+impl Canvas {
+    pub fn new(width: u8, height: u8) -> Canvas {
+        Canvas {
+            cells: vec![ScreenChar::new(b' ', 0); width as usize * height as usize],
+            width,
+            height,
+        }
+    }
+
+    pub fn width(&self) -> u8 {
+        self.width
+    }
+
+    pub fn height(&self) -> u8 {
+        self.height
+    }
+
+    /// returns the whole canvas as an area
+    pub fn area(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// returns the cells, row after row
+    pub fn cells(&self) -> &[ScreenChar] {
+        &self.cells
+    }
+
+    /// Fills the whole canvas with spaces
+    pub fn clear(&mut self, style: Style) {
+        let blank = ScreenChar::new(b' ', style.attr());
+        for cell in self.cells.iter_mut() {
+            *cell = blank;
+        }
+    }
+
+    /// Draws a character in a cell
+    pub fn put(&mut self, x: u8, y: u8, c: char, style: Style) {
+        if x < self.width && y < self.height {
+            self.cells[y as usize * self.width as usize + x as usize] = ScreenChar::new(glyph(c), style.attr());
+        }
+    }
+
+    /// Draws text on a line, cut off after `max_width` characters
+    /// returns how many cells it took
+    pub fn text(&mut self, x: u8, y: u8, text: &str, max_width: u8, style: Style) -> u8 {
+        let mut width = 0;
+        for c in text.chars().filter(|c| !c.is_control()).take(max_width as usize) {
+            self.put(x.saturating_add(width), y, c, style);
+            width += 1;
+        }
+        width
+    }
+
+    /// Fills an area with a character
+    pub fn fill(&mut self, area: Rect, c: char, style: Style) {
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                self.put(x, y, c, style);
+            }
+        }
+    }
+
+    /// Draws a single line box around the edge of an area
+    pub fn border(&mut self, area: Rect, style: Style) {
+        if area.width < 2 || area.height < 2 {
+            return;
+        }
+        let (right, bottom) = (area.right() - 1, area.bottom() - 1);
+        for x in area.x + 1..right {
+            self.put(x, area.y, '─', style);
+            self.put(x, bottom, '─', style);
+        }
+        for y in area.y + 1..bottom {
+            self.put(area.x, y, '│', style);
+            self.put(right, y, '│', style);
+        }
+        self.put(area.x, area.y, '┌', style);
+        self.put(right, area.y, '┐', style);
+        self.put(area.x, bottom, '└', style);
+        self.put(right, bottom, '┘', style);
+    }
+}
@@ -0,0 +1,51 @@
+use pc_keyboard::KeyCode;
+
+use crate::task::key_events::KeyInfo;
+
+/// Which of a program's widgets has the keyboard focus, by their index in the program
+pub struct Focus {
+    count: usize,
+    current: usize,
+}
+
+impl Focus {
+    /// Gives the focus to the first of `count` widgets
+    pub fn new(count: usize) -> Focus {
+        Focus { count: count.max(1), current: 0 }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    /// returns true if the widget at `index` has the focus
+    pub fn is(&self, index: usize) -> bool {
+        self.current == index
+    }
+
+    pub fn set(&mut self, index: usize) {
+        self.current = index % self.count;
+    }
+
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.count;
+    }
+
+    pub fn prev(&mut self) {
+        self.current = (self.current + self.count - 1) % self.count;
+    }
+
+    /// Moves the focus to the next widget on Tab, or back on Shift+Tab
+    /// returns true if the key was used
+    pub fn handle_key(&mut self, key: &KeyInfo) -> bool {
+        if key.code != KeyCode::Tab {
+            return false;
+        }
+        if key.modifiers.shift {
+            self.prev();
+        } else {
+            self.next();
+        }
+        true
+    }
+}
@@ -0,0 +1,113 @@
+use alloc::vec::Vec;
+
+/// An area of the screen, in cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: u8,
+    pub y: u8,
+    pub width: u8,
+    pub height: u8,
+}
+
+/// How much of an area a part gets when it is split up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Constraint {
+    Fixed(u8), // rows or columns
+    Percent(u8), // of the whole area
+    Fill, // what is left, shared evenly with the other Fills
+}
+
+impl Rect {
+    pub const fn new(x: u8, y: u8, width: u8, height: u8) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// returns the column after the area
+    pub fn right(&self) -> u8 {
+        self.x.saturating_add(self.width)
+    }
+
+    /// returns the row below the area
+    pub fn bottom(&self) -> u8 {
+        self.y.saturating_add(self.height)
+    }
+
+    pub fn contains(&self, x: u8, y: u8) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// returns the area shrunk by `margin` cells on every side
+    pub fn inner(&self, margin: u8) -> Rect {
+        Rect {
+            x: self.x.saturating_add(margin),
+            y: self.y.saturating_add(margin),
+            width: self.width.saturating_sub(margin * 2),
+            height: self.height.saturating_sub(margin * 2),
+        }
+    }
+
+    /// returns an area of (at most) width x height in the middle of this one
+    pub fn centered(&self, width: u8, height: u8) -> Rect {
+        let (width, height) = (width.min(self.width), height.min(self.height));
+        Rect {
+            x: self.x + (self.width - width) / 2,
+            y: self.y + (self.height - height) / 2,
+            width,
+            height,
+        }
+    }
+
+    /// Splits the area into rows, from the top
+    pub fn rows(&self, constraints: &[Constraint]) -> Vec<Rect> {
+        split(self.y, self.height, constraints).into_iter()
+            .map(|(y, height)| Rect { y, height, ..*self })
+            .collect()
+    }
+
+    /// Splits the area into columns, from the left
+    pub fn columns(&self, constraints: &[Constraint]) -> Vec<Rect> {
+        split(self.x, self.width, constraints).into_iter()
+            .map(|(x, width)| Rect { x, width, ..*self })
+            .collect()
+    }
+}
+
+/// Splits `total` cells from `start` into parts, returned as (start, length)
+///
+/// Fixed and Percent parts are taken first, in order, while there is room. Fill parts share
+/// what is left, the first ones getting a cell more if it doesn't divide evenly.
+fn split(start: u8, total: u8, constraints: &[Constraint]) -> Vec<(u8, u8)> {
+    let mut left = total;
+    let mut sizes: Vec<u8> = constraints.iter().map(|constraint| {
+        let size = match *constraint {
+            Constraint::Fixed(size) => size,
+            Constraint::Percent(percent) => (total as u16 * percent.min(100) as u16 / 100) as u8,
+            Constraint::Fill => 0,
+        }.min(left);
+        left -= size;
+        size
+    }).collect();
+
+    let fills = constraints.iter().filter(|constraint| **constraint == Constraint::Fill).count();
+    if fills > 0 {
+        let share = left as usize / fills;
+        let mut extra = left as usize % fills;
+        for (size, constraint) in sizes.iter_mut().zip(constraints) {
+            if *constraint == Constraint::Fill {
+                *size = (share + if extra > 0 { 1 } else { 0 }) as u8;
+                extra = extra.saturating_sub(1);
+            }
+        }
+    }
+
+    let mut pos = start;
+    sizes.into_iter().map(|size| {
+        let part = (pos, size);
+        pos = pos.saturating_add(size);
+        part
+    }).collect()
+}
@@ -0,0 +1,90 @@
+use core::time::Duration;
+
+use futures_util::{future::{select, Either}, pin_mut};
+use x86_64::instructions::interrupts;
+
+use crate::task::key_events::{self, Grab, KeyEvent, KeyEventStream, KeyInfo};
+use crate::task::timer::{interval, Interval};
+use crate::vga_textmode::{self, print_to_console};
+
+use super::{Canvas, Rect};
+
+/// how often next_event() gives a Tick, to redraw the clock
+const TICK: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Key(KeyInfo), // a key press
+    Tick,
+}
+
+/// The console of a full screen program, for as long as the program has it
+///
+/// Making one grabs the keyboard of the calling task's console, dropping it gives the keyboard
+/// back and clears the screen for the shell.
+pub struct Screen {
+    console: usize,
+    canvas: Canvas,
+    keys: KeyEventStream,
+    ticks: Interval,
+    _grab: Grab,
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        let (width, height) = vga_textmode::screen_size();
+        Screen {
+            console: vga_textmode::output_console(),
+            canvas: Canvas::new(width, height),
+            keys: key_events::subscribe(),
+            ticks: interval(TICK),
+            _grab: key_events::grab_keyboard(),
+        }
+    }
+
+    /// returns the canvas to draw the next frame into, remade if the screen changed size
+    /// (a move to or from the framebuffer console)
+    pub fn canvas(&mut self) -> &mut Canvas {
+        let (width, height) = vga_textmode::screen_size();
+        if (width, height) != (self.canvas.width(), self.canvas.height()) {
+            self.canvas = Canvas::new(width, height);
+        }
+        &mut self.canvas
+    }
+
+    /// returns the whole screen as an area
+    pub fn area(&self) -> Rect {
+        self.canvas.area()
+    }
+
+    /// Shows the canvas on the console, with the cursor at a (column, row) or hidden
+    pub fn present(&self, cursor: Option<(u8, u8)>) {
+        interrupts::without_interrupts(|| {
+            vga_textmode::console(self.console).lock()
+                .show_cells(self.canvas.cells(), self.canvas.width() as usize, cursor);
+        });
+    }
+
+    /// Waits for a key press or the next tick
+    /// returns None if the keyboard is gone
+    pub async fn next_event(&mut self) -> Option<Event> {
+        loop {
+            let key = self.keys.next_event();
+            let tick = self.ticks.tick();
+            pin_mut!(key, tick);
+            match select(key, tick).await {
+                Either::Left((Some(KeyEvent::Pressed(info)), _)) => return Some(Event::Key(info)),
+                Either::Left((Some(KeyEvent::Released(_)), _)) => continue,
+                Either::Left((None, _)) => return None,
+                Either::Right(_) => return Some(Event::Tick),
+            }
+        }
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        // reset the colors, clear the screen, home and show the cursor
+        print_to_console(self.console, format_args!("\x1b[0m\x1b[2J\x1b[H\x1b[?25h"));
+    }
+}
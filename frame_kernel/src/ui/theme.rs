@@ -0,0 +1,51 @@
+use crate::vga_textmode::Color;
+
+/// The colors of a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color, // the bright 8 blink in the text mode, unless set_blink_enabled(false)
+}
+
+impl Style {
+    pub const fn new(fg: Color, bg: Color) -> Style {
+        Style { fg, bg }
+    }
+
+    /// returns the attribute byte of the style
+    pub fn attr(&self) -> u8 {
+        (self.bg as u8) << 4 | self.fg as u8
+    }
+}
+
+/// The styles widgets are drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub normal: Style,
+    pub border: Style,
+    pub focused_border: Style, // the border of the widget with the focus
+    pub title: Style,
+    pub selected: Style, // the selected item of a list with the focus
+    pub selected_unfocused: Style,
+    pub input: Style,
+    pub bar_filled: Style,
+    pub bar_empty: Style,
+    pub menu: Style,
+    pub menu_selected: Style,
+    pub status: Style,
+}
+
+pub const THEME: Theme = Theme {
+    normal: Style::new(Color::LightGray, Color::Blue),
+    border: Style::new(Color::LightCyan, Color::Blue),
+    focused_border: Style::new(Color::Yellow, Color::Blue),
+    title: Style::new(Color::White, Color::Blue),
+    selected: Style::new(Color::Black, Color::Cyan),
+    selected_unfocused: Style::new(Color::Black, Color::LightGray),
+    input: Style::new(Color::White, Color::Black),
+    bar_filled: Style::new(Color::LightGreen, Color::Blue),
+    bar_empty: Style::new(Color::Gray, Color::Blue),
+    menu: Style::new(Color::Black, Color::LightGray),
+    menu_selected: Style::new(Color::White, Color::Black),
+    status: Style::new(Color::Black, Color::Cyan),
+};
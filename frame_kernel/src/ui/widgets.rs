@@ -0,0 +1,397 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cell::Cell;
+
+use pc_keyboard::KeyCode;
+
+use crate::rtc;
+use crate::task::info;
+use crate::task::key_events::KeyInfo;
+use crate::time;
+
+use super::{Canvas, Rect, Response, Style, Widget, THEME};
+
+/// Where a line of text goes in its area
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// returns the column text of `len` characters starts at in an area
+fn aligned(area: Rect, len: usize, align: Align) -> u8 {
+    let len = len.min(area.width as usize) as u8;
+    match align {
+        Align::Left => area.x,
+        Align::Center => area.x + (area.width - len) / 2,
+        Align::Right => area.right() - len,
+    }
+}
+
+// ================= BLOCK
+
+/// A box with a title, to put other widgets in
+pub struct Block {
+    title: String,
+}
+
+impl Block {
+    pub fn new(title: &str) -> Block {
+        Block { title: title.to_string() }
+    }
+
+    /// returns the area inside the border
+    pub fn inner(&self, area: Rect) -> Rect {
+        area.inner(1)
+    }
+}
+
+impl Widget for Block {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, focused: bool) {
+        canvas.fill(area, ' ', THEME.normal);
+        canvas.border(area, if focused { THEME.focused_border } else { THEME.border });
+        if !self.title.is_empty() && area.width > 4 {
+            let x = area.x + 1;
+            canvas.put(x, area.y, ' ', THEME.title);
+            let len = canvas.text(x + 1, area.y, &self.title, area.width - 4, THEME.title);
+            canvas.put(x + 1 + len, area.y, ' ', THEME.title);
+        }
+    }
+}
+
+// ================= LABEL
+
+/// Lines of text, cut off where they don't fit
+pub struct Label {
+    pub text: String,
+    pub style: Style,
+    pub align: Align,
+}
+
+impl Label {
+    pub fn new(text: &str) -> Label {
+        Label { text: text.to_string(), style: THEME.normal, align: Align::Left }
+    }
+}
+
+impl Widget for Label {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, _focused: bool) {
+        for (line, y) in self.text.lines().zip(area.y..area.bottom()) {
+            let x = aligned(area, line.chars().count(), self.align);
+            canvas.text(x, y, line, area.width, self.style);
+        }
+    }
+}
+
+// ================= PROGRESS BAR
+
+/// A bar filled up to `value` out of `max`, with the percentage in the middle
+pub struct ProgressBar {
+    pub value: u64,
+    pub max: u64,
+}
+
+impl ProgressBar {
+    pub fn new(max: u64) -> ProgressBar {
+        ProgressBar { value: 0, max }
+    }
+
+    /// returns how full the bar is, 0-100%
+    pub fn percent(&self) -> u64 {
+        if self.max == 0 {
+            return 0;
+        }
+        self.value.min(self.max) * 100 / self.max
+    }
+}
+
+impl Widget for ProgressBar {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, _focused: bool) {
+        let filled = if self.max == 0 {
+            0
+        } else {
+            (area.width as u64 * self.value.min(self.max) / self.max) as u8
+        };
+        let y = area.y + area.height / 2;
+        for x in area.x..area.right() {
+            if x < area.x + filled {
+                canvas.put(x, y, '█', THEME.bar_filled);
+            } else {
+                canvas.put(x, y, '░', THEME.bar_empty);
+            }
+        }
+        let percent = format!(" {}% ", self.percent());
+        let x = aligned(area, percent.len(), Align::Center);
+        canvas.text(x, y, &percent, area.width, THEME.title);
+    }
+}
+
+// ================= LIST
+
+/// A scrollable list of lines with one of them selected
+pub struct List {
+    items: Vec<String>,
+    selected: usize,
+    offset: Cell<usize>, // the first item on the screen, follows the selection when drawn
+    page: Cell<u8>, // how many items fit, as last drawn
+}
+
+impl List {
+    pub fn new(items: Vec<String>) -> List {
+        List { items, selected: 0, offset: Cell::new(0), page: Cell::new(1) }
+    }
+
+    pub fn items(&self) -> &[String] {
+        &self.items
+    }
+
+    /// Replaces the items, keeping the selection where it is if it is still on the list
+    pub fn set_items(&mut self, items: Vec<String>) {
+        self.items = items;
+        self.selected = self.selected.min(self.items.len().saturating_sub(1));
+    }
+
+    /// returns the index of the selected item, None if the list is empty
+    pub fn selected(&self) -> Option<usize> {
+        if self.items.is_empty() { None } else { Some(self.selected) }
+    }
+
+    pub fn select(&mut self, index: usize) {
+        self.selected = index.min(self.items.len().saturating_sub(1));
+    }
+
+    /// Moves the selection by some items (negative is up)
+    fn move_selection(&mut self, by: isize) {
+        let last = self.items.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + by).max(0).min(last) as usize;
+    }
+}
+
+impl Widget for List {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, focused: bool) {
+        let rows = area.height as usize;
+        self.page.set(area.height.max(1));
+        let mut offset = self.offset.get().min(self.items.len().saturating_sub(rows));
+        if self.selected < offset {
+            offset = self.selected;
+        } else if rows > 0 && self.selected >= offset + rows {
+            offset = self.selected + 1 - rows;
+        }
+        self.offset.set(offset);
+
+        for (index, y) in (offset..self.items.len()).zip(area.y..area.bottom()) {
+            let style = match (index == self.selected, focused) {
+                (true, true) => THEME.selected,
+                (true, false) => THEME.selected_unfocused,
+                _ => THEME.normal,
+            };
+            canvas.fill(Rect::new(area.x, y, area.width, 1), ' ', style);
+            canvas.text(area.x, y, &self.items[index], area.width, style);
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyInfo) -> Response {
+        let page = self.page.get() as isize;
+        match key.code {
+            KeyCode::ArrowUp => self.move_selection(-1),
+            KeyCode::ArrowDown => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-page),
+            KeyCode::PageDown => self.move_selection(page),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.move_selection(isize::max_value()),
+            KeyCode::Enter if !self.items.is_empty() => return Response::Activated,
+            _ => return Response::Ignored,
+        }
+        Response::Handled
+    }
+}
+
+// ================= TEXT INPUT
+
+/// A line of text being typed
+pub struct TextInput {
+    text: Vec<char>,
+    cursor: usize, // position of the cursor in `text`
+    offset: Cell<usize>, // the first character on the screen, follows the cursor when drawn
+}
+
+impl TextInput {
+    pub fn new() -> TextInput {
+        TextInput { text: Vec::new(), cursor: 0, offset: Cell::new(0) }
+    }
+
+    pub fn value(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn set_value(&mut self, value: &str) {
+        self.text = value.chars().collect();
+        self.cursor = self.text.len();
+    }
+
+    pub fn clear(&mut self) {
+        self.set_value("");
+    }
+
+    /// returns the first character drawn in an area `width` cells wide
+    fn scroll(&self, width: u8) -> usize {
+        let width = width.max(1) as usize;
+        let mut offset = self.offset.get();
+        if self.cursor < offset {
+            offset = self.cursor;
+        } else if self.cursor >= offset + width {
+            offset = self.cursor + 1 - width;
+        }
+        offset
+    }
+}
+
+impl Widget for TextInput {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, _focused: bool) {
+        let offset = self.scroll(area.width);
+        self.offset.set(offset);
+        let y = area.y + area.height / 2;
+        canvas.fill(Rect::new(area.x, y, area.width, 1), ' ', THEME.input);
+        let visible: String = self.text.iter().skip(offset).collect();
+        canvas.text(area.x, y, &visible, area.width, THEME.input);
+    }
+
+    fn handle_key(&mut self, key: &KeyInfo) -> Response {
+        match (key.code, key.char) {
+            (KeyCode::ArrowLeft, _) => self.cursor = self.cursor.saturating_sub(1),
+            (KeyCode::ArrowRight, _) => self.cursor = (self.cursor + 1).min(self.text.len()),
+            (KeyCode::Home, _) => self.cursor = 0,
+            (KeyCode::End, _) => self.cursor = self.text.len(),
+            (KeyCode::Backspace, _) => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            (KeyCode::Delete, _) => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            (KeyCode::Enter, _) => return Response::Activated,
+            (_, Some(c)) if !c.is_control() && !key.modifiers.ctrl => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            _ => return Response::Ignored,
+        }
+        Response::Handled
+    }
+
+    fn cursor(&self, area: Rect) -> Option<(u8, u8)> {
+        let col = self.cursor - self.scroll(area.width);
+        Some((area.x + col as u8, area.y + area.height / 2))
+    }
+}
+
+// ================= MENU
+
+/// A box of choices over the rest of the screen
+///
+/// Up and Down move between the items, Enter picks one and Esc closes the menu. An item can also
+/// be picked with its first letter.
+pub struct Menu {
+    title: String,
+    items: Vec<String>,
+    selected: usize,
+}
+
+impl Menu {
+    pub fn new(title: &str, items: &[&str]) -> Menu {
+        Menu {
+            title: title.to_string(),
+            items: items.iter().map(|item| item.to_string()).collect(),
+            selected: 0,
+        }
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    /// returns where the menu goes when it is opened over `over`, just big enough for it
+    pub fn area(&self, over: Rect) -> Rect {
+        let longest = self.items.iter().map(|item| item.chars().count())
+            .chain(Some(self.title.chars().count() + 2))
+            .max().unwrap_or(0);
+        over.centered((longest + 4).min(u8::max_value() as usize) as u8,
+                      (self.items.len() + 2).min(u8::max_value() as usize) as u8)
+    }
+}
+
+impl Widget for Menu {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, _focused: bool) {
+        canvas.fill(area, ' ', THEME.menu);
+        canvas.border(area, THEME.menu);
+        if area.width > 4 {
+            canvas.text(area.x + 2, area.y, &self.title, area.width - 4, THEME.menu);
+        }
+        let inner = area.inner(1);
+        for (index, y) in (0..self.items.len()).zip(inner.y..inner.bottom()) {
+            let style = if index == self.selected { THEME.menu_selected } else { THEME.menu };
+            canvas.fill(Rect::new(inner.x, y, inner.width, 1), ' ', style);
+            canvas.text(inner.x + 1, y, &self.items[index], inner.width.saturating_sub(2), style);
+        }
+    }
+
+    fn handle_key(&mut self, key: &KeyInfo) -> Response {
+        let count = self.items.len().max(1);
+        match (key.code, key.char) {
+            (KeyCode::ArrowUp, _) => self.selected = (self.selected + count - 1) % count,
+            (KeyCode::ArrowDown, _) => self.selected = (self.selected + 1) % count,
+            (KeyCode::Enter, _) => return Response::Activated,
+            (KeyCode::Escape, _) => return Response::Cancelled,
+            (_, Some(c)) => {
+                let first = |item: &String| item.chars().next().map(|first| first.eq_ignore_ascii_case(&c));
+                match self.items.iter().position(|item| first(item) == Some(true)) {
+                    Some(index) => {
+                        self.selected = index;
+                        return Response::Activated;
+                    }
+                    None => return Response::Ignored,
+                }
+            }
+            _ => return Response::Ignored,
+        }
+        Response::Handled
+    }
+}
+
+// ================= STATUS BAR
+
+/// A line with a message on the left, and the number of tasks, the uptime and the time of the
+/// RTC on the right
+///
+/// The RTC is read as is, under QEMU that is UTC unless it runs with `-rtc base=localtime`.
+pub struct StatusBar {
+    pub message: String,
+}
+
+impl StatusBar {
+    pub fn new(message: &str) -> StatusBar {
+        StatusBar { message: message.to_string() }
+    }
+}
+
+impl Widget for StatusBar {
+    fn draw(&self, canvas: &mut Canvas, area: Rect, _focused: bool) {
+        let line = Rect::new(area.x, area.y, area.width, 1);
+        canvas.fill(line, ' ', THEME.status);
+        let seconds = time::uptime_ms() / 1000;
+        let now = rtc::now();
+        let status = format!("{} tasks  up {:02}:{:02}:{:02}  {:02}:{:02}:{:02} ", info::task_count(),
+                             seconds / 3600, seconds / 60 % 60, seconds % 60,
+                             now.hour, now.minute, now.second);
+        let x = aligned(line, status.len(), Align::Right);
+        canvas.text(x, line.y, &status, line.width, THEME.status);
+        canvas.text(line.x + 1, line.y, &self.message,
+                    x.saturating_sub(line.x + 2), THEME.status);
+    }
+}
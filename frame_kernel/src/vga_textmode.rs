@@ -1,13 +1,34 @@
 use alloc::borrow::ToOwned;
-use alloc::string::ToString;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 
 use lazy_static::lazy_static;
 use spin::Mutex;
 
+use crate::graphics::{rgb, Framebuffer, Rgb};
 use crate::outb;
 
+use self::ansi::{Advance, Command};
+use self::fbcon::FbConsole;
+use self::markup::Token;
+use self::scrollback::{Line, Scrollback};
+
+pub use self::codepage::CodePage;
+pub use self::font::{parse_psf, read_glyphs, set_nine_dot_clock, Font, FontError, GLYPH_STRIDE};
+pub use self::markup::strip_markup;
+pub use self::snapshot::{Row, Scope, Snapshot};
+
+mod ansi;
+mod codepage;
+mod fbcon;
+mod font;
+mod markup;
+mod palette;
+mod scrollback;
+mod snapshot;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -31,14 +52,57 @@ pub enum Color {
     White = 15,      // f
 }
 
+impl Color {
+    /// returns the color with a VGA color index (0-15)
+    fn from_index(index: u8) -> Color {
+        match index & 0xf {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::Gray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+
+    /// returns the color of an ANSI color number (0-15), which orders the colors differently
+    fn from_ansi(index: u8) -> Color {
+        const ANSI_TO_VGA: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+        Color::from_index(ANSI_TO_VGA[(index & 7) as usize] | (index & 8))
+    }
+}
+
+/// the colors text is written in after a reset
+const DEFAULT_FG: Color = Color::White;
+const DEFAULT_BG: Color = Color::Black;
+
 fn color(fg: Color, bg: Color) -> u8 { // create an attribute byte from 2 colors
     ((bg as u8) << 4 | (fg as u8)) // shift the bits together into a form of 0x(bg)(fg)
 }
 
-const SCREEN_HEIGHT: u8 = 25;
-const SCREEN_WIDTH: u8 = 80;
+/// the size of the VGA text mode, see screen_size() for the size of the console on the screen
+pub const SCREEN_HEIGHT: u8 = 25;
+pub const SCREEN_WIDTH: u8 = 80;
+
+/// the biggest the console gets on a framebuffer
+pub const MAX_HEIGHT: u8 = 48;
+pub const MAX_WIDTH: u8 = 128;
+
+/// how many lines each console keeps by default, changed with Writer::set_scrollback(...)
+pub const DEFAULT_SCROLLBACK: usize = 1000;
 
-const DATA_BUFFER_SIZE: u8 = 255;
+/// the most lines a console can keep, so the scrollback can't take the whole heap
+pub const MAX_SCROLLBACK: usize = 10_000;
 
 const VGA_TEXTMODE_PTR: *mut u8 = 0xb8000 as *mut u8; // a pointer to VGA TextMode Memory
 // (mut for writing to it)
@@ -48,13 +112,43 @@ pub struct ScreenChar {
     // a screenchar that can be displayed
     ascii: u8,
     attr: u8,
+    fg: [u8; 3], // the colors on a framebuffer, which can be any RGB rather than the 16 of attr
+    bg: [u8; 3],
 }
 
 impl ScreenChar {
     pub fn new(c: u8, attr: u8) -> ScreenChar {
+        // bit 7 is blink or a bright background in the text mode, nothing blinks on a framebuffer
+        ScreenChar::with_colors(c, attr, palette::vga(attr), palette::vga(attr >> 4))
+    }
+
+    /// A character with the RGB colors the framebuffer console shows it in, `attr` being what
+    /// the text mode shows
+    pub fn with_colors(c: u8, attr: u8, fg: Rgb, bg: Rgb) -> ScreenChar {
+        let bytes = |color: Rgb| [(color >> 16) as u8, (color >> 8) as u8, color as u8];
         ScreenChar {
             ascii: c,
             attr,
+            fg: bytes(fg),
+            bg: bytes(bg),
+        }
+    }
+
+    fn fg(&self) -> Rgb {
+        rgb(self.fg[0], self.fg[1], self.fg[2])
+    }
+
+    fn bg(&self) -> Rgb {
+        rgb(self.bg[0], self.bg[1], self.bg[2])
+    }
+
+    /// returns the character with its colors swapped
+    fn highlighted(self) -> ScreenChar {
+        ScreenChar {
+            attr: self.attr.rotate_left(4),
+            fg: self.bg,
+            bg: self.fg,
+            ..self
         }
     }
 }
@@ -91,16 +185,153 @@ pub unsafe fn set_vga_cursor_pos(x: u8, y: u8) {
     outb(0x3D5, ((pos >> 8) & 0xFF) as u16);
 }
 
+// ================= CODE PAGES
+
+static CODE_PAGE: AtomicU8 = AtomicU8::new(0); // CodePage::index()
+static FONT_PAGE: AtomicU8 = AtomicU8::new(0); // the code page of the font in the VGA
+static REPLACEMENT: AtomicU32 = AtomicU32::new('■' as u32);
+
+lazy_static! {
+    /// the BIOS font, saved before the first other font or glyph replaces it
+    static ref BIOS_FONT: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+}
+
+/// the height of the glyphs in the 80x25 text mode
+const FONT_HEIGHT: usize = 16;
+
+/// Saves the BIOS font if nothing replaced it yet, so cp437 can be restored
+/// Needs the heap
+fn save_bios_font() {
+    let mut bios_font = BIOS_FONT.lock();
+    if bios_font.is_none() {
+        *bios_font = Some(font::read_glyphs(FONT_HEIGHT));
+    }
+}
+
+/// Replaces the font with 256 glyphs of `height` bytes each, every byte shows its glyph again
+pub fn load_glyphs(glyphs: &[u8], height: usize) -> Result<(), FontError> {
+    save_bios_font();
+    font::load_glyphs(glyphs, height)?;
+    codepage::release_all();
+    Ok(())
+}
+
+/// Puts back a font read with read_glyphs(...), like after a graphics mode drew over it
+/// Replaced glyphs stay reserved, they are back with the rest
+pub(crate) fn restore_glyphs(glyphs: &[u8], height: usize) -> Result<(), FontError> {
+    font::load_glyphs(glyphs, height)
+}
+
+/// Replaces the glyph of a byte with one of the caller's (like the logo's), no character is
+/// written with the byte until the next font is loaded
+pub fn set_glyph(index: u8, lines: &[u8]) {
+    save_bios_font();
+    font::set_glyph(index, lines);
+    codepage::reserve(index);
+}
+
+/// returns the code page characters are written in
+pub fn code_page() -> CodePage {
+    CodePage::from_index(CODE_PAGE.load(Ordering::SeqCst))
+}
+
+/// Switches the code page characters are written in
+///
+/// `font` is the 8x16 font of the code page (256 glyphs of 16 bytes), which is loaded into the
+/// VGA. Without one, switching to cp437 restores the BIOS font, and the other code pages need
+/// their font to have been loaded before. Text already on the consoles is shown with the new
+/// glyphs.
+pub fn set_code_page(page: CodePage, font: Option<&[u8]>) -> Result<(), FontError> {
+    match font {
+        Some(font) => load_glyphs(font, FONT_HEIGHT)?,
+        None if page == CodePage::Cp437 => {
+            let bios_font = BIOS_FONT.lock().clone();
+            if let Some(bios_font) = bios_font {
+                load_glyphs(&bios_font, FONT_HEIGHT)?;
+            }
+        }
+        None if FONT_PAGE.load(Ordering::SeqCst) != page.index() => return Err(FontError::NotLoaded),
+        None => {}
+    }
+    FONT_PAGE.store(page.index(), Ordering::SeqCst);
+    CODE_PAGE.store(page.index(), Ordering::SeqCst);
+    Ok(())
+}
+
+/// Loads an 8x16 PSF font for a code page and switches to it
+pub fn load_psf_font(data: &[u8], page: CodePage) -> Result<(), FontError> {
+    let font = parse_psf(data)?;
+    if font.width != 8 {
+        return Err(FontError::BadWidth(font.width as u32));
+    }
+    if font.height != FONT_HEIGHT {
+        return Err(FontError::BadHeight(font.height));
+    }
+    set_code_page(page, Some(&font.glyphs))
+}
+
+/// returns the character shown for characters the code page has no glyph for
+pub fn replacement_char() -> char {
+    core::char::from_u32(REPLACEMENT.load(Ordering::SeqCst)).unwrap_or('?')
+}
+
+/// Sets the character shown for characters the code page has no glyph for
+/// returns false if the code page has no glyph for `c` either
+pub fn set_replacement_char(c: char) -> bool {
+    if code_page().encode(c).is_none() {
+        return false;
+    }
+    REPLACEMENT.store(c as u32, Ordering::SeqCst);
+    true
+}
+
+/// returns the byte that shows `c` in the code page
+pub(crate) fn glyph(c: char) -> u8 {
+    let page = code_page();
+    page.encode(c).or_else(|| page.encode(replacement_char())).unwrap_or(b'?')
+}
+
+/// Chooses what bit 7 of an attribute byte does: blink the character (the default), or
+/// select one of the bright background colors 8-f instead
+///
+/// This is the mode control register of the attribute controller, so it applies to every
+/// console at once.
+pub fn set_blink_enabled(enabled: bool) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mode = font::read_attribute(0x10); // mode control
+        font::write_attribute(0x10, if enabled { mode | 0x08 } else { mode & !0x08 });
+    });
+}
+
 pub struct Writer { // TODO: optimize Writer
     col_pos: u8, // the current column position in the buffer
-    row_pos: u8, // the current row position in the buffer
+    row_pos: usize, // the current row position in the buffer
     def_attr: u8, // the default attribute byte for writing
+    def_fg: Rgb, // and the colors the framebuffer console writes in
+    def_bg: Rgb,
     current_fg: Color,
     current_bg: Color,
+    fg_rgb: Option<Rgb>, // a 256 or true color, current_fg being the closest VGA color to it
+    bg_rgb: Option<Rgb>,
+    width: u8, // the size of the screen, bigger on a framebuffer
+    height: u8,
     drawing: bool, // true if the system is allowed to write to the screen
-    screen_buf_pos: u8, // the current position of the start of the screen in the data buffer
-    buffer: [[ScreenChar; SCREEN_WIDTH as usize]; DATA_BUFFER_SIZE as usize],
+    screen_buf_pos: usize, // the current position of the start of the screen in the data buffer
+    buffer: Scrollback,
     // all data written to the screen, including what is not displayed
+    pointer: Option<(u8, u8)>, // the mouse pointer as (column, row) on the screen
+    selection: Option<(usize, usize)>, // the selected cells as (anchor, end) indexes into the buffer
+    search: Option<Search>, // the search through the buffer, while searching
+    ansi: ansi::Parser, // escape sequences can be split between writes
+    markup: markup::Parser, // and so can color markup
+    bold: bool, // bright foreground (ANSI bold or &+)
+    blink: bool, // attribute bit 7, blinking or a bright background (%+)
+    reverse: bool, // ANSI swapped colors
+    saved_cursor: (u8, u8), // (column, row on the screen) saved by ESC 7 / CSI s
+    scroll_region: Option<(u8, u8)>, // (top, bottom) rows on the screen that scroll, inclusive
+    cursor_visible: bool,
 }
 
 impl Writer {
@@ -108,13 +339,30 @@ impl Writer {
         Writer {
             col_pos: 0,
             row_pos: 0,
-            def_attr: color(Color::White, Color::Black),
+            def_attr: color(DEFAULT_FG, DEFAULT_BG),
+            def_fg: palette::vga(DEFAULT_FG as u8),
+            def_bg: palette::vga(DEFAULT_BG as u8),
             current_fg: Color::White,
             current_bg: Color::Black,
+            fg_rgb: None,
+            bg_rgb: None,
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
             drawing: true,
             screen_buf_pos: 0,
-            buffer: [[ScreenChar::new(b' ', color(Color::White, Color::Black));
-                SCREEN_WIDTH as usize]; DATA_BUFFER_SIZE as usize],
+            buffer: Scrollback::new(DEFAULT_SCROLLBACK,
+                                    ScreenChar::new(b' ', color(DEFAULT_FG, DEFAULT_BG))),
+            pointer: None,
+            selection: None,
+            search: None,
+            ansi: ansi::Parser::new(),
+            markup: markup::Parser::new(),
+            bold: false,
+            blink: false,
+            reverse: false,
+            saved_cursor: (0, 0),
+            scroll_region: None,
+            cursor_visible: true,
         }
     }
 
@@ -128,6 +376,64 @@ impl Writer {
         self.drawing = true;
     }
 
+    /// returns the position of the cursor in the buffer as (column, row)
+    pub fn cursor(&self) -> (u8, usize) {
+        (self.col_pos, self.row_pos)
+    }
+
+    /// Moves the cursor to a position in the buffer, scrolling the screen to keep it visible
+    pub fn set_cursor(&mut self, col: u8, row: usize) {
+        self.col_pos = col.min(self.width - 1);
+        self.row_pos = row.min(self.buffer.len() - 1);
+        if self.row_pos < self.screen_buf_pos {
+            self.screen_buf_pos = self.row_pos;
+        } else if self.row_pos >= self.screen_buf_pos + self.height as usize {
+            self.screen_buf_pos = self.row_pos - (self.height as usize - 1);
+        }
+        self.draw();
+    }
+
+    /// Changes the size of the screen, when the console moves to or from a framebuffer
+    ///
+    /// Lines keep their text past the new width, so it comes back if the screen grows again.
+    pub(crate) fn resize(&mut self, width: u8, height: u8) {
+        self.width = width.max(1).min(MAX_WIDTH);
+        while self.buffer.len() < height as usize {
+            let blank = self.blank();
+            if self.buffer.push(blank) {
+                break; // no heap yet, the buffer can't grow
+            }
+        }
+        self.height = height.max(1).min(MAX_HEIGHT).min(self.buffer.len().min(MAX_HEIGHT as usize) as u8);
+
+        self.col_pos = self.col_pos.min(self.width - 1);
+        self.saved_cursor = (self.saved_cursor.0.min(self.width - 1), self.saved_cursor.1.min(self.height - 1));
+        self.scroll_region = None;
+        self.pointer = None;
+        self.search = None;
+        self.screen_buf_pos = self.screen_buf_pos.min(self.live_screen_pos());
+        if self.row_pos >= self.screen_buf_pos + self.height as usize {
+            self.screen_buf_pos = self.row_pos - (self.height as usize - 1);
+        }
+        self.draw();
+    }
+
+    /// returns how many lines of scrollback the console keeps
+    pub fn scrollback(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Changes how many lines of scrollback the console keeps (at least a screen, at most
+    /// MAX_SCROLLBACK), dropping the oldest lines if there are too many
+    pub fn set_scrollback(&mut self, lines: usize) {
+        let dropped = self.buffer.set_capacity(lines);
+        self.row_pos = self.row_pos.saturating_sub(dropped);
+        self.screen_buf_pos = self.screen_buf_pos.saturating_sub(dropped).min(self.live_screen_pos());
+        self.selection = None;
+        self.search = None;
+        self.draw();
+    }
+
     /// Moves the screen up in the buffer
     /// returns true if moved up successfully
     pub fn move_screen_up(&mut self) -> bool {
@@ -164,9 +470,9 @@ impl Writer {
     pub fn move_cursor_right(&mut self, wrap: bool) {
         self.col_pos += 1; // increase the column position
 
-        if self.col_pos > SCREEN_WIDTH { // requires a new line
+        if self.col_pos > self.width { // requires a new line
             if !wrap { // if not wrapping, move cursor to the end of the line and return
-                self.col_pos = SCREEN_WIDTH - 1;
+                self.col_pos = self.width - 1;
                 self.draw();
                 return;
             }
@@ -177,37 +483,47 @@ impl Writer {
         self.draw();
     }
 
-    /// Moves the screen down in the buffer
+    /// Moves the cursor down a line, adding a line to the buffer when it is on the last one
     pub fn move_screen_down(&mut self) {
-        if self.screen_buf_pos + self.row_pos >= DATA_BUFFER_SIZE - 1 {
-            // shift all lines up to make room for new lines at the end of the buffer
-            for x in 0..DATA_BUFFER_SIZE {
-                if x != DATA_BUFFER_SIZE - 1 {
-                    self.buffer[x as usize] = self.buffer[(x + 1) as usize];
-                    // set the value in buffer[x + 1] to buffer[x] to move everything up
-                } else {
-                    self.buffer[x as usize] = [ScreenChar::new(b' ', self.def_attr);
-                        SCREEN_WIDTH as usize]; // clear last entry
+        if self.row_pos + 1 < self.buffer.len() {
+            // the cursor was moved up, the line below already exists
+            self.row_pos += 1;
+        } else {
+            let live = self.screen_buf_pos == self.live_screen_pos();
+            let blank = self.blank();
+            if self.buffer.push(blank) {
+                // the oldest line was dropped, so everything moved up a line
+                if !live {
+                    self.screen_buf_pos = self.screen_buf_pos.saturating_sub(1);
+                }
+                self.lines_dropped(1);
+            } else {
+                self.row_pos += 1;
+                if live {
+                    self.screen_buf_pos += 1;
                 }
             }
-            self.draw();
-            return;
         }
 
-        // check the current pos on screen is not the end
-        if self.row_pos < self.screen_buf_pos + (SCREEN_HEIGHT - 1) {
-            // for when not moving position in buffer, only on screen
-            self.row_pos += 1;
-            self.draw();
-            return;
+        if self.row_pos >= self.screen_buf_pos + self.height as usize {
+            self.screen_buf_pos = self.row_pos - (self.height as usize - 1);
         }
+        self.draw();
+    }
 
-        // check that the position of the screen is before the end of the buffer
-        if self.screen_buf_pos < DATA_BUFFER_SIZE - SCREEN_HEIGHT {
-            // shifts the screen down by one and sets the row position accordingly
-            self.screen_buf_pos += 1;
-            self.row_pos += 1;
-            self.draw();
+    /// Moves the selection and search match up after the oldest lines of the buffer were dropped
+    fn lines_dropped(&mut self, lines: usize) {
+        let cells = lines * MAX_WIDTH as usize;
+        self.selection = match self.selection {
+            Some((anchor, end)) if anchor.min(end) >= cells => Some((anchor - cells, end - cells)),
+            _ => None, // part of the selected text is gone
+        };
+        if let Some(search) = &mut self.search {
+            search.view = search.view.saturating_sub(lines);
+            search.found = match search.found {
+                Some((row, col)) if row >= lines => Some((row - lines, col)),
+                _ => None,
+            };
         }
     }
 
@@ -218,6 +534,14 @@ impl Writer {
 
     /// moves the current row position down by one
     pub fn newline(&mut self) {
+        if let Some((_, bottom)) = self.scroll_region {
+            if self.screen_row() == bottom { // only the region scrolls
+                self.scroll_region_up(1);
+                self.carriage_ret();
+                self.draw();
+                return;
+            }
+        }
         self.move_screen_down(); // move the line down 1
         self.carriage_ret(); // go to start of line
         self.draw(); // draw the change
@@ -231,268 +555,776 @@ impl Writer {
     }
 
     pub fn clear(&mut self) {
-        self.buffer = [[ScreenChar::new(b' ',
-                                        color(Color::White, Color::Black));
-            SCREEN_WIDTH as usize]; DATA_BUFFER_SIZE as usize];
+        self.buffer.clear(ScreenChar::new(b' ', color(DEFAULT_FG, DEFAULT_BG)));
         self.col_pos = 0;
-        self.set_fg_color(b'f');
+        self.set_fg(DEFAULT_FG);
+        self.update_attr();
         self.row_pos = 0;
         self.screen_buf_pos = 0;
-        self.draw();
+        self.selection = None;
+        self.search = None;
+        // the buffer is back to a text mode screen of lines, a framebuffer screen needs more
+        self.resize(self.width, self.height);
     }
 
     /// Writes a byte to the buffer with a colored attribute byte following
     pub fn write_byte_colored(&mut self, byte: u8, color: u8) {
-        match byte {
+        self.write_cell(ScreenChar::new(byte, color));
+    }
+
+    fn write_cell(&mut self, cell: ScreenChar) {
+        match cell.ascii {
             b'\n' => self.newline(), // newline
             b'\r' => self.carriage_ret(), // return carriage
             b'\x08' => self.backspace(), // backspace
-            byte => { // if the byte is a normal character, print it to the buffer
-                // set the correct location
-                self.buffer[self.row_pos as usize][self.col_pos as usize]
-                    = ScreenChar::new(byte, color);
-
-                self.col_pos += 1; // increment the column position in the buffer
-                // (column = character in the line)
-                if self.col_pos >= SCREEN_WIDTH {
-                    // check if the current column position is at the end of the line
-                    self.newline(); // go to the next line
-                }
-            }
+            _ => self.put_glyph(cell), // a normal character
         }
     }
 
-    /// Writes a byte to the buffer with the default color attribute of the writer
+    /// Writes the glyph of a cell to the buffer, even the ones write_byte(...) treats as controls
+    fn put_glyph(&mut self, cell: ScreenChar) {
+        // set the correct location
+        self.buffer.line_mut(self.row_pos).set(self.col_pos as usize, cell);
+
+        self.col_pos += 1; // increment the column position in the buffer
+        // (column = character in the line)
+        if self.col_pos >= self.width {
+            // check if the current column position is at the end of the line
+            self.newline(); // go to the next line
+        }
+    }
+
+    /// Writes a byte to the buffer with the current colors of the writer
     pub fn write_byte(&mut self, byte: u8) {
-        self.write_byte_colored(byte, self.def_attr);
-        // write the byte with the current default color attribute
+        self.write_cell(self.styled(byte));
     }
 
-    /// Writes a character to the screen,
-    /// but checks it to make sure its something that can be printed
-    fn write_valid_byte(&mut self, byte: u8) {
-        match byte {
-            // match the non color code byte
-            // printable ASCII byte or newline
-            0x20..=0x7e | b'\n' | b'\r' | b'\x08' | 0xfe => self.write_byte(byte),
-            // not part of printable ASCII range
-            _ => self.write_byte(0xfe),
+    /// Writes a character without interpreting markup or escape sequences, as its glyph in
+    /// the code page (or the replacement character if it has none)
+    pub fn put_char(&mut self, c: char) {
+        match c {
+            '\n' | '\r' | '\x08' => self.write_byte(c as u8),
+            c => self.put_glyph(self.styled(glyph(c))),
         }
     }
 
-    /// check if the current byte is a color attribute identifier (foreground)
-    /// returns true if color is changed
-    fn set_fg_color(&mut self, cbyte: u8) -> bool {
-        match cbyte {
-            b'0' => {
-                self.def_attr = color(Color::Black, self.current_bg);
-                self.current_fg = Color::Black;
-            }
-            b'1' => {
-                self.def_attr = color(Color::Blue, self.current_bg);
-                self.current_fg = Color::Blue;
-            }
-            b'2' => {
-                self.def_attr = color(Color::Green, self.current_bg);
-                self.current_fg = Color::Green;
-            }
-            b'3' => {
-                self.def_attr = color(Color::Cyan, self.current_bg);
-                self.current_fg = Color::Cyan;
-            }
-            b'4' => {
-                self.def_attr = color(Color::Red, self.current_bg);
-                self.current_fg = Color::Red;
-            }
-            b'5' => {
-                self.def_attr = color(Color::Magenta, self.current_bg);
-                self.current_fg = Color::Magenta;
-            }
-            b'6' => {
-                self.def_attr = color(Color::Brown, self.current_bg);
-                self.current_fg = Color::Brown;
-            }
-            b'7' => {
-                self.def_attr = color(Color::LightGray, self.current_bg);
-                self.current_fg = Color::LightGray;
-            }
-            b'8' => {
-                self.def_attr = color(Color::Gray, self.current_bg);
-                self.current_fg = Color::Gray;
-            }
-            b'9' => {
-                self.def_attr = color(Color::LightBlue, self.current_bg);
-                self.current_fg = Color::LightBlue;
-            }
-            b'a' => {
-                self.def_attr = color(Color::LightGreen, self.current_bg);
-                self.current_fg = Color::LightGreen;
-            }
-            b'b' => {
-                self.def_attr = color(Color::LightCyan, self.current_bg);
-                self.current_fg = Color::LightCyan;
-            }
-            b'c' => {
-                self.def_attr = color(Color::LightRed, self.current_bg);
-                self.current_fg = Color::LightRed;
-            }
-            b'd' => {
-                self.def_attr = color(Color::Pink, self.current_bg);
-                self.current_fg = Color::Pink;
+    /// returns a cell of a byte in the current colors
+    fn styled(&self, byte: u8) -> ScreenChar {
+        ScreenChar::with_colors(byte, self.def_attr, self.def_fg, self.def_bg)
+    }
+
+    /// returns an empty cell in the current colors
+    fn blank(&self) -> ScreenChar {
+        self.styled(b' ')
+    }
+
+    /// Carries out a piece of color markup
+    fn apply_markup(&mut self, token: Token) {
+        match token {
+            Token::Char(c) => {
+                self.put_char(c);
+                return;
             }
-            b'e' => {
-                self.def_attr = color(Color::Yellow, self.current_bg);
-                self.current_fg = Color::Yellow;
+            Token::Fg(color) => self.set_fg(color),
+            Token::Bg(color) => self.set_bg(color),
+            Token::ResetFg => self.set_fg(DEFAULT_FG),
+            Token::ResetBg => self.set_bg(DEFAULT_BG),
+            Token::Reset => self.reset_attributes(),
+            Token::Bright(bright) => self.bold = bright,
+            Token::Blink(blink) => self.blink = blink,
+        }
+        self.update_attr();
+    }
+
+    /// Goes back to the default colors without bright, blink or reversed colors
+    fn reset_attributes(&mut self) {
+        self.set_fg(DEFAULT_FG);
+        self.set_bg(DEFAULT_BG);
+        self.bold = false;
+        self.blink = false;
+        self.reverse = false;
+        self.update_attr();
+    }
+
+    /// Writes a string literal to the buffer using write_byte(...)
+    pub fn write_string(&mut self, s: &str) {
+        self.follow_cursor();
+        for c in s.chars() { // loop through all the characters in the string
+            // escape sequences are ASCII, anything else just ends an unfinished one
+            let byte = if c.is_ascii() { c as u8 } else { 0x80 };
+            match self.ansi.advance(byte) { // ANSI escape sequences
+                Advance::Pass => {}
+                Advance::Consumed => continue,
+                Advance::Command(command) => {
+                    self.run_ansi(command);
+                    continue;
+                }
             }
-            b'f' => {
-                self.def_attr = color(Color::White, self.current_bg);
-                self.current_fg = Color::White;
+            // color markup, see markup.rs for the codes
+            let (first, second) = self.markup.advance(c);
+            for token in first.into_iter().chain(second) {
+                self.apply_markup(token);
             }
-            _ => return false
         }
-        true
+        self.draw();
+    }
+
+    // ================= ANSI ESCAPE SEQUENCES
+
+    /// returns the buffer row of the screen escape sequences work on, the newest lines even
+    /// while the view is scrolled back (searching keeps the view where it is)
+    fn terminal_pos(&self) -> usize {
+        self.live_screen_pos()
+    }
+
+    /// returns the row of the cursor on the screen
+    fn screen_row(&self) -> u8 {
+        self.row_pos.saturating_sub(self.terminal_pos()).min(self.height as usize - 1) as u8
+    }
+
+    /// Moves the cursor to a (column, row) on the screen
+    fn move_on_screen(&mut self, col: i16, row: i16) {
+        self.col_pos = col.max(0).min(self.width as i16 - 1) as u8;
+        self.row_pos = self.terminal_pos() + row.max(0).min(self.height as i16 - 1) as usize;
+    }
+
+    fn set_fg(&mut self, color: Color) {
+        self.current_fg = color;
+        self.fg_rgb = None;
+    }
+
+    fn set_bg(&mut self, color: Color) {
+        self.current_bg = color;
+        self.bg_rgb = None;
+    }
+
+    /// Sets a foreground color the text mode can't show, it shows the closest one instead
+    fn set_fg_rgb(&mut self, color: Rgb) {
+        self.current_fg = palette::nearest(color);
+        self.fg_rgb = Some(color);
+    }
+
+    fn set_bg_rgb(&mut self, color: Rgb) {
+        self.current_bg = palette::nearest(color);
+        self.bg_rgb = Some(color);
+    }
+
+    /// Recalculates the attribute byte from the colors and ANSI attributes
+    fn update_attr(&mut self) {
+        let fg = self.current_fg as u8 | if self.bold { 8 } else { 0 };
+        let bg = self.current_bg as u8;
+        let attr = if self.reverse { fg << 4 | bg } else { bg << 4 | fg };
+        self.def_attr = if self.blink { attr | 0x80 } else { attr };
+
+        // bold only brightens the 16 colors, and nothing blinks on a framebuffer
+        let fg = self.fg_rgb.unwrap_or(palette::vga(fg));
+        let bg = self.bg_rgb.unwrap_or(palette::vga(bg));
+        let (fg, bg) = if self.reverse { (bg, fg) } else { (fg, bg) };
+        self.def_fg = fg;
+        self.def_bg = bg;
+    }
+
+    /// Clears the cells between two (column, row) positions on the screen, inclusive
+    fn erase(&mut self, from: (u8, u8), to: (u8, u8)) {
+        let blank = self.blank();
+        let width = self.width as usize;
+        let from = from.1 as usize * width + from.0 as usize;
+        let to = to.1 as usize * width + to.0 as usize;
+        for cell in from..=to {
+            let row = self.terminal_pos() + cell / width;
+            self.buffer.line_mut(row).set(cell % width, blank);
+        }
+    }
+
+    /// returns the scroll region, or the whole screen
+    fn region(&self) -> (u8, u8) {
+        self.scroll_region.unwrap_or((0, self.height - 1))
+    }
+
+    /// Scrolls the lines of the scroll region up, clearing the lines at the bottom
+    fn scroll_region_up(&mut self, lines: u8) {
+        let (top, bottom) = self.region();
+        let top = self.terminal_pos() + top as usize;
+        let bottom = self.terminal_pos() + bottom as usize;
+        for row in top..=bottom {
+            *self.buffer.line_mut(row) = if row + (lines as usize) <= bottom {
+                self.buffer.line(row + lines as usize).clone()
+            } else {
+                Line::filled(self.blank())
+            };
+        }
+    }
+
+    /// Scrolls the lines of the scroll region down, clearing the lines at the top
+    fn scroll_region_down(&mut self, lines: u8) {
+        let (top, bottom) = self.region();
+        let top = self.terminal_pos() + top as usize;
+        let bottom = self.terminal_pos() + bottom as usize;
+        for row in (top..=bottom).rev() {
+            *self.buffer.line_mut(row) = if row >= top + lines as usize {
+                self.buffer.line(row - lines as usize).clone()
+            } else {
+                Line::filled(self.blank())
+            };
+        }
     }
 
-    /// check if the current byte is a color attribute identifier (background)
-    /// returns true if color is changed
-    fn set_bg_color(&mut self, cbyte: u8) -> bool {
-        match cbyte {
-            b'0' => {
-                self.def_attr = color(self.current_fg, Color::Black);
-                self.current_bg = Color::Black;
+    /// Sets colors and attributes from an SGR (ESC [ ... m) sequence
+    fn select_graphic_rendition(&mut self, command: &Command) {
+        let count = match command {
+            Command::Csi { count, .. } => (*count).max(1), // ESC [ m is a reset
+            Command::Esc(_) => return,
+        };
+        let mut i = 0;
+        while i < count {
+            match command.param(i, 0) {
+                0 => self.reset_attributes(),
+                1 => self.bold = true,
+                2 | 22 => self.bold = false,
+                5 | 6 => self.blink = true,
+                25 => self.blink = false,
+                7 => self.reverse = true,
+                27 => self.reverse = false,
+                n @ 30..=37 => self.set_fg(Color::from_ansi(n as u8 - 30)),
+                39 => self.set_fg(DEFAULT_FG),
+                n @ 40..=47 => self.set_bg(Color::from_ansi(n as u8 - 40)),
+                49 => self.set_bg(DEFAULT_BG),
+                n @ 90..=97 => self.set_fg(Color::from_ansi(n as u8 - 90 + 8)),
+                n @ 100..=107 => self.set_bg(Color::from_ansi(n as u8 - 100 + 8)),
+                n @ 38 | n @ 48 => match command.param(i + 1, 0) {
+                    5 => { // 256 colors, the first 16 being the usual ones
+                        let code = command.param(i + 2, 0).min(255) as u8;
+                        match (n, code) {
+                            (38, 0..=15) => self.set_fg(Color::from_ansi(code)),
+                            (_, 0..=15) => self.set_bg(Color::from_ansi(code)),
+                            (38, _) => self.set_fg_rgb(palette::xterm(code)),
+                            _ => self.set_bg_rgb(palette::xterm(code)),
+                        }
+                        i += 2;
+                    }
+                    2 => { // true color, as red;green;blue
+                        let component = |j: usize| command.param(i + j, 0).min(255) as u8;
+                        let color = rgb(component(2), component(3), component(4));
+                        if n == 38 { self.set_fg_rgb(color) } else { self.set_bg_rgb(color) }
+                        i += 4;
+                    }
+                    _ => i += 1,
+                },
+                _ => {} // not supported
             }
-            b'1' => {
-                self.def_attr = color(self.current_fg, Color::Blue);
-                self.current_bg = Color::Blue;
+            i += 1;
+        }
+        self.update_attr();
+    }
+
+    /// Carries out an escape sequence
+    fn run_ansi(&mut self, command: Command) {
+        let col = self.col_pos as i16;
+        let row = self.screen_row() as i16;
+        let n = command.param(0, 1).min(0x1000) as i16; // past the screen anyway, and row + n fits
+
+        match command {
+            Command::Esc(b'7') => self.saved_cursor = (self.col_pos, self.screen_row()),
+            Command::Esc(b'8') => {
+                let (col, row) = self.saved_cursor;
+                self.move_on_screen(col as i16, row as i16);
             }
-            b'2' => {
-                self.def_attr = color(self.current_fg, Color::Green);
-                self.current_bg = Color::Green;
+            Command::Esc(b'D') => self.newline_keep_column(), // index
+            Command::Esc(b'M') => { // reverse index
+                if row as u8 == self.region().0 {
+                    self.scroll_region_down(1);
+                } else {
+                    self.move_on_screen(col, row - 1);
+                }
             }
-            b'3' => {
-                self.def_attr = color(self.current_fg, Color::Cyan);
-                self.current_bg = Color::Cyan;
+            Command::Esc(b'c') => { // full reset
+                self.scroll_region = None;
+                self.cursor_visible = true;
+                self.select_graphic_rendition(&Command::Csi {
+                    params: [0; ansi::MAX_PARAMS], count: 0, private: false, action: b'm',
+                });
+                self.clear();
             }
-            b'4' => {
-                self.def_attr = color(self.current_fg, Color::Red);
-                self.current_bg = Color::Red;
+            Command::Esc(_) => {}
+            Command::Csi { private: true, action, .. } => match (command.param(0, 0), action) {
+                (25, b'h') => self.cursor_visible = true,
+                (25, b'l') => self.cursor_visible = false,
+                _ => {}
+            },
+            Command::Csi { action, .. } => match action {
+                b'A' => self.move_on_screen(col, row - n),
+                b'B' => self.move_on_screen(col, row + n),
+                b'C' => self.move_on_screen(col + n, row),
+                b'D' => self.move_on_screen(col - n, row),
+                b'E' => self.move_on_screen(0, row + n),
+                b'F' => self.move_on_screen(0, row - n),
+                b'G' => self.move_on_screen(n - 1, row),
+                b'd' => self.move_on_screen(col, n - 1),
+                b'H' | b'f' => {
+                    let col = command.param(1, 1).min(0x1000) as i16;
+                    self.move_on_screen(col - 1, n - 1);
+                }
+                b'J' => {
+                    let cursor = (self.col_pos, self.screen_row());
+                    let (start, end) = ((0, 0), (self.width - 1, self.height - 1));
+                    match command.param(0, 0) {
+                        0 => self.erase(cursor, end),
+                        1 => self.erase(start, cursor),
+                        _ => self.erase(start, end),
+                    }
+                }
+                b'K' => {
+                    let cursor = (self.col_pos, self.screen_row());
+                    let (start, end) = ((0, cursor.1), (self.width - 1, cursor.1));
+                    match command.param(0, 0) {
+                        0 => self.erase(cursor, end),
+                        1 => self.erase(start, cursor),
+                        _ => self.erase(start, end),
+                    }
+                }
+                b'S' => self.scroll_region_up(n.min(self.height as i16) as u8),
+                b'T' => self.scroll_region_down(n.min(self.height as i16) as u8),
+                b'm' => self.select_graphic_rendition(&command),
+                b'r' => {
+                    // clamped as u16, the parameters can be bigger than a u8
+                    let height = self.height as u16;
+                    let top = (command.param(0, 1).max(1) - 1).min(height - 1) as u8;
+                    let bottom = command.param(1, height).max(1).min(height) as u8 - 1;
+                    self.scroll_region = if top < bottom && (top, bottom) != (0, self.height - 1) {
+                        Some((top, bottom))
+                    } else {
+                        None
+                    };
+                    self.move_on_screen(0, 0);
+                }
+                b's' => self.saved_cursor = (self.col_pos, self.screen_row()),
+                b'u' => {
+                    let (col, row) = self.saved_cursor;
+                    self.move_on_screen(col as i16, row as i16);
+                }
+                _ => {} // not supported
+            },
+        }
+    }
+
+    /// Moves the cursor down a line without returning to the start of it
+    fn newline_keep_column(&mut self) {
+        let col = self.col_pos;
+        self.newline();
+        self.col_pos = col;
+    }
+
+    /// returns the position of the screen that shows the newest lines
+    fn live_screen_pos(&self) -> usize {
+        self.buffer.len() - self.height as usize
+    }
+
+    /// Scrolls the screen back to the cursor if it was scrolled up with scroll_view(...)
+    fn follow_cursor(&mut self) {
+        if self.screen_buf_pos < self.live_screen_pos() && self.search.is_none() {
+            self.screen_buf_pos = if self.row_pos >= self.live_screen_pos() {
+                self.live_screen_pos()
+            } else { // the cursor was moved above the newest screen, keep it on the last row
+                self.row_pos.saturating_sub(self.height as usize - 1)
+            };
+        }
+    }
+
+    /// Replaces the screen with rows of `width` cells, for full screen programs (see crate::ui),
+    /// and moves the cursor to a (column, row) on the screen, None hiding it
+    ///
+    /// The cells go over the newest lines, so what was on the screen stays in the scrollback.
+    pub fn show_cells(&mut self, cells: &[ScreenChar], width: usize, cursor: Option<(u8, u8)>) {
+        self.search = None;
+        self.selection = None;
+        self.scroll_region = None;
+        self.screen_buf_pos = self.live_screen_pos();
+        let columns = width.min(self.width as usize);
+        for (row, cells) in cells.chunks(width.max(1)).take(self.height as usize).enumerate() {
+            let line = self.buffer.line_mut(self.screen_buf_pos + row);
+            for (col, cell) in cells.iter().take(columns).enumerate() {
+                line.set(col, *cell);
             }
-            b'5' => {
-                self.def_attr = color(self.current_fg, Color::Magenta);
-                self.current_bg = Color::Magenta;
+        }
+        let (col, row) = cursor.unwrap_or((0, self.height - 1));
+        self.move_on_screen(col as i16, row as i16);
+        self.cursor_visible = cursor.is_some();
+        self.draw();
+    }
+
+    /// Copies the rows of the screen or the whole buffer, for a snapshot of the console
+    fn snapshot(&self, console: usize, scope: Scope) -> Snapshot {
+        let (first, last) = match scope {
+            Scope::Screen => (self.screen_buf_pos, self.screen_buf_pos + self.height as usize),
+            Scope::Scrollback => (0, self.buffer.len()),
+        };
+        let page = code_page();
+        let rows = (first..last).map(|row| {
+            let line = self.buffer.line(row);
+            let cells = (0..self.width as usize).map(|col| line.get(col));
+            Row {
+                text: cells.clone().map(|cell| page.decode(cell.ascii)).collect(),
+                attrs: cells.map(|cell| cell.attr).collect(),
             }
-            b'6' => {
-                self.def_attr = color(self.current_fg, Color::Brown);
-                self.current_bg = Color::Brown;
+        }).collect();
+        Snapshot {
+            console,
+            width: self.width,
+            height: self.height,
+            screen: self.screen_buf_pos,
+            cursor: (self.col_pos, self.row_pos),
+            cursor_visible: self.cursor_visible,
+            first,
+            rows,
+        }
+    }
+
+    /// Scrolls the screen through the buffer without moving the cursor
+    /// (negative is up, towards older lines)
+    pub fn scroll_view(&mut self, lines: isize) {
+        let pos = self.screen_buf_pos as isize + lines;
+        self.screen_buf_pos = pos.max(0).min(self.live_screen_pos() as isize) as usize;
+        self.draw();
+    }
+
+    /// Scrolls the screen to the oldest line in the buffer
+    pub fn scroll_to_top(&mut self) {
+        self.screen_buf_pos = 0;
+        self.draw();
+    }
+
+    /// Scrolls the screen back to the newest lines
+    pub fn scroll_to_bottom(&mut self) {
+        self.screen_buf_pos = self.live_screen_pos();
+        self.draw();
+    }
+
+    // ================= MOUSE OVERLAY
+
+    /// Shows the mouse pointer at a position on the screen
+    pub fn set_pointer(&mut self, col: u8, row: u8) {
+        let pointer = Some((col.min(self.width - 1), row.min(self.height - 1)));
+        if self.pointer != pointer {
+            self.pointer = pointer;
+            self.draw();
+        }
+    }
+
+    pub fn hide_pointer(&mut self) {
+        self.pointer = None;
+        self.draw();
+    }
+
+    /// returns the buffer index of a position on the screen
+    fn screen_to_index(&self, col: u8, row: u8) -> usize {
+        let row = (self.screen_buf_pos + row as usize).min(self.buffer.len() - 1);
+        row * MAX_WIDTH as usize + col.min(self.width - 1) as usize
+    }
+
+    /// Starts selecting text at a position on the screen
+    pub fn start_selection(&mut self, col: u8, row: u8) {
+        let index = self.screen_to_index(col, row);
+        self.selection = Some((index, index));
+        self.draw();
+    }
+
+    /// Moves the end of the selection to a position on the screen
+    pub fn extend_selection(&mut self, col: u8, row: u8) {
+        let index = self.screen_to_index(col, row);
+        if let Some((anchor, _)) = self.selection {
+            self.selection = Some((anchor, index));
+            self.draw();
+        }
+    }
+
+    /// Ends a selection, a click without dragging selects nothing
+    pub fn finish_selection(&mut self) {
+        if let Some((anchor, end)) = self.selection {
+            if anchor == end {
+                self.clear_selection();
             }
-            b'7' => {
-                self.def_attr = color(self.current_fg, Color::LightGray);
-                self.current_bg = Color::LightGray;
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+        self.draw();
+    }
+
+    /// returns true if the cell at a buffer index is selected
+    fn is_selected(&self, index: usize) -> bool {
+        match self.selection {
+            Some((anchor, end)) => index >= anchor.min(end) && index <= anchor.max(end),
+            None => false,
+        }
+    }
+
+    /// returns the selected text, with trailing spaces removed from each line
+    pub fn selected_text(&self) -> String {
+        let (anchor, end) = match self.selection {
+            Some(selection) => selection,
+            None => return String::new(),
+        };
+        let mut text = String::new();
+        let mut line = String::new();
+        let width = self.width as usize;
+        for index in anchor.min(end)..=anchor.max(end) {
+            let row = index / MAX_WIDTH as usize;
+            let col = index % MAX_WIDTH as usize;
+            if col >= width {
+                continue; // off the screen
             }
-            b'8' => {
-                self.def_attr = color(self.current_fg, Color::Gray);
-                self.current_bg = Color::Gray;
+            line.push(code_page().decode(self.buffer.line(row).glyphs()[col]));
+            if col == width - 1 && index != anchor.max(end) {
+                text.push_str(line.trim_end());
+                text.push('\n');
+                line.clear();
             }
-            b'9' => {
-                self.def_attr = color(self.current_fg, Color::LightBlue);
-                self.current_bg = Color::LightBlue;
+        }
+        text.push_str(line.trim_end());
+        text
+    }
+
+    // ================= SCROLLBACK SEARCH
+
+    /// Starts searching the buffer, the bottom row of the screen shows the query until
+    /// end_search(...) is called
+    pub fn start_search(&mut self) {
+        self.search = Some(Search {
+            query: String::new(),
+            glyphs: Vec::new(),
+            found: None,
+            view: self.screen_buf_pos,
+        });
+        self.draw();
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Adds a character to the query, moving to the closest match of the longer query
+    pub fn search_push(&mut self, c: char) {
+        if let Some(search) = &mut self.search {
+            let glyph = if c.is_control() { None } else { code_page().encode(c) };
+            if let (Some(glyph), true) = (glyph, search.glyphs.len() < MAX_QUERY) {
+                search.query.push(c);
+                search.glyphs.push(glyph);
+                self.update_search();
             }
-            b'a' => {
-                self.def_attr = color(self.current_fg, Color::LightGreen);
-                self.current_bg = Color::LightGreen;
+        }
+    }
+
+    /// Removes the last character of the query
+    pub fn search_pop(&mut self) {
+        if let Some(search) = &mut self.search {
+            search.query.pop();
+            search.glyphs.pop();
+            self.update_search();
+        }
+    }
+
+    /// Moves to the next match towards older (or newer) lines
+    /// returns false if there are no more matches that way
+    pub fn search_next(&mut self, older: bool) -> bool {
+        let found = match &self.search {
+            Some(search) => {
+                let from = search.found.unwrap_or(self.buffer_end());
+                self.find_match(&search.glyphs, from, older, search.found.is_none())
             }
-            b'b' => {
-                self.def_attr = color(self.current_fg, Color::LightCyan);
-                self.current_bg = Color::LightCyan;
+            None => return false,
+        };
+        match found {
+            Some(found) => {
+                self.show_match(found);
+                true
             }
-            b'c' => {
-                self.def_attr = color(self.current_fg, Color::LightRed);
-                self.current_bg = Color::LightRed;
+            None => false,
+        }
+    }
+
+    /// Stops searching, going back to where the screen was if `keep_view` is false
+    pub fn end_search(&mut self, keep_view: bool) {
+        if let Some(search) = self.search.take() {
+            if !keep_view {
+                self.screen_buf_pos = search.view.min(self.live_screen_pos());
             }
-            b'd' => {
-                self.def_attr = color(self.current_fg, Color::Pink);
-                self.current_bg = Color::Pink;
+        }
+        self.draw();
+    }
+
+    /// returns the (row, column) of the last cell in the buffer
+    fn buffer_end(&self) -> (usize, u8) {
+        (self.buffer.len() - 1, self.width - 1)
+    }
+
+    /// Finds the current match again after the query changed, the closest one at or before it
+    fn update_search(&mut self) {
+        let found = match &self.search {
+            Some(search) => {
+                let query = &search.glyphs;
+                let from = search.found.unwrap_or(self.buffer_end());
+                self.find_match(query, from, true, true)
+                    .or_else(|| self.find_match(query, self.buffer_end(), true, true))
             }
-            b'e' => {
-                self.def_attr = color(self.current_fg, Color::Yellow);
-                self.current_bg = Color::Yellow;
+            None => return,
+        };
+        match found {
+            Some(found) => self.show_match(found),
+            None => {
+                if let Some(search) = &mut self.search {
+                    search.found = None;
+                }
+                self.draw();
             }
-            b'f' => {
-                self.def_attr = color(self.current_fg, Color::White);
-                self.current_bg = Color::White;
+        }
+    }
+
+    /// Makes a match the current one, scrolling the screen to it if it isn't visible
+    fn show_match(&mut self, found: (usize, u8)) {
+        if let Some(search) = &mut self.search {
+            search.found = Some(found);
+        }
+        let row = found.0;
+        // the bottom row of the screen is taken by the query
+        if row < self.screen_buf_pos || row >= self.screen_buf_pos + self.height as usize - 1 {
+            self.screen_buf_pos = row.saturating_sub(self.height as usize / 2).min(self.live_screen_pos());
+        }
+        self.draw();
+    }
+
+    /// returns the columns where `query` starts on a line of the buffer, ignoring case
+    fn matches_in_line(&self, row: usize, query: &[u8]) -> Vec<u8> {
+        let line = &self.buffer.line(row).glyphs()[..self.width as usize];
+        let mut cols = Vec::new();
+        if query.is_empty() || query.len() > line.len() {
+            return cols;
+        }
+        for col in 0..=line.len() - query.len() {
+            if line[col..col + query.len()].iter().zip(query)
+                .all(|(c, q)| c.eq_ignore_ascii_case(q)) {
+                cols.push(col as u8);
             }
-            _ => return false
         }
-        true
+        cols
     }
 
-    /// Writes a string literal to the buffer using write_byte(...)
-    pub fn write_string(&mut self, s: &str) {
-        let mut colored_fg = false; // flag the next byte might be a fg color byte
-        let mut colored_bg = false; // flag the next byte might be a bg color byte
-        for x in 0..s.bytes().len() { // loop through all the bytes in the string
-            let byte = s.bytes().nth(x).unwrap(); // get the raw value of the current byte
-            if colored_fg { // if colored fg is flagged
-                if self.set_fg_color(byte) { // changes the color if needed
-                    colored_fg = false; // flip colored flag
-                    continue; // set color, nothing left to do this loop iteration
-                } else {
-                    colored_fg = false; // unflag colored
-                    self.write_valid_byte(b'&'); // print out '&' as it wasnt a colored byte
+    /// Finds the closest match before (`older`) or after a (row, column) position in the buffer,
+    /// which can itself be the match if `inclusive`
+    fn find_match(&self, query: &[u8], from: (usize, u8), older: bool, inclusive: bool)
+                  -> Option<(usize, u8)> {
+        let from_row = from.0.min(self.buffer.len() - 1);
+        let before = |row: usize, col: u8| (row, col) < from || (inclusive && (row, col) == from);
+        let after = |row: usize, col: u8| (row, col) > from || (inclusive && (row, col) == from);
+        if older {
+            for row in (0..=from_row).rev() {
+                if let Some(col) = self.matches_in_line(row, query).into_iter()
+                    .filter(|col| before(row, *col)).last() {
+                    return Some((row, col));
                 }
             }
-            if colored_bg {
-                if self.set_bg_color(byte) {
-                    colored_bg = false;
-                    continue;
-                } else {
-                    colored_bg = false;
-                    self.write_valid_byte(b'%');
+        } else {
+            for row in from_row..self.buffer.len() {
+                if let Some(col) = self.matches_in_line(row, query).into_iter()
+                    .find(|col| after(row, *col)) {
+                    return Some((row, col));
                 }
             }
+        }
+        None
+    }
 
-            if byte == b'&' { // flag colored if needed
-                colored_fg = true;
-                continue;
-            }
-            if byte == b'%' {
-                colored_bg = true;
-                continue;
-            }
-
-            self.write_valid_byte(byte); // output current byte
-
+    /// Draws the search query over the bottom row of the screen
+    /// returns where the cursor goes, after the query
+    fn draw_search_status(&self, search: &Search, framebuffer: &mut Option<FbConsole>) -> (u8, u8) {
+        let mut status: Vec<u8> = b" search: ".to_vec();
+        status.extend_from_slice(&search.glyphs);
+        let cursor = status.len() as u8;
+        if search.found.is_none() && !search.query.is_empty() {
+            status.extend_from_slice(b"  (no matches)");
         }
-        self.draw();
+        let attr = color(Color::Black, Color::LightGray);
+        let row = self.height - 1;
+        for col in 0..self.width {
+            let byte = status.get(col as usize).copied().unwrap_or(b' ');
+            draw_cell(framebuffer, col, row, ScreenChar::new(byte, attr));
+        }
+        (cursor.min(self.width - 1), row)
     }
 
     /// Draws the portion of the buffer marked by screen_buf_pos to the screen
     pub fn draw(&mut self) {
-        if !self.drawing {
+        if !self.drawing || SUSPENDED.load(Ordering::SeqCst) {
             return;
         }
-        for row in 0..SCREEN_HEIGHT { // all the rows (lines) on the screen
-            for col in 0..SCREEN_WIDTH { // all the characters on the current line
-                let byte
-                    = self.buffer[(self.screen_buf_pos + row) as usize][col as usize];
-                unsafe {
-                    // Write the current screenchar to the screen
-                    vga_write(byte, row, col * 2);
-                    // get the current row position in the buffer
-                    // col * 2 to account for attribute bytes
+        let mut framebuffer = FRAMEBUFFER.lock();
+        let query = self.search.as_ref().map(|search| &search.glyphs[..]).unwrap_or(&[]);
+        let found = self.search.as_ref().and_then(|search| search.found);
+        for row in 0..self.height { // all the rows (lines) on the screen
+            let buf_row = self.screen_buf_pos + row as usize;
+            let matches = self.matches_in_line(buf_row, query);
+            for col in 0..self.width { // all the characters on the current line
+                let mut byte = self.buffer.line(buf_row).get(col as usize);
+                if self.pointer == Some((col, row))
+                    || self.is_selected(self.screen_to_index(col, row)) {
+                    byte = byte.highlighted(); // swap the colors to highlight it
+                }
+                // highlight search matches, the current one in a different color
+                if let Some(&start) = matches.iter().rev().find(|start| **start <= col) {
+                    if col < start + query.len() as u8 {
+                        byte = if found == Some((buf_row, start)) {
+                            ScreenChar::new(byte.ascii, color(Color::Black, Color::Yellow))
+                        } else {
+                            ScreenChar::new(byte.ascii, color(Color::Black, Color::Brown))
+                        };
+                    }
                 }
+                draw_cell(&mut framebuffer, col, row, byte);
             }
         }
-        unsafe {
-            let mut r = self.row_pos - self.screen_buf_pos;
-            if r > 25 {
-                r = 24;
+
+        let cursor = match &self.search {
+            Some(search) => Some(self.draw_search_status(search, &mut framebuffer)),
+            None => match self.row_pos.checked_sub(self.screen_buf_pos) {
+                Some(r) if r < self.height as usize && self.cursor_visible => Some((self.col_pos, r as u8)),
+                _ => None, // hidden or scrolled away
+            },
+        };
+        match framebuffer.as_mut() {
+            Some(framebuffer) => {
+                framebuffer.set_cursor(cursor);
+                framebuffer.flush();
             }
-            set_vga_cursor_pos(self.col_pos, r);
+            None => unsafe {
+                match cursor {
+                    Some((col, row)) => set_vga_cursor_pos(col, row),
+                    None => set_vga_cursor_pos(0, SCREEN_HEIGHT), // put it past the screen
+                }
+            },
         }
     }
 }
 
+/// Writes a cell to the screen, the framebuffer console's if there is one
+fn draw_cell(framebuffer: &mut Option<FbConsole>, col: u8, row: u8, cell: ScreenChar) {
+    match framebuffer {
+        Some(framebuffer) => framebuffer.set_cell(col, row, cell),
+        // col * 2 to account for attribute bytes
+        None => unsafe { vga_write(cell, row, col * 2) },
+    }
+}
+
+/// the longest search query, so it fits on the status line
+const MAX_QUERY: usize = 48;
+
+/// An interactive search through the buffer of a console
+struct Search {
+    query: String,
+    glyphs: Vec<u8>, // the query in the code page, to compare with the buffer
+    found: Option<(usize, u8)>, // the (row, column) of the current match in the buffer
+    view: usize, // screen_buf_pos before searching, to go back to if the search is cancelled
+}
+
 impl fmt::Write for Writer {
     fn write_str(&mut self, s: &str) -> fmt::Result {
         self.write_string(s);
@@ -500,8 +1332,191 @@ impl fmt::Write for Writer {
     }
 }
 
+// ================= VIRTUAL CONSOLES
+
+/// how many virtual consoles there are, switched between with Alt+F1..F6
+pub const CONSOLE_COUNT: usize = 6;
+
+/// the console dedicated to the kernel log
+pub const LOG_CONSOLE: usize = CONSOLE_COUNT - 1;
+
+/// Creates the writer of a console, only the first one draws until another is switched to
+fn new_console(index: usize) -> Writer {
+    let mut writer = Writer::new();
+    if index != 0 {
+        writer.lock_drawing();
+    }
+    let (width, height) = screen_size();
+    if (width, height) != (SCREEN_WIDTH, SCREEN_HEIGHT) { // made while on a framebuffer
+        writer.resize(width, height);
+    }
+    writer
+}
+
+// separate statics, as building an array of writers would put all of them on the stack at once
+lazy_static! {
+    static ref CONSOLE_0: Mutex<Writer> = Mutex::new(new_console(0));
+    static ref CONSOLE_1: Mutex<Writer> = Mutex::new(new_console(1));
+    static ref CONSOLE_2: Mutex<Writer> = Mutex::new(new_console(2));
+    static ref CONSOLE_3: Mutex<Writer> = Mutex::new(new_console(3));
+    static ref CONSOLE_4: Mutex<Writer> = Mutex::new(new_console(4));
+    static ref CONSOLE_5: Mutex<Writer> = Mutex::new(new_console(5));
+}
+
+static ACTIVE_CONSOLE: AtomicUsize = AtomicUsize::new(0); // the console on the screen
+static OUTPUT_CONSOLE: AtomicUsize = AtomicUsize::new(0); // the console print! writes to
+static SUSPENDED: AtomicBool = AtomicBool::new(false); // a graphics mode has the screen
+
+/// returns the writer of a console (indexes past the last console wrap around)
+pub fn console(index: usize) -> &'static Mutex<Writer> {
+    match index % CONSOLE_COUNT {
+        0 => &CONSOLE_0,
+        1 => &CONSOLE_1,
+        2 => &CONSOLE_2,
+        3 => &CONSOLE_3,
+        4 => &CONSOLE_4,
+        _ => &CONSOLE_5,
+    }
+}
+
+/// returns the index of the console on the screen, which has the keyboard focus
+pub fn active_console() -> usize {
+    ACTIVE_CONSOLE.load(Ordering::SeqCst)
+}
+
+/// returns the writer of the console on the screen
+pub(crate) fn active_writer() -> &'static Mutex<Writer> {
+    console(active_console())
+}
+
+/// Shows a console on the screen, the others keep their output without drawing it
+pub fn switch_console(index: usize) {
+    use x86_64::instructions::interrupts;
+
+    let index = index % CONSOLE_COUNT;
+    interrupts::without_interrupts(|| {
+        let old = ACTIVE_CONSOLE.swap(index, Ordering::SeqCst);
+        if old == index {
+            return;
+        }
+        console(old).lock().lock_drawing();
+        let mut writer = console(index).lock();
+        writer.unlock_drawing();
+        writer.draw();
+    });
+}
+
+/// Takes a snapshot of the screen or the whole scrollback of a console
+pub fn take_snapshot(index: usize, scope: Scope) -> Snapshot {
+    use x86_64::instructions::interrupts;
+
+    let index = index % CONSOLE_COUNT;
+    interrupts::without_interrupts(|| console(index).lock().snapshot(index, scope))
+}
+
+// ================= FRAMEBUFFER CONSOLE
+
+static SCREEN_COLS: AtomicU8 = AtomicU8::new(SCREEN_WIDTH);
+static SCREEN_ROWS: AtomicU8 = AtomicU8::new(SCREEN_HEIGHT);
+
 lazy_static! {
-    pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer::new());
+    /// the screen the consoles draw on in a graphics mode, None in the text mode
+    static ref FRAMEBUFFER: Mutex<Option<FbConsole>> = Mutex::new(None);
+}
+
+/// returns the size of the consoles on the screen as (columns, rows)
+pub fn screen_size() -> (u8, u8) {
+    (SCREEN_COLS.load(Ordering::SeqCst), SCREEN_ROWS.load(Ordering::SeqCst))
+}
+
+/// Changes the size of every console
+fn resize_consoles(width: u8, height: u8) {
+    SCREEN_COLS.store(width, Ordering::SeqCst);
+    SCREEN_ROWS.store(height, Ordering::SeqCst);
+    for index in 0..CONSOLE_COUNT {
+        console(index).lock().resize(width, height);
+    }
+}
+
+/// Moves the consoles onto a framebuffer (from graphics::set_mode(...)), drawn in a font,
+/// until graphics::text_mode() puts them back in the text mode
+/// returns the size of the consoles as (columns, rows)
+///
+/// Glyphs are picked by their byte in the code page, like in the text mode.
+pub fn use_framebuffer(screen: Framebuffer, font: Font) -> (u8, u8) {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let framebuffer = FbConsole::new(screen, font);
+        let (width, height) = framebuffer.size();
+        *FRAMEBUFFER.lock() = Some(framebuffer);
+        SUSPENDED.store(false, Ordering::SeqCst);
+        resize_consoles(width, height);
+        (width, height)
+    })
+}
+
+/// Changes the font of the framebuffer console
+/// returns the new size of the consoles, or None in the text mode
+pub fn set_console_font(font: Font) -> Option<(u8, u8)> {
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let screen = FRAMEBUFFER.lock().take()?.into_screen();
+        Some(use_framebuffer(screen, font))
+    })
+}
+
+/// returns the font the consoles are drawn in, None while a graphics mode has the screen
+pub fn console_font() -> Option<Font> {
+    use x86_64::instructions::interrupts;
+
+    // without interrupts, the watchdog draws from the timer interrupt
+    let font = interrupts::without_interrupts(|| {
+        FRAMEBUFFER.lock().as_ref().map(|framebuffer| framebuffer.font().clone())
+    });
+    if font.is_some() {
+        return font;
+    }
+    if SUSPENDED.load(Ordering::SeqCst) {
+        return None; // the font in the VGA is drawn over
+    }
+    Some(Font { glyphs: font::read_glyphs(FONT_HEIGHT), width: 8, height: FONT_HEIGHT })
+}
+
+/// Stops all consoles drawing, while a graphics mode is using the screen
+///
+/// The framebuffer console is dropped (its screen belongs to the last mode, and its back buffer
+/// could be most of the heap) and the consoles go back to the size of the text mode.
+pub(crate) fn suspend_drawing() {
+    use x86_64::instructions::interrupts;
+
+    SUSPENDED.store(true, Ordering::SeqCst);
+    interrupts::without_interrupts(|| {
+        let framebuffer = FRAMEBUFFER.lock().take();
+        if framebuffer.is_some() {
+            drop(framebuffer);
+            resize_consoles(SCREEN_WIDTH, SCREEN_HEIGHT);
+        }
+    });
+}
+
+/// Lets the consoles draw in the text mode again and redraws the active one
+pub(crate) fn resume_drawing() {
+    use x86_64::instructions::interrupts;
+
+    SUSPENDED.store(false, Ordering::SeqCst);
+    interrupts::without_interrupts(|| active_writer().lock().draw());
+}
+
+/// returns the console print! currently writes to
+pub fn output_console() -> usize {
+    OUTPUT_CONSOLE.load(Ordering::SeqCst)
+}
+
+/// Makes print! write to a console, the executor sets this to the console of each task it polls
+pub fn set_output_console(index: usize) {
+    OUTPUT_CONSOLE.store(index % CONSOLE_COUNT, Ordering::SeqCst);
 }
 
 #[macro_export]
@@ -525,12 +1540,13 @@ pub fn _clear() {
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().clear();
+        get_writer().lock().clear();
     });
 }
 
+/// returns the writer of the console print! writes to
 pub(crate) fn get_writer<'a>() -> &'a Mutex<Writer> {
-    &WRITER
+    console(output_console())
 }
 
 #[doc(hidden)]
@@ -539,6 +1555,31 @@ pub fn _print(args: fmt::Arguments) {
     use x86_64::instructions::interrupts;
 
     interrupts::without_interrupts(|| {
-        WRITER.lock().write_fmt(args).unwrap();
+        get_writer().lock().write_fmt(args).unwrap();
+    });
+}
+
+/// Prints like print! unless the console is locked, for interrupt handlers that could have
+/// interrupted the code holding the lock
+/// returns false if nothing was printed
+pub fn try_print(args: fmt::Arguments) -> bool {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        match get_writer().try_lock() {
+            Some(mut writer) => writer.write_fmt(args).is_ok(),
+            None => false,
+        }
+    })
+}
+
+/// Prints to a specific console, whichever console print! is writing to
+pub fn print_to_console(index: usize, args: fmt::Arguments) {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        console(index).lock().write_fmt(args).unwrap();
     });
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+// ANSI/VT100 escape sequence parser
+//
+// The parser only splits the byte stream into escape sequences, the Writer carries them out.
+// It keeps its state between writes, so a sequence can be split over several print!s.
+
+/// the most parameters kept for a sequence, extra ones are ignored
+pub const MAX_PARAMS: usize = 16;
+
+const ESC: u8 = 0x1b;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground, // not in a sequence
+    Escape, // after ESC
+    Csi, // after ESC [
+}
+
+/// A complete escape sequence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// ESC [ params final, `private` if the parameters started with '?'
+    Csi {
+        params: [u16; MAX_PARAMS],
+        count: usize, // how many of `params` were given
+        private: bool,
+        action: u8, // the final byte
+    },
+    /// ESC followed by a single byte, such as ESC 7 (save cursor)
+    Esc(u8),
+}
+
+impl Command {
+    /// returns parameter `i` of a CSI sequence, or `default` if it wasn't given (or was 0)
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        match self {
+            Command::Csi { params, count, .. } if i < *count && params[i] != 0 => params[i],
+            _ => default,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Advance {
+    Pass, // the byte isn't part of a sequence, print it
+    Consumed, // the byte is part of an unfinished sequence
+    Command(Command),
+}
+
+pub struct Parser {
+    state: State,
+    params: [u16; MAX_PARAMS],
+    count: usize,
+    private: bool,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            count: 0,
+            private: false,
+        }
+    }
+
+    pub fn advance(&mut self, byte: u8) -> Advance {
+        match self.state {
+            State::Ground => {
+                if byte == ESC {
+                    self.state = State::Escape;
+                    Advance::Consumed
+                } else {
+                    Advance::Pass
+                }
+            }
+            State::Escape => {
+                if byte == b'[' {
+                    self.state = State::Csi;
+                    self.params = [0; MAX_PARAMS];
+                    self.count = 0;
+                    self.private = false;
+                    Advance::Consumed
+                } else {
+                    self.state = State::Ground;
+                    Advance::Command(Command::Esc(byte))
+                }
+            }
+            State::Csi => match byte {
+                b'0'..=b'9' => {
+                    if self.count == 0 {
+                        self.count = 1;
+                    }
+                    if self.count <= MAX_PARAMS {
+                        let param = &mut self.params[self.count - 1];
+                        *param = param.saturating_mul(10).saturating_add((byte - b'0') as u16);
+                    }
+                    Advance::Consumed
+                }
+                b';' => {
+                    // an empty parameter before the ';' still counts, as 0 (the default)
+                    self.count = if self.count == 0 { 2 } else { self.count + 1 };
+                    Advance::Consumed
+                }
+                b'?' if self.count == 0 => {
+                    self.private = true;
+                    Advance::Consumed
+                }
+                0x20..=0x3f => Advance::Consumed, // intermediate bytes we don't use
+                0x40..=0x7e => {
+                    self.state = State::Ground;
+                    Advance::Command(Command::Csi {
+                        params: self.params,
+                        count: self.count.min(MAX_PARAMS),
+                        private: self.private,
+                        action: byte,
+                    })
+                }
+                _ => { // not valid in a sequence, drop it
+                    self.state = State::Ground;
+                    Advance::Consumed
+                }
+            },
+        }
+    }
+}
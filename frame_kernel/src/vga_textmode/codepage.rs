@@ -0,0 +1,146 @@
+// Mapping characters onto the glyphs of a VGA code page
+//
+// The glyph a byte in text mode memory shows depends on the font in plane 2. The BIOS font is
+// code page 437, the others need their font loaded with set_code_page(...). Bytes 0x20-0x7e are
+// ASCII in all of them, and 0x01-0x1f/0x7f are the same symbols (smileys, arrows...) in all of
+// their fonts.
+//
+// A glyph replaced with one of a program's own (the logo's) is reserved: no character is written
+// with its byte until the next font is loaded, and the byte reads back as a block.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// the glyphs of bytes 0x00-0x1f, which the writer only uses for characters that aren't controls
+const LOW_GLYPHS: [char; 32] = [
+    '\0', '☺', '☻', '♥', '♦', '♣', '♠', '•', '◘', '○', '◙', '♂', '♀', '♪', '♫', '☼',
+    '►', '◄', '↕', '‼', '¶', '§', '▬', '↨', '↑', '↓', '→', '←', '∟', '↔', '▲', '▼',
+];
+
+/// the glyph of byte 0x7f
+const DEL_GLYPH: char = '⌂';
+
+// the glyphs of bytes 0x80-0xff
+const CP437: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+const CP850: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', 'ø', '£', 'Ø', '×', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '®', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', 'Á', 'Â', 'À', '©', '╣', '║', '╗', '╝', '¢', '¥', '┐',
+    '└', '┴', '┬', '├', '─', '┼', 'ã', 'Ã', '╚', '╔', '╩', '╦', '╠', '═', '╬', '¤',
+    'ð', 'Ð', 'Ê', 'Ë', 'È', 'ı', 'Í', 'Î', 'Ï', '┘', '┌', '█', '▄', '¦', 'Ì', '▀',
+    'Ó', 'ß', 'Ô', 'Ò', 'õ', 'Õ', 'µ', 'þ', 'Þ', 'Ú', 'Û', 'Ù', 'ý', 'Ý', '¯', '´',
+    '\u{ad}', '±', '‗', '¾', '¶', '§', '÷', '¸', '°', '¨', '·', '¹', '³', '²', '■', '\u{a0}',
+];
+
+const CP866: [char; 128] = [
+    'А', 'Б', 'В', 'Г', 'Д', 'Е', 'Ж', 'З', 'И', 'Й', 'К', 'Л', 'М', 'Н', 'О', 'П',
+    'Р', 'С', 'Т', 'У', 'Ф', 'Х', 'Ц', 'Ч', 'Ш', 'Щ', 'Ъ', 'Ы', 'Ь', 'Э', 'Ю', 'Я',
+    'а', 'б', 'в', 'г', 'д', 'е', 'ж', 'з', 'и', 'й', 'к', 'л', 'м', 'н', 'о', 'п',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'р', 'с', 'т', 'у', 'ф', 'х', 'ц', 'ч', 'ш', 'щ', 'ъ', 'ы', 'ь', 'э', 'ю', 'я',
+    'Ё', 'ё', 'Є', 'є', 'Ї', 'ї', 'Ў', 'ў', '°', '∙', '·', '√', '№', '¤', '■', '\u{a0}',
+];
+
+/// the bytes whose glyph was replaced, a bit each
+static RESERVED: [AtomicU64; 4] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+/// what reserved bytes read back as
+const RESERVED_CHAR: char = '█';
+
+/// Stops characters being written with a byte, its glyph was replaced
+pub(super) fn reserve(byte: u8) {
+    RESERVED[byte as usize / 64].fetch_or(1 << (byte % 64), Ordering::SeqCst);
+}
+
+/// Lets every byte be written again, a font with all the glyphs was loaded
+pub(super) fn release_all() {
+    for bits in RESERVED.iter() {
+        bits.store(0, Ordering::SeqCst);
+    }
+}
+
+fn is_reserved(byte: u8) -> bool {
+    RESERVED[byte as usize / 64].load(Ordering::SeqCst) & 1 << (byte % 64) != 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodePage {
+    Cp437, // US, the BIOS font
+    Cp850, // western european
+    Cp866, // cyrillic
+}
+
+impl CodePage {
+    pub const ALL: [CodePage; 3] = [CodePage::Cp437, CodePage::Cp850, CodePage::Cp866];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CodePage::Cp437 => "cp437",
+            CodePage::Cp850 => "cp850",
+            CodePage::Cp866 => "cp866",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<CodePage> {
+        CodePage::ALL.iter().copied().find(|page| page.name() == name)
+    }
+
+    pub(super) fn from_index(index: u8) -> CodePage {
+        match index {
+            1 => CodePage::Cp850,
+            2 => CodePage::Cp866,
+            _ => CodePage::Cp437,
+        }
+    }
+
+    pub(super) fn index(&self) -> u8 {
+        *self as u8
+    }
+
+    fn upper_half(&self) -> &'static [char; 128] {
+        match self {
+            CodePage::Cp437 => &CP437,
+            CodePage::Cp850 => &CP850,
+            CodePage::Cp866 => &CP866,
+        }
+    }
+
+    /// returns the byte that shows `c`, if the code page has a glyph for it
+    pub fn encode(&self, c: char) -> Option<u8> {
+        self.encode_any(c).filter(|byte| !is_reserved(*byte))
+    }
+
+    fn encode_any(&self, c: char) -> Option<u8> {
+        match c {
+            ' '..='~' => Some(c as u8),
+            DEL_GLYPH => Some(0x7f),
+            '\0' => None,
+            c => self.upper_half().iter().position(|glyph| *glyph == c).map(|i| 0x80 + i as u8)
+                .or_else(|| LOW_GLYPHS.iter().position(|glyph| *glyph == c).map(|i| i as u8)),
+        }
+    }
+
+    /// returns the character a byte shows
+    pub fn decode(&self, byte: u8) -> char {
+        match byte {
+            _ if is_reserved(byte) => RESERVED_CHAR,
+            0x00 => ' ',
+            0x01..=0x1f => LOW_GLYPHS[byte as usize],
+            0x7f => DEL_GLYPH,
+            0x80..=0xff => self.upper_half()[byte as usize - 0x80],
+            _ => byte as char,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+// The consoles on a framebuffer, once a graphics mode with a linear framebuffer is set
+//
+// Writers hand their cells over here instead of writing the text buffer, and flush() draws the
+// glyphs of the cells that changed since the last flush, presenting only the lines they cover.
+// When the screen moved up (a new line at the bottom) the pixels are moved instead, so scrolling
+// only draws the new lines.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::graphics::Framebuffer;
+
+use super::{Font, ScreenChar, MAX_HEIGHT, MAX_WIDTH};
+
+/// how many pixel lines the cursor takes at the bottom of its cell
+const CURSOR_HEIGHT: usize = 2;
+
+pub struct FbConsole {
+    screen: Framebuffer,
+    font: Font,
+    cols: u8,
+    rows: u8,
+    left: usize, // the pixel position of the grid, centered on the screen
+    top: usize,
+    cells: Vec<ScreenChar>, // what the writer drew
+    shown: Vec<ScreenChar>, // what is in the back buffer
+    stale: Vec<bool>, // rows of the back buffer that are drawn whatever `shown` says
+    cursor: Option<(u8, u8)>,
+    shown_cursor: Option<(u8, u8)>,
+}
+
+impl FbConsole {
+    pub fn new(mut screen: Framebuffer, font: Font) -> FbConsole {
+        let cols = (screen.width() / font.width).max(1).min(MAX_WIDTH as usize);
+        let rows = (screen.height() / font.height).max(1).min(MAX_HEIGHT as usize);
+        let blank = ScreenChar::new(b' ', 0);
+        screen.clear(0);
+        screen.present(); // the borders around the grid stay black
+        FbConsole {
+            left: screen.width().saturating_sub(cols * font.width) / 2,
+            top: screen.height().saturating_sub(rows * font.height) / 2,
+            screen,
+            font,
+            cols: cols as u8,
+            rows: rows as u8,
+            cells: vec![blank; cols * rows],
+            shown: vec![blank; cols * rows],
+            stale: vec![true; rows],
+            cursor: None,
+            shown_cursor: None,
+        }
+    }
+
+    /// returns the size of the grid as (columns, rows)
+    pub fn size(&self) -> (u8, u8) {
+        (self.cols, self.rows)
+    }
+
+    pub fn font(&self) -> &Font {
+        &self.font
+    }
+
+    /// returns the framebuffer back, to draw the console with another font
+    pub fn into_screen(self) -> Framebuffer {
+        self.screen
+    }
+
+    pub fn set_cell(&mut self, col: u8, row: u8, cell: ScreenChar) {
+        if col < self.cols && row < self.rows {
+            self.cells[row as usize * self.cols as usize + col as usize] = cell;
+        }
+    }
+
+    /// Moves the cursor to a cell, None hides it
+    pub fn set_cursor(&mut self, cursor: Option<(u8, u8)>) {
+        self.cursor = cursor;
+    }
+
+    /// returns how many rows the cells moved up since they were shown, 0 if they didn't
+    fn scrolled(&self) -> usize {
+        let cols = self.cols as usize;
+        let rows = self.rows as usize;
+        if self.stale.iter().any(|stale| *stale)
+            || line(&self.cells, cols, 0) == line(&self.shown, cols, 0) {
+            return 0;
+        }
+        // moving the pixels only pays off when most of the screen is kept
+        (1..=rows / 2)
+            .find(|by| (0..rows - by)
+                .all(|row| line(&self.cells, cols, row) == line(&self.shown, cols, row + by)))
+            .unwrap_or(0)
+    }
+
+    /// Draws a cell into the back buffer
+    fn draw_cell(&mut self, col: u8, row: u8) {
+        let index = row as usize * self.cols as usize + col as usize;
+        let cell = self.cells[index];
+        let (width, height) = (self.font.width, self.font.height);
+        let x = (self.left + col as usize * width) as i32;
+        let y = (self.top + row as usize * height) as i32;
+        let glyph = self.font.glyph(cell.ascii);
+        self.screen.draw_bitmap(x, y, width, height, glyph, cell.fg(), cell.bg());
+        if self.cursor == Some((col, row)) {
+            let cursor = CURSOR_HEIGHT.min(height);
+            self.screen.fill_rect(x, y + (height - cursor) as i32, width, cursor, cell.fg());
+        }
+        self.shown[index] = cell;
+    }
+
+    /// Draws the cells that changed and shows them
+    pub fn flush(&mut self) {
+        let cols = self.cols as usize;
+        let rows = self.rows as usize;
+        let line_height = self.font.height;
+
+        let by = self.scrolled();
+        if by > 0 {
+            self.screen.copy_lines(self.top + by * line_height, self.top, (rows - by) * line_height);
+            self.shown.copy_within(by * cols.., 0);
+            for stale in self.stale[rows - by..].iter_mut() {
+                *stale = true;
+            }
+            // the cursor moved up with the pixels
+            self.shown_cursor = self.shown_cursor
+                .and_then(|(col, row)| (row as usize).checked_sub(by).map(|row| (col, row as u8)));
+        }
+
+        let mut changed: Option<(usize, usize)> = None; // the first and last rows drawn
+        for row in 0..rows {
+            for col in 0..cols {
+                let index = row * cols + col;
+                let position = Some((col as u8, row as u8));
+                if self.stale[row] || self.cells[index] != self.shown[index]
+                    || self.cursor == position || self.shown_cursor == position {
+                    self.draw_cell(col as u8, row as u8);
+                    changed = Some(changed.map_or((row, row), |(first, _)| (first, row)));
+                }
+            }
+            self.stale[row] = false;
+        }
+        self.shown_cursor = self.cursor;
+
+        if by > 0 {
+            self.screen.present_lines(self.top, rows * line_height);
+        } else if let Some((first, last)) = changed {
+            self.screen.present_lines(self.top + first * line_height, (last - first + 1) * line_height);
+        }
+    }
+}
+
+/// returns a row of a grid of cells
+fn line(cells: &[ScreenChar], cols: usize, row: usize) -> &[ScreenChar] {
+    &cells[row * cols..(row + 1) * cols]
+}
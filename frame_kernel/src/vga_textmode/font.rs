@@ -0,0 +1,234 @@
+// The text mode font in plane 2 of VGA memory
+//
+// Each of the 256 glyphs takes 32 bytes in plane 2, one byte per scan line (the bits are the
+// pixels of the line, most significant on the left), of which only the character height is
+// shown. To reach plane 2 the sequencer and graphics controller are switched out of the odd/even
+// text mode addressing for the duration of the access.
+//
+// Fonts can come from PSF files (the Linux console font format, versions 1 and 2). Characters
+// are 8 pixels wide, plus a 9th column with the 9 dot clock, which is blank except for the box
+// drawing glyphs 0xC0-0xDF where it repeats the 8th so their lines join up. The framebuffer
+// console takes wider PSF2 fonts too, their lines are padded to whole bytes.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+
+use x86_64::instructions::{interrupts, port::Port};
+use x86_64::PhysAddr;
+
+use crate::memory::phys_to_virt;
+
+/// how many glyphs a font has
+pub const GLYPH_COUNT: usize = 256;
+
+/// the bytes between the start of two glyphs in plane 2, also the tallest a glyph can be
+pub const GLYPH_STRIDE: usize = 32;
+
+const SEQUENCER: u16 = 0x3C4;
+const GRAPHICS: u16 = 0x3CE;
+const ATTRIBUTE: u16 = 0x3C0; // index and data are both written here
+const INPUT_STATUS: u16 = 0x3DA; // reading it makes the attribute controller expect an index
+const MISC_OUTPUT_READ: u16 = 0x3CC;
+const MISC_OUTPUT_WRITE: u16 = 0x3C2;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01; // the font has 512 glyphs instead of 256
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    BadHeight(usize), // glyphs must be 1-32 lines high
+    BadSize(usize), // the font isn't 256 glyphs of that height
+    NotLoaded, // switched to a code page without giving its font
+    BadMagic, // not a PSF file
+    BadWidth(u32), // the text mode takes 8 pixel wide glyphs, the framebuffer up to 32
+    Truncated, // the PSF file ends before its last glyph
+}
+
+/// the widest glyphs a PSF font can have
+pub const MAX_GLYPH_WIDTH: usize = 32;
+
+/// A font read from a PSF file (or the VGA)
+#[derive(Clone)]
+pub struct Font {
+    pub glyphs: Vec<u8>, // the first 256 glyphs, `height` lines of bytes_per_line() bytes each
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Font {
+    /// returns how many bytes each line of a glyph takes
+    pub fn bytes_per_line(&self) -> usize {
+        (self.width + 7) / 8
+    }
+
+    /// returns the lines of a glyph
+    pub fn glyph(&self, index: u8) -> &[u8] {
+        let size = self.height * self.bytes_per_line();
+        &self.glyphs[index as usize * size..(index as usize + 1) * size]
+    }
+}
+
+fn write_register(port: u16, index: u8, value: u8) {
+    let mut index_port: Port<u8> = Port::new(port);
+    let mut data_port: Port<u8> = Port::new(port + 1);
+    unsafe {
+        index_port.write(index);
+        data_port.write(value);
+    }
+}
+
+/// Runs `f` with plane 2 mapped at 0xA0000 in place of the text buffer
+fn with_plane_2<T>(f: impl FnOnce(*mut u8) -> T) -> T {
+    interrupts::without_interrupts(|| {
+        write_register(SEQUENCER, 0x02, 0x04); // write to plane 2 only
+        write_register(SEQUENCER, 0x04, 0x07); // sequential addressing
+        write_register(GRAPHICS, 0x04, 0x02); // read from plane 2
+        write_register(GRAPHICS, 0x05, 0x00); // no odd/even
+        write_register(GRAPHICS, 0x06, 0x04); // map 0xA0000-0xAFFFF, graphics addressing
+
+        let result = f(phys_to_virt(PhysAddr::new(0xA0000)).as_mut_ptr());
+
+        // back to text mode: planes 0 and 1, odd/even, mapped at 0xB8000
+        write_register(SEQUENCER, 0x02, 0x03);
+        write_register(SEQUENCER, 0x04, 0x03);
+        write_register(GRAPHICS, 0x04, 0x00);
+        write_register(GRAPHICS, 0x05, 0x10);
+        write_register(GRAPHICS, 0x06, 0x0E);
+        result
+    })
+}
+
+/// Replaces the font with 256 glyphs of `height` bytes each
+pub fn load_glyphs(glyphs: &[u8], height: usize) -> Result<(), FontError> {
+    if height == 0 || height > GLYPH_STRIDE {
+        return Err(FontError::BadHeight(height));
+    }
+    if glyphs.len() != GLYPH_COUNT * height {
+        return Err(FontError::BadSize(glyphs.len()));
+    }
+    with_plane_2(|plane| {
+        for (i, glyph) in glyphs.chunks(height).enumerate() {
+            for line in 0..GLYPH_STRIDE {
+                let byte = glyph.get(line).copied().unwrap_or(0);
+                unsafe { ptr::write_volatile(plane.add(i * GLYPH_STRIDE + line), byte) };
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Replaces a single glyph, its lines past the end of `lines` are cleared
+pub fn set_glyph(index: u8, lines: &[u8]) {
+    with_plane_2(|plane| {
+        for line in 0..GLYPH_STRIDE {
+            let byte = lines.get(line).copied().unwrap_or(0);
+            unsafe { ptr::write_volatile(plane.add(index as usize * GLYPH_STRIDE + line), byte) };
+        }
+    });
+}
+
+/// returns the current font, 256 glyphs of `height` bytes each
+pub fn read_glyphs(height: usize) -> Vec<u8> {
+    let height = height.min(GLYPH_STRIDE);
+    with_plane_2(|plane| {
+        let mut glyphs = Vec::with_capacity(GLYPH_COUNT * height);
+        for i in 0..GLYPH_COUNT {
+            for line in 0..height {
+                glyphs.push(unsafe { ptr::read_volatile(plane.add(i * GLYPH_STRIDE + line)) });
+            }
+        }
+        glyphs
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, FontError> {
+    let bytes = data.get(offset..offset + 4).ok_or(FontError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Reads the glyphs of a PSF (version 1 or 2) font, the unicode table is ignored
+pub fn parse_psf(data: &[u8]) -> Result<Font, FontError> {
+    let (offset, count, width, height) = if data.starts_with(&PSF1_MAGIC) {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+        (4, if mode & PSF1_MODE_512 != 0 { 512 } else { 256 }, 8, height)
+    } else if data.starts_with(&PSF2_MAGIC) {
+        let header_size = read_u32(data, 8)? as usize;
+        let count = read_u32(data, 16)? as usize;
+        let glyph_size = read_u32(data, 20)? as usize;
+        let height = read_u32(data, 24)? as usize;
+        let width = read_u32(data, 28)?;
+        if width == 0 || width as usize > MAX_GLYPH_WIDTH
+            || glyph_size != height * ((width as usize + 7) / 8) {
+            return Err(FontError::BadWidth(width));
+        }
+        (header_size, count, width as usize, height)
+    } else {
+        return Err(FontError::BadMagic);
+    };
+
+    if height == 0 || height > GLYPH_STRIDE {
+        return Err(FontError::BadHeight(height));
+    }
+    // fonts with fewer glyphs leave the rest blank
+    let glyph_size = height * ((width + 7) / 8);
+    let mut glyphs = vec![0; GLYPH_COUNT * glyph_size];
+    let size = count.min(GLYPH_COUNT) * glyph_size;
+    let font = data.get(offset..offset + size).ok_or(FontError::Truncated)?;
+    glyphs[..size].copy_from_slice(font);
+    Ok(Font { glyphs, width, height })
+}
+
+pub(super) fn write_attribute(index: u8, value: u8) {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS);
+    let mut attribute: Port<u8> = Port::new(ATTRIBUTE);
+    unsafe {
+        status.read();
+        attribute.write(index | 0x20); // keep the palette address source bit on, or the screen blanks
+        attribute.write(value);
+    }
+}
+
+pub(super) fn read_attribute(index: u8) -> u8 {
+    let mut status: Port<u8> = Port::new(INPUT_STATUS);
+    let mut attribute: Port<u8> = Port::new(ATTRIBUTE);
+    let mut data: Port<u8> = Port::new(ATTRIBUTE + 1);
+    unsafe {
+        status.read();
+        attribute.write(index | 0x20);
+        let value = data.read();
+        status.read(); // back to expecting an index
+        value
+    }
+}
+
+/// Switches between 8 and 9 pixel wide characters (720 pixel lines, the BIOS default)
+///
+/// The pixel clock changes along with it, so the refresh rate stays the same.
+pub fn set_nine_dot_clock(nine: bool) {
+    interrupts::without_interrupts(|| {
+        let mut misc_read: Port<u8> = Port::new(MISC_OUTPUT_READ);
+        let mut misc_write: Port<u8> = Port::new(MISC_OUTPUT_WRITE);
+        let mut sequencer_index: Port<u8> = Port::new(SEQUENCER);
+        let mut sequencer_data: Port<u8> = Port::new(SEQUENCER + 1);
+
+        write_register(SEQUENCER, 0x00, 0x01); // synchronous reset while the clock changes
+        unsafe {
+            sequencer_index.write(0x01); // clocking mode, bit 0 set for 8 dot characters
+            let clocking = sequencer_data.read();
+            sequencer_data.write(if nine { clocking & !0x01 } else { clocking | 0x01 });
+
+            // 28.322 MHz for 720 pixels, 25.175 MHz for 640
+            let misc = misc_read.read() & !0x0C;
+            misc_write.write(if nine { misc | 0x04 } else { misc });
+        }
+        write_register(SEQUENCER, 0x00, 0x03);
+
+        // the 9 dot mode shifts the picture by a pixel, and repeats the line graphics column
+        write_attribute(0x13, if nine { 0x08 } else { 0x00 });
+        let mode = read_attribute(0x10);
+        write_attribute(0x10, if nine { mode | 0x04 } else { mode & !0x04 });
+    });
+}
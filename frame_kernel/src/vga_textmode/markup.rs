@@ -0,0 +1,200 @@
+// The colour markup understood by print!
+//
+//   &x      foreground colour x, a hex digit (0-9, a-f) as in the Color enum
+//   %x      background colour x
+//   &r      reset the foreground to the default (white)
+//   %r      reset the background to the default (black)
+//   &R      reset everything: both colours, bright and blink
+//   &+ &-   bright foreground on / off (adds 8 to the colour, like ANSI bold)
+//   %+ %-   blink on / off, shown as a bright background instead while blink is disabled
+//           with set_blink_enabled(false)
+//   && %%   a literal & or %
+//
+// Any other character after & or % is printed as it is, along with the & or %.
+// Like the ANSI parser, it keeps its state between writes, so markup can be split over
+// several print!s.
+
+use alloc::string::String;
+
+use super::Color;
+
+/// A piece of markup, or a character to print
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Char(char),
+    Fg(Color),
+    Bg(Color),
+    ResetFg,
+    ResetBg,
+    Reset,
+    Bright(bool),
+    Blink(bool),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Text,
+    Fg, // after &
+    Bg, // after %
+}
+
+pub struct Parser {
+    state: State,
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser { state: State::Text }
+    }
+
+    /// Feeds a character to the parser
+    /// returns up to two tokens, as an unknown code prints both the & or % and the character
+    /// after it
+    pub fn advance(&mut self, c: char) -> (Option<Token>, Option<Token>) {
+        let state = self.state;
+        self.state = State::Text;
+        match (state, c) {
+            (State::Text, '&') => {
+                self.state = State::Fg;
+                (None, None)
+            }
+            (State::Text, '%') => {
+                self.state = State::Bg;
+                (None, None)
+            }
+            (State::Text, c) => (Some(Token::Char(c)), None),
+
+            (State::Fg, '&') => (Some(Token::Char('&')), None),
+            (State::Fg, 'r') => (Some(Token::ResetFg), None),
+            (State::Fg, 'R') => (Some(Token::Reset), None),
+            (State::Fg, '+') => (Some(Token::Bright(true)), None),
+            (State::Fg, '-') => (Some(Token::Bright(false)), None),
+            (State::Fg, c) => match hex_digit(c) {
+                Some(index) => (Some(Token::Fg(Color::from_index(index))), None),
+                None => (Some(Token::Char('&')), self.advance(c).0),
+            },
+
+            (State::Bg, '%') => (Some(Token::Char('%')), None),
+            (State::Bg, 'r') => (Some(Token::ResetBg), None),
+            (State::Bg, '+') => (Some(Token::Blink(true)), None),
+            (State::Bg, '-') => (Some(Token::Blink(false)), None),
+            (State::Bg, c) => match hex_digit(c) {
+                Some(index) => (Some(Token::Bg(Color::from_index(index))), None),
+                None => (Some(Token::Char('%')), self.advance(c).0),
+            },
+        }
+    }
+}
+
+/// returns the value of a lowercase hex digit
+fn hex_digit(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' | 'a'..='f' => c.to_digit(16).map(|digit| digit as u8),
+        _ => None,
+    }
+}
+
+/// Removes the markup from a string, leaving the text it would print
+/// (for channels such as serial that would show the codes)
+pub fn strip_markup(s: &str) -> String {
+    let mut parser = Parser::new();
+    let mut text = String::with_capacity(s.len());
+    for c in s.chars() {
+        let (first, second) = parser.advance(c);
+        for token in first.into_iter().chain(second) {
+            if let Token::Char(c) = token {
+                text.push(c);
+            }
+        }
+    }
+    // a trailing & or % is kept, it can't start markup anymore
+    match parser.state {
+        State::Fg => text.push('&'),
+        State::Bg => text.push('%'),
+        State::Text => {}
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// returns the tokens of a string, fed to the parser in one go
+    fn tokens(s: &str) -> Vec<Token> {
+        let mut parser = Parser::new();
+        let mut tokens = Vec::new();
+        for c in s.chars() {
+            let (first, second) = parser.advance(c);
+            tokens.extend(first.into_iter().chain(second));
+        }
+        tokens
+    }
+
+    #[test_case]
+    fn strip_removes_colours() {
+        assert_eq!(strip_markup("&cred%1 on blue&7%0"), "red on blue");
+        assert_eq!(strip_markup("&rreset%rboth&Rall"), "resetbothall");
+        assert_eq!(strip_markup("&+bright&- %+blink%-"), "bright blink");
+    }
+
+    #[test_case]
+    fn strip_keeps_escapes() {
+        assert_eq!(strip_markup("Tom && Jerry"), "Tom & Jerry");
+        assert_eq!(strip_markup("100%% done"), "100% done");
+        assert_eq!(strip_markup("&&c"), "&c");
+    }
+
+    #[test_case]
+    fn strip_keeps_trailing() {
+        assert_eq!(strip_markup("a&"), "a&");
+        assert_eq!(strip_markup("a%"), "a%");
+        assert_eq!(strip_markup("&"), "&");
+    }
+
+    #[test_case]
+    fn strip_keeps_unknown_codes() {
+        assert_eq!(strip_markup("&z%q"), "&z%q");
+        assert_eq!(strip_markup("&C%F"), "&C%F"); // the digits are lowercase only
+        assert_eq!(strip_markup("%R"), "%R"); // only & has reset everything
+        assert_eq!(strip_markup("& b"), "& b");
+    }
+
+    #[test_case]
+    fn unknown_code_starts_markup() {
+        // the character after an unknown code is parsed again, so it can start markup itself
+        assert_eq!(strip_markup("&%1x"), "&x");
+        assert_eq!(tokens("&%1"), [Token::Char('&'), Token::Bg(Color::Blue)]);
+    }
+
+    #[test_case]
+    fn parses_colours() {
+        assert_eq!(tokens("&a"), [Token::Fg(Color::LightGreen)]);
+        assert_eq!(tokens("%4"), [Token::Bg(Color::Red)]);
+        assert_eq!(tokens("&f%0x"), [Token::Fg(Color::White), Token::Bg(Color::Black), Token::Char('x')]);
+    }
+
+    #[test_case]
+    fn parses_resets() {
+        assert_eq!(tokens("&r"), [Token::ResetFg]);
+        assert_eq!(tokens("%r"), [Token::ResetBg]);
+        assert_eq!(tokens("&R"), [Token::Reset]);
+    }
+
+    #[test_case]
+    fn parses_bright_and_blink() {
+        assert_eq!(tokens("&+&-"), [Token::Bright(true), Token::Bright(false)]);
+        assert_eq!(tokens("%+%-"), [Token::Blink(true), Token::Blink(false)]);
+    }
+
+    #[test_case]
+    fn keeps_state_between_writes() {
+        let mut parser = Parser::new();
+        assert_eq!(parser.advance('&'), (None, None));
+        assert_eq!(parser.advance('c'), (Some(Token::Fg(Color::LightRed)), None));
+        assert_eq!(parser.advance('%'), (None, None));
+        assert_eq!(parser.advance('%'), (Some(Token::Char('%')), None));
+    }
+}
@@ -0,0 +1,54 @@
+// The colors of the console as RGB, for the framebuffer console
+//
+// The 16 VGA colors are the ones the VGA's DAC shows by default. ANSI 256 color codes use the
+// xterm palette: the 16 ANSI colors, a 6x6x6 color cube and 24 grays. The text mode can only
+// show the 16 VGA colors, so other colors are also kept as the closest of those.
+
+use crate::graphics::{rgb, Rgb};
+
+use super::Color;
+
+/// the default DAC colors, indexed by VGA color
+const VGA: [Rgb; 16] = [
+    rgb(0x00, 0x00, 0x00), rgb(0x00, 0x00, 0xAA), rgb(0x00, 0xAA, 0x00), rgb(0x00, 0xAA, 0xAA),
+    rgb(0xAA, 0x00, 0x00), rgb(0xAA, 0x00, 0xAA), rgb(0xAA, 0x55, 0x00), rgb(0xAA, 0xAA, 0xAA),
+    rgb(0x55, 0x55, 0x55), rgb(0x55, 0x55, 0xFF), rgb(0x55, 0xFF, 0x55), rgb(0x55, 0xFF, 0xFF),
+    rgb(0xFF, 0x55, 0x55), rgb(0xFF, 0x55, 0xFF), rgb(0xFF, 0xFF, 0x55), rgb(0xFF, 0xFF, 0xFF),
+];
+
+/// the levels of each component in the xterm color cube
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// returns the RGB of a VGA color index (0-15)
+pub fn vga(index: u8) -> Rgb {
+    VGA[(index & 0xf) as usize]
+}
+
+/// returns the RGB of an ANSI 256 color code
+pub fn xterm(code: u8) -> Rgb {
+    match code {
+        0..=15 => vga(Color::from_ansi(code) as u8),
+        16..=231 => {
+            let cube = code - 16;
+            rgb(CUBE_LEVELS[(cube / 36) as usize], CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize])
+        }
+        _ => {
+            let gray = 8 + (code - 232) * 10;
+            rgb(gray, gray, gray)
+        }
+    }
+}
+
+/// returns the VGA color that looks closest to an RGB color
+pub fn nearest(color: Rgb) -> Color {
+    let component = |color: Rgb, shift: u32| ((color >> shift) & 0xFF) as i32;
+    let distance = |other: Rgb| {
+        let dr = component(color, 16) - component(other, 16);
+        let dg = component(color, 8) - component(other, 8);
+        let db = component(color, 0) - component(other, 0);
+        dr * dr + dg * dg + db * db
+    };
+    let index = (0..16u8).min_by_key(|index| distance(vga(*index))).unwrap_or(0);
+    Color::from_index(index)
+}
@@ -0,0 +1,255 @@
+// The lines of a console, the screen being the last screen height of them
+//
+// Lines live in a ring buffer on the heap, so appending a line is O(1) and the oldest line is
+// dropped once the buffer is full. Consoles print before the heap exists, so until then the
+// lines are kept in a text mode screen sized array and moved to the heap on the first new line
+// after it. Lines are as wide as the widest framebuffer console, the text mode shows the start.
+//
+// A line keeps the byte and attribute of its cells, 2 bytes each. The RGB colors of the
+// framebuffer console follow from the attribute for the 16 VGA colors, only a line with a 256 or
+// true color in it keeps the colors of every cell too.
+//
+// Every console can keep MAX_SCROLLBACK lines, which all of them together don't fit in the heap
+// with colored lines, so the buffers allocate without panicking: a ring stops growing and a line
+// falls back to the closest VGA colors when the heap is out of room.
+
+use alloc::alloc::{alloc, Layout};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use super::{ScreenChar, MAX_HEIGHT, MAX_SCROLLBACK, MAX_WIDTH, SCREEN_HEIGHT};
+use crate::allocator;
+
+/// the (foreground, background) RGB of every cell of a line
+type Colors = [([u8; 3], [u8; 3]); MAX_WIDTH as usize];
+
+#[derive(Clone)]
+pub struct Line {
+    glyphs: [u8; MAX_WIDTH as usize],
+    attrs: [u8; MAX_WIDTH as usize],
+    colors: Option<Box<Colors>>, // only once a cell has colors its attribute doesn't tell
+}
+
+impl Line {
+    const EMPTY: Line = Line {
+        glyphs: [0; MAX_WIDTH as usize],
+        attrs: [0; MAX_WIDTH as usize],
+        colors: None,
+    };
+
+    /// returns a line of the same cell
+    pub fn filled(cell: ScreenChar) -> Line {
+        let mut line = Line::EMPTY;
+        for col in 0..MAX_WIDTH as usize {
+            line.set(col, cell);
+        }
+        line
+    }
+
+    pub fn get(&self, col: usize) -> ScreenChar {
+        match &self.colors {
+            Some(colors) => ScreenChar {
+                ascii: self.glyphs[col],
+                attr: self.attrs[col],
+                fg: colors[col].0,
+                bg: colors[col].1,
+            },
+            None => ScreenChar::new(self.glyphs[col], self.attrs[col]),
+        }
+    }
+
+    pub fn set(&mut self, col: usize, cell: ScreenChar) {
+        self.glyphs[col] = cell.ascii;
+        self.attrs[col] = cell.attr;
+        if self.colors.is_none() && cell != ScreenChar::new(cell.ascii, cell.attr) {
+            // too early or no room for a 256 or true color, it's shown as its closest VGA color
+            let mut colors = match try_box_colors() {
+                Some(colors) => colors,
+                None => return,
+            };
+            for (other, color) in colors.iter_mut().enumerate() {
+                let other = ScreenChar::new(self.glyphs[other], self.attrs[other]);
+                *color = (other.fg, other.bg);
+            }
+            self.colors = Some(colors);
+        }
+        if let Some(colors) = &mut self.colors {
+            colors[col] = (cell.fg, cell.bg);
+        }
+    }
+
+    /// returns the byte of every cell
+    pub fn glyphs(&self) -> &[u8] {
+        &self.glyphs
+    }
+}
+
+/// Allocates the colors of a line, None before the heap is ready or if it has no room for them
+fn try_box_colors() -> Option<Box<Colors>> {
+    if !allocator::heap_ready() {
+        return None;
+    }
+    unsafe {
+        let colors = alloc(Layout::new::<Colors>()) as *mut Colors;
+        if colors.is_null() {
+            return None;
+        }
+        colors.write([([0; 3], [0; 3]); MAX_WIDTH as usize]);
+        Some(Box::from_raw(colors))
+    }
+}
+
+/// Allocates an empty vec with room for `capacity` lines, None if the heap has no room for it
+fn try_with_capacity(capacity: usize) -> Option<Vec<Line>> {
+    let layout = Layout::array::<Line>(capacity).ok()?;
+    if layout.size() == 0 {
+        return Some(Vec::new());
+    }
+    unsafe {
+        let lines = alloc(layout) as *mut Line;
+        if lines.is_null() {
+            return None;
+        }
+        Some(Vec::from_raw_parts(lines, 0, capacity))
+    }
+}
+
+/// the fewest lines the ring grows by, it otherwise doubles so appending stays O(1) on average
+const GROW_BY: usize = 64;
+
+pub struct Scrollback {
+    early: [Line; SCREEN_HEIGHT as usize], // the lines until the heap is ready
+    lines: Vec<Line>, // the ring, empty until the heap is ready
+    start: usize, // the index in `lines` of the oldest line
+    capacity: usize, // the most lines kept
+}
+
+impl Scrollback {
+    /// Creates a buffer with a screen of blank lines
+    pub fn new(capacity: usize, blank: ScreenChar) -> Scrollback {
+        Scrollback {
+            early: Scrollback::early_lines(blank),
+            lines: Vec::new(),
+            start: 0,
+            capacity: capacity.max(MAX_HEIGHT as usize).min(MAX_SCROLLBACK),
+        }
+    }
+
+    fn early_lines(blank: ScreenChar) -> [Line; SCREEN_HEIGHT as usize] {
+        let mut lines = [Line::EMPTY; SCREEN_HEIGHT as usize];
+        for line in lines.iter_mut() {
+            *line = Line::filled(blank);
+        }
+        lines
+    }
+
+    fn on_heap(&self) -> bool {
+        !self.lines.is_empty()
+    }
+
+    /// returns how many lines there are, never less than a text mode screen
+    pub fn len(&self) -> usize {
+        if self.on_heap() { self.lines.len() } else { SCREEN_HEIGHT as usize }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// returns line `index`, 0 being the oldest
+    pub fn line(&self, index: usize) -> &Line {
+        if self.on_heap() {
+            &self.lines[(self.start + index) % self.lines.len()]
+        } else {
+            &self.early[index]
+        }
+    }
+
+    pub fn line_mut(&mut self, index: usize) -> &mut Line {
+        if self.on_heap() {
+            let len = self.lines.len();
+            &mut self.lines[(self.start + index) % len]
+        } else {
+            &mut self.early[index]
+        }
+    }
+
+    /// Adds a blank line at the end
+    /// returns true if the oldest line was dropped to make room, which moves every line up one
+    pub fn push(&mut self, blank: ScreenChar) -> bool {
+        if !self.on_heap() {
+            let lines = match allocator::heap_ready() {
+                true => try_with_capacity(GROW_BY.min(self.capacity)),
+                false => None,
+            };
+            match lines {
+                Some(lines) => {
+                    self.lines = lines;
+                    self.lines.extend_from_slice(&self.early);
+                }
+                None => {
+                    self.early.rotate_left(1);
+                    self.early[SCREEN_HEIGHT as usize - 1] = Line::filled(blank);
+                    return true;
+                }
+            }
+        }
+
+        if self.lines.len() < self.capacity && self.grow() {
+            self.lines.push(Line::filled(blank));
+            false
+        } else {
+            // overwrite the oldest line, which becomes the newest
+            let start = self.start;
+            self.lines[start] = Line::filled(blank);
+            self.start = (start + 1) % self.lines.len();
+            true
+        }
+    }
+
+    /// Makes room for another line in the ring if it is full
+    /// returns false if the heap has no room for more lines, the ring then stays the size it is
+    fn grow(&mut self) -> bool {
+        if self.lines.len() < self.lines.capacity() {
+            return true;
+        }
+        let grow = self.lines.len().max(GROW_BY).min(self.capacity - self.lines.len());
+        match try_with_capacity(self.lines.len() + grow) {
+            Some(mut lines) => {
+                lines.extend(self.lines.drain(..));
+                self.lines = lines;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Goes back to a single screen of blank lines
+    pub fn clear(&mut self, blank: ScreenChar) {
+        self.early = Scrollback::early_lines(blank);
+        self.lines = Vec::new();
+        self.start = 0;
+    }
+
+    /// Changes how many lines are kept, between a screen and MAX_SCROLLBACK
+    /// returns how many of the oldest lines were dropped
+    pub fn set_capacity(&mut self, capacity: usize) -> usize {
+        let capacity = capacity.max(MAX_HEIGHT as usize).min(MAX_SCROLLBACK);
+        self.capacity = capacity;
+        if !self.on_heap() {
+            return 0;
+        }
+
+        // put the lines in order and drop the oldest, then move them into a ring of their size
+        // (if there's no room for it, the old one keeps its memory)
+        let dropped = self.lines.len().saturating_sub(capacity);
+        self.lines.rotate_left(self.start);
+        self.start = 0;
+        self.lines.drain(..dropped);
+        if let Some(mut lines) = try_with_capacity(self.lines.len()) {
+            lines.extend(self.lines.drain(..));
+            self.lines = lines;
+        }
+        dropped
+    }
+}
@@ -0,0 +1,91 @@
+// Snapshots of a console, to check what the kernel drew without someone watching the screen
+//
+// A snapshot holds the text and attributes of rows of a console's buffer: the ones on the screen,
+// or the whole scrollback. write_serial() sends it to the serial port as a block of lines, which
+// a test running QEMU headless can find in the output:
+//
+//   === SNAPSHOT BEGIN console 0 ===
+//   size 80 25            the columns and rows of the screen
+//   screen 975            the buffer row at the top of the screen
+//   cursor 4 977 shown    the column and buffer row of the cursor, shown or hidden
+//   rows 975 25           the buffer row of the first row in the snapshot, and how many there are
+//   T 975 frame> ls       the text of a row, without the spaces at the end
+//   A 975 0f0f0f0f...     the attribute byte of every column of the row, in hex
+//   ...
+//   === SNAPSHOT END console 0 ===
+//
+// Text is UTF-8, decoded from the code page. Attributes are the VGA ones (the background in the
+// high nibble), on the framebuffer console that is the closest VGA color of a 256 or true color.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+
+use crate::serial;
+
+/// Which rows of the buffer a snapshot takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Screen, // the rows on the screen, scrolled back or not
+    Scrollback, // every row in the buffer
+}
+
+/// A row of a console in a snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub text: String, // a character for every column, spaces at the end included
+    pub attrs: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub console: usize,
+    pub width: u8,
+    pub height: u8,
+    pub screen: usize, // the buffer row at the top of the screen
+    pub cursor: (u8, usize), // (column, buffer row)
+    pub cursor_visible: bool,
+    pub first: usize, // the buffer row of rows[0]
+    pub rows: Vec<Row>,
+}
+
+impl Snapshot {
+    /// returns the text of the rows, a line each without the spaces at the end
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in self.rows.iter() {
+            text.push_str(row.text.trim_end());
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Writes the snapshot as a block of lines (see the top of this file)
+    pub fn write_block(&self, out: &mut impl Write) -> fmt::Result {
+        writeln!(out, "=== SNAPSHOT BEGIN console {} ===", self.console)?;
+        writeln!(out, "size {} {}", self.width, self.height)?;
+        writeln!(out, "screen {}", self.screen)?;
+        writeln!(out, "cursor {} {} {}", self.cursor.0, self.cursor.1,
+                 if self.cursor_visible { "shown" } else { "hidden" })?;
+        writeln!(out, "rows {} {}", self.first, self.rows.len())?;
+        for (index, row) in self.rows.iter().enumerate() {
+            writeln!(out, "T {} {}", self.first + index, row.text.trim_end())?;
+            write!(out, "A {} ", self.first + index)?;
+            for attr in row.attrs.iter() {
+                write!(out, "{:02x}", attr)?;
+            }
+            writeln!(out)?;
+        }
+        writeln!(out, "=== SNAPSHOT END console {} ===", self.console)
+    }
+
+    /// Sends the snapshot to the serial port
+    ///
+    /// The port stays locked for the whole block, so no other output lands between its lines.
+    /// Interrupts stay on (a scrollback takes a while to send), handlers that report over serial
+    /// use serial::try_print and skip their output instead of waiting on the lock.
+    pub fn write_serial(&self) {
+        let mut port = serial::SERIAL1.lock();
+        self.write_block(&mut *port).ok();
+    }
+}
@@ -0,0 +1,139 @@
+// Soft-lockup watchdog
+//
+// The executor pets the watchdog every time it goes around its run loop. The timer interrupt
+// checks how long ago that was, so a task that never returns from poll() (or anything else that
+// stops the loop) is reported with the id of the task being polled and a backtrace of it.
+// Interrupt handlers are timed with the tsc, as the timer can't fire while they run.
+//
+// Reports are printed from interrupt handlers, which could have interrupted the code holding the
+// console or serial lock, so they skip whichever of the two is locked instead of waiting on it.
+
+use core::fmt;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::{backtrace, serial, time, vga_textmode};
+
+const NO_TASK: u64 = u64::max_value();
+
+/// What to do when a soft lockup is found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Action {
+    Log = 0,
+    Panic = 1,
+    Reboot = 2,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static ACTION: AtomicU8 = AtomicU8::new(Action::Log as u8);
+static THRESHOLD_MS: AtomicU64 = AtomicU64::new(5000);
+static IRQ_THRESHOLD_US: AtomicU64 = AtomicU64::new(10_000);
+
+static LAST_PET: AtomicU64 = AtomicU64::new(0); // the tick the executor last went around its loop
+static CURRENT_TASK: AtomicU64 = AtomicU64::new(NO_TASK); // the id of the task being polled
+static REPORTED: AtomicBool = AtomicBool::new(false); // true if the current lockup was reported
+
+pub fn set_enabled(enabled: bool) {
+    pet();
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn set_action(action: Action) {
+    ACTION.store(action as u8, Ordering::SeqCst);
+}
+
+/// sets how long the executor can go without completing a loop before it is reported
+pub fn set_threshold_ms(ms: u64) {
+    THRESHOLD_MS.store(ms, Ordering::SeqCst);
+}
+
+/// sets how long an interrupt handler can run before it is reported
+pub fn set_irq_threshold_us(us: u64) {
+    IRQ_THRESHOLD_US.store(us, Ordering::SeqCst);
+}
+
+/// Called by the executor every time it goes around its run loop
+pub fn pet() {
+    LAST_PET.store(time::ticks(), Ordering::Relaxed);
+    REPORTED.store(false, Ordering::Relaxed);
+}
+
+/// Called by the executor before polling a task
+pub fn poll_start(task_id: u64) {
+    CURRENT_TASK.store(task_id, Ordering::Relaxed);
+}
+
+/// Called by the executor after polling a task
+pub fn poll_end() {
+    CURRENT_TASK.store(NO_TASK, Ordering::Relaxed);
+    pet(); // a poll that returned is progress, even if the ready queue never empties
+}
+
+/// Prints a report to the screen and serial, skipping either one if it is locked
+fn report(screen: fmt::Arguments, serial: fmt::Arguments) {
+    vga_textmode::try_print(screen);
+    serial::try_print(serial);
+}
+
+/// Called by the timer interrupt handler
+/// Inlined so the backtrace starts at the interrupted code
+#[inline(always)]
+pub fn check(stack_frame: &InterruptStackFrame) {
+    if !ENABLED.load(Ordering::Relaxed) || REPORTED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let stalled_ticks = time::ticks().saturating_sub(LAST_PET.load(Ordering::Relaxed));
+    if stalled_ticks < time::ms_to_ticks(THRESHOLD_MS.load(Ordering::Relaxed)) {
+        return;
+    }
+    REPORTED.store(true, Ordering::Relaxed); // only report each lockup once
+
+    let stalled_ms = stalled_ticks * 1000 / time::TIMER_HZ;
+    match CURRENT_TASK.load(Ordering::Relaxed) {
+        NO_TASK => report(
+            format_args!("&4WATCHDOG: soft lockup, executor stuck for {}ms (not polling a task)\n", stalled_ms),
+            format_args!("WATCHDOG: soft lockup, executor stuck for {}ms (not polling a task)\n", stalled_ms)),
+        task => report(
+            format_args!("&4WATCHDOG: soft lockup, TaskId({}) has been polled for {}ms\n", task, stalled_ms),
+            format_args!("WATCHDOG: soft lockup, TaskId({}) has been polled for {}ms\n", task, stalled_ms)),
+    }
+    backtrace::try_print_exception_backtrace(stack_frame);
+
+    match ACTION.load(Ordering::Relaxed) {
+        1 => panic!("soft lockup detected"),
+        2 => crate::acpi::reboot(),
+        _ => {}
+    }
+}
+
+/// Times an interrupt handler, reporting it if it runs for too long
+/// Create one at the start of the handler, it checks the time when dropped
+pub struct InterruptTimer {
+    name: &'static str,
+    start: u64,
+}
+
+impl InterruptTimer {
+    pub fn new(name: &'static str) -> InterruptTimer {
+        InterruptTimer {
+            name,
+            start: time::rdtsc(),
+        }
+    }
+}
+
+impl Drop for InterruptTimer {
+    fn drop(&mut self) {
+        if !ENABLED.load(Ordering::Relaxed) {
+            return;
+        }
+        let us = time::tsc_to_us(time::rdtsc().wrapping_sub(self.start));
+        if us > IRQ_THRESHOLD_US.load(Ordering::Relaxed) {
+            report(format_args!("&eWATCHDOG: interrupt handler '{}' ran for {}us\n", self.name, us),
+                   format_args!("WATCHDOG: interrupt handler '{}' ran for {}us\n", self.name, us));
+        }
+    }
+}
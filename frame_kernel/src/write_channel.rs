@@ -1,10 +1,13 @@
 use alloc::string::String;
 
-use crate::println;
+use crate::{println, serial_println};
+use crate::vga_textmode::strip_markup;
 
 pub const stdout: ChannelSTDOUT = ChannelSTDOUT {};
 pub const stdin: ChannelSTDIN = ChannelSTDIN {};
 pub const stderr: ChannelSTDERR = ChannelSTDERR {};
+pub const klog: ChannelLOG = ChannelLOG {};
+pub const serial: ChannelSERIAL = ChannelSERIAL {};
 
 pub trait WriteChannel {
     fn write(&self, data: &str);
@@ -38,4 +41,23 @@ impl WriteChannel for ChannelSTDERR {
     fn write(&self, data: &str) {
         println!("ON STDERR: {}", data);
     }
-}
\ No newline at end of file
+}
+
+/// writes to the virtual console dedicated to the kernel log
+pub struct ChannelLOG {}
+
+impl WriteChannel for ChannelLOG {
+    fn write(&self, data: &str) {
+        crate::vga_textmode::print_to_console(crate::vga_textmode::LOG_CONSOLE,
+                                              format_args!("{}\n", data));
+    }
+}
+
+/// writes to the serial port, without the color markup the other end can't show
+pub struct ChannelSERIAL {}
+
+impl WriteChannel for ChannelSERIAL {
+    fn write(&self, data: &str) {
+        serial_println!("{}", strip_markup(data));
+    }
+}